@@ -0,0 +1,2 @@
+DROP INDEX subscriptions_canonical_email_idx;
+ALTER TABLE subscriptions DROP COLUMN canonical_email;
@@ -0,0 +1,29 @@
+-- Existing rows are backfilled with the lowercased address. The app finishes
+-- the job when it starts, with the canonical form the configured email policy
+-- gives, i.e. with punycode domains and possibly the provider rules. Rows whose
+-- canonical form collides with an older subscriber are counted and left with a
+-- NULL canonical_email so they can be resolved by hand:
+--     SELECT * FROM subscriptions WHERE canonical_email IS NULL;
+ALTER TABLE subscriptions ADD COLUMN canonical_email TEXT NULL;
+
+UPDATE subscriptions s
+    SET canonical_email = lower(s.email)
+    WHERE NOT EXISTS (
+        SELECT 1 FROM subscriptions older
+            WHERE lower(older.email) = lower(s.email)
+            AND (older.subscribed_at, older.id) < (s.subscribed_at, s.id)
+    );
+
+DO $$
+DECLARE
+    collisions bigint;
+BEGIN
+    SELECT count(*) INTO collisions FROM subscriptions WHERE canonical_email IS NULL;
+    IF collisions > 0 THEN
+        RAISE WARNING '% subscribers share a canonical email with an older subscriber',
+            collisions;
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX subscriptions_canonical_email_idx ON subscriptions (canonical_email);
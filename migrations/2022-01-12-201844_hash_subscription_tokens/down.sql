@@ -0,0 +1,4 @@
+-- Hashed tokens cannot be turned back into the links that were emailed.
+DELETE FROM subscription_tokens WHERE hashed;
+ALTER TABLE subscription_tokens DROP COLUMN hashed;
+ALTER TABLE subscription_tokens DROP COLUMN created_at;
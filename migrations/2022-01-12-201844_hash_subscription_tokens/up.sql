@@ -0,0 +1,7 @@
+-- Tokens are now stored as an HMAC of the value sent to the subscriber.
+-- Rows that predate this migration hold the plaintext token and are flagged
+-- with hashed = false; they keep working until they expire, counting from
+-- the time of this migration.
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE subscription_tokens ADD COLUMN hashed BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE subscription_tokens ALTER COLUMN hashed DROP DEFAULT;
@@ -0,0 +1,11 @@
+-- Addresses we must not send to anymore, e.g. after a permanent bounce or a
+-- spam complaint. They are matched on the same canonical form that subscribers
+-- are deduplicated on, and record where they came from.
+CREATE TABLE suppressions(
+    canonical_email TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    detail TEXT,
+    created_at timestamptz NOT NULL,
+    source TEXT NOT NULL,
+    PRIMARY KEY (canonical_email)
+);
@@ -0,0 +1,3 @@
+DROP TABLE tracking_events;
+DROP TABLE issue_links;
+DROP TABLE newsletter_issues;
@@ -0,0 +1,34 @@
+CREATE TABLE newsletter_issues(
+    id uuid NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    published_at timestamptz NOT NULL,
+    track_opens BOOLEAN NOT NULL,
+    track_clicks BOOLEAN NOT NULL,
+    PRIMARY KEY (id)
+);
+
+-- The distinct links of an issue, numbered in order of first appearance;
+-- click tokens refer to a link by its position.
+CREATE TABLE issue_links(
+    issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (id),
+    position INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (issue_id, position)
+);
+
+-- One row per pixel load or click; link_position is only set for clicks.
+CREATE TABLE tracking_events(
+    id uuid NOT NULL,
+    issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    kind TEXT NOT NULL,
+    link_position INTEGER,
+    occurred_at timestamptz NOT NULL,
+    PRIMARY KEY (id)
+);
+CREATE INDEX tracking_events_issue_id_idx ON tracking_events (issue_id);
@@ -0,0 +1,15 @@
+-- One row per recipient of an issue: queued when the issue is published,
+-- then sent, failed or suppressed, and bounced if SES later reports so.
+CREATE TABLE issue_deliveries(
+    issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    status TEXT NOT NULL,
+    error TEXT,
+    -- assigned by the email backend, and quoted in SES events
+    message_id TEXT,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY (issue_id, subscriber_id)
+);
+CREATE INDEX issue_deliveries_message_id_idx ON issue_deliveries (message_id);
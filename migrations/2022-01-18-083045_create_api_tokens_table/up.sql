@@ -0,0 +1,15 @@
+-- Bearer tokens for programs. Only a SHA-256 hash of each token is kept; the
+-- prefix is stored so that a leaked token can be matched to its row.
+CREATE TABLE api_tokens(
+    id uuid PRIMARY KEY,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    token_prefix TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_by uuid NOT NULL
+        REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    last_used_at timestamptz,
+    revoked_at timestamptz
+);
@@ -0,0 +1,14 @@
+-- Recent failed logins, per username and per client address, so that guessing
+-- passwords is slowed down and eventually locked out.
+CREATE TABLE login_failures(
+    kind TEXT NOT NULL CHECK (kind IN ('username', 'ip')),
+    subject TEXT NOT NULL,
+    failures INTEGER NOT NULL,
+    last_failure_at timestamptz NOT NULL,
+    next_attempt_at timestamptz NOT NULL,
+    locked_until timestamptz,
+    -- when an attempt for a username with failures started being checked, so
+    -- that another one is refused until the outcome of the first is counted
+    attempt_started_at timestamptz,
+    PRIMARY KEY (kind, subject)
+);
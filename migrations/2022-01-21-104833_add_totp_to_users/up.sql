@@ -0,0 +1,17 @@
+-- TOTP two-factor authentication. The secret is set, encrypted, when
+-- enrollment starts and only takes effect once a code has confirmed it, at
+-- `totp_enabled_at`.
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled_at timestamptz;
+-- The time step of the last TOTP code accepted, so that no code is accepted
+-- twice, even within the window it is valid for.
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- Single-use codes for when the authenticator is lost; only hashes are kept.
+CREATE TABLE recovery_codes(
+    user_id uuid NOT NULL
+        REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at timestamptz,
+    PRIMARY KEY (user_id, code_hash)
+);
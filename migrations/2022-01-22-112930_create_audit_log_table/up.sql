@@ -0,0 +1,30 @@
+-- Who did what to which record, for every privileged action. Rows are only
+-- ever added: the triggers below refuse to change or remove them.
+CREATE TABLE audit_log(
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at timestamptz NOT NULL,
+    actor_type TEXT NOT NULL CHECK (actor_type IN ('user', 'api_token', 'system')),
+    actor_id uuid,
+    actor_name TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target_type TEXT NOT NULL,
+    target_id TEXT,
+    before JSONB,
+    after JSONB,
+    request_id TEXT
+);
+CREATE INDEX audit_log_occurred_at_idx ON audit_log (occurred_at);
+CREATE INDEX audit_log_target_idx ON audit_log (target_type, target_id);
+
+CREATE FUNCTION refuse_audit_log_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE PROCEDURE refuse_audit_log_changes();
+CREATE TRIGGER audit_log_no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE PROCEDURE refuse_audit_log_changes();
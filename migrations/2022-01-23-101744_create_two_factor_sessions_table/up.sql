@@ -0,0 +1,10 @@
+-- Sessions started with a one-time code, so that a user with two-factor
+-- authentication can make more than one request per time step. Only hashes of
+-- the tokens are kept.
+CREATE TABLE two_factor_sessions(
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL
+        REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE suppressions DROP COLUMN id;
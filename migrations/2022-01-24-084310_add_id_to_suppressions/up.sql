@@ -0,0 +1,4 @@
+-- An identifier for the audit log to refer to a suppression by, so that it
+-- never has to record the address itself.
+ALTER TABLE suppressions ADD COLUMN id uuid NOT NULL UNIQUE DEFAULT gen_random_uuid();
+ALTER TABLE suppressions ALTER COLUMN id DROP DEFAULT;
@@ -0,0 +1,159 @@
+use crate::guards::{AuthenticatedUser, Caller, RequestId};
+use crate::models::NewAuditLogEntry;
+use crate::telemetry::Pii;
+use chrono::Utc;
+use diesel::{PgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+/// Who a privileged action is attributed to.
+#[derive(Clone, Debug)]
+pub enum Actor {
+    User {
+        id: Uuid,
+        name: String,
+    },
+    ApiToken {
+        id: Uuid,
+        name: String,
+    },
+    /// The server itself, e.g. when it locks out a username.
+    System,
+}
+
+impl Actor {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Actor::User { .. } => "user",
+            Actor::ApiToken { .. } => "api_token",
+            Actor::System => "system",
+        }
+    }
+
+    fn id(&self) -> Option<&Uuid> {
+        match self {
+            Actor::User { id, .. } | Actor::ApiToken { id, .. } => Some(id),
+            Actor::System => None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Actor::User { name, .. } | Actor::ApiToken { name, .. } => name,
+            Actor::System => "system",
+        }
+    }
+}
+
+impl From<&AuthenticatedUser> for Actor {
+    fn from(user: &AuthenticatedUser) -> Self {
+        Actor::User {
+            id: user.user_id,
+            name: user.username.clone(),
+        }
+    }
+}
+
+impl From<&Caller> for Actor {
+    fn from(caller: &Caller) -> Self {
+        match caller {
+            Caller::User(user) => user.into(),
+            Caller::Client(client) => Actor::ApiToken {
+                id: client.token_id,
+                name: client.name.clone(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Actor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Actor::User { id, name } => write!(f, "user {} ({})", name, id),
+            Actor::ApiToken { id, name } => write!(f, "token {} ({})", name, id),
+            Actor::System => f.write_str("system"),
+        }
+    }
+}
+
+/// An entry for the `audit_log` table. Privileged actions record one in the
+/// same transaction as the change itself, so that neither happens without the
+/// other.
+pub struct AuditEntry {
+    actor: Actor,
+    action: &'static str,
+    target_type: &'static str,
+    target_id: Option<String>,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+    request_id: RequestId,
+}
+
+impl AuditEntry {
+    /// `action` reads like `suppression.added`: what was acted on, then what
+    /// happened to it.
+    pub fn new(actor: Actor, action: &'static str, request_id: &RequestId) -> Self {
+        Self {
+            actor,
+            action,
+            target_type: action.split('.').next().unwrap_or(action),
+            target_id: None,
+            before: None,
+            after: None,
+            request_id: request_id.clone(),
+        }
+    }
+
+    /// Names the record that was acted on, of the type at the start of the
+    /// action unless `with_target_type` says otherwise.
+    pub fn with_target(mut self, target_id: impl ToString) -> Self {
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    pub fn with_target_type(mut self, target_type: &'static str) -> Self {
+        self.target_type = target_type;
+        self
+    }
+
+    /// The state of the target before the action.
+    pub fn with_before(mut self, before: impl serde::Serialize) -> Self {
+        self.before = serde_json::to_value(before).ok();
+        self
+    }
+
+    /// The state of the target after the action.
+    pub fn with_after(mut self, after: impl serde::Serialize) -> Self {
+        self.after = serde_json::to_value(after).ok();
+        self
+    }
+
+    /// Stores the entry. It is logged first, so that it is not lost
+    /// altogether if storing it fails.
+    pub fn record(&self, conn: &PgConnection) -> Result<(), diesel::result::Error> {
+        use crate::schema::audit_log;
+        tracing::info!(
+            target: "audit",
+            action = self.action,
+            actor = %self.actor,
+            target_type = self.target_type,
+            target_id = ?self.target_id.as_ref().map(Pii),
+            request_id = %self.request_id,
+            "Recorded a privileged action."
+        );
+        diesel::insert_into(audit_log::table)
+            .values(NewAuditLogEntry {
+                occurred_at: &Utc::now(),
+                actor_type: self.actor.kind(),
+                actor_id: self.actor.id(),
+                actor_name: self.actor.name(),
+                action: self.action,
+                target_type: self.target_type,
+                target_id: self.target_id.as_deref(),
+                before: self.before.as_ref(),
+                after: self.after.as_ref(),
+                request_id: Some(&self.request_id.0),
+            })
+            .execute(conn)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::domain::{EmailPolicy, SubscriberEmail};
+use crate::startup::NewsletterDbPool;
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// What bringing the subscribers in line with the email policy changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Recanonicalized {
+    pub updated: usize,
+    /// Subscribers whose canonical form is already taken by an older one, and
+    /// who are left with a NULL `canonical_email` to be resolved by hand.
+    pub collisions: usize,
+}
+
+/// Gives every subscriber the canonical email `email_policy` gives it now.
+/// The migration that added the column could only lowercase addresses, and
+/// turning the provider rules on changes the canonical form of existing rows.
+///
+/// Addresses that no longer parse keep what they have.
+pub fn recanonicalize(
+    conn: &PgConnection,
+    email_policy: &EmailPolicy,
+) -> Result<Recanonicalized, diesel::result::Error> {
+    use crate::schema::subscriptions;
+    conn.transaction(|| {
+        // keeps subscribers from signing up with a form that is being moved
+        diesel::sql_query("LOCK TABLE subscriptions IN EXCLUSIVE MODE").execute(conn)?;
+        let rows = subscriptions::table
+            .select((
+                subscriptions::id,
+                subscriptions::email,
+                subscriptions::canonical_email,
+            ))
+            .order((subscriptions::subscribed_at, subscriptions::id))
+            .load::<(Uuid, String, Option<String>)>(conn)?;
+
+        let mut rows: Vec<_> = rows
+            .into_iter()
+            .map(|(id, email, current)| {
+                let canonical = SubscriberEmail::parse(email)
+                    .ok()
+                    .map(|email| email_policy.canonicalize(&email));
+                (id, canonical, current)
+            })
+            .collect();
+        let mut taken: HashSet<String> = rows
+            .iter()
+            .filter(|(_, canonical, _)| canonical.is_none())
+            .filter_map(|(_, _, current)| current.clone())
+            .collect();
+        let mut collisions = 0;
+        for (_, canonical, current) in &mut rows {
+            match canonical.take() {
+                // the oldest subscriber keeps the canonical form
+                Some(wanted) if taken.insert(wanted.clone()) => *canonical = Some(wanted),
+                Some(_) => collisions += 1,
+                None => *canonical = current.clone(),
+            }
+        }
+
+        let changed: Vec<_> = rows
+            .into_iter()
+            .filter(|(_, canonical, current)| canonical != current)
+            .collect();
+        // cleared first, so that forms moving between rows never collide
+        let ids: Vec<Uuid> = changed.iter().map(|(id, _, _)| *id).collect();
+        diesel::update(subscriptions::table.filter(subscriptions::id.eq_any(&ids)))
+            .set(subscriptions::canonical_email.eq(None::<String>))
+            .execute(conn)?;
+        for (id, canonical, _) in &changed {
+            if canonical.is_some() {
+                diesel::update(subscriptions::table.find(id))
+                    .set(subscriptions::canonical_email.eq(canonical))
+                    .execute(conn)?;
+            }
+        }
+
+        Ok(Recanonicalized {
+            updated: changed.len(),
+            collisions,
+        })
+    })
+}
+
+/// Runs `recanonicalize` when the app starts. The app has to start while the
+/// database is down or its migrations are pending, so failures are only
+/// logged; the next start tries again.
+pub async fn recanonicalize_on_startup(pool: &NewsletterDbPool, email_policy: &EmailPolicy) {
+    let email_policy = email_policy.clone();
+    let recanonicalized = match pool.get().await {
+        Ok(conn) => conn
+            .run(move |c: &mut PgConnection| recanonicalize(c, &email_policy))
+            .await
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    match recanonicalized {
+        Ok(Recanonicalized {
+            updated: 0,
+            collisions: 0,
+        }) => {}
+        Ok(Recanonicalized {
+            updated,
+            collisions,
+        }) => tracing::warn!(
+            updated,
+            collisions,
+            "Brought the canonical emails of subscribers in line with the email policy."
+        ),
+        Err(e) => tracing::error!(
+            error.cause_chain = ?e,
+            "Failed to update the canonical emails of subscribers."
+        ),
+    }
+}
@@ -0,0 +1,8 @@
+use crate::problem::Problem;
+use rocket::http::Status;
+use rocket::Request;
+
+#[catch(default)]
+pub fn problem_details(status: Status, req: &Request) -> Problem {
+    Problem::stashed(req, status).unwrap_or_else(|| Problem::from_status(status))
+}
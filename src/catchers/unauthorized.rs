@@ -1,9 +1,14 @@
-use rocket::http::Header;
+use crate::problem::Problem;
+use rocket::http::{Header, Status};
 use rocket::response::Responder;
+use rocket::Request;
 
 #[catch(401)]
-pub fn unauthorized_request_credentials() -> RequestBasicAuth {
-    RequestBasicAuth::new()
+pub fn unauthorized_request_credentials(req: &Request) -> RequestBasicAuth {
+    RequestBasicAuth::new(
+        Problem::stashed(req, Status::Unauthorized)
+            .unwrap_or_else(|| Problem::from_status(Status::Unauthorized)),
+    )
 }
 
 struct RequestBasicAuthHeader;
@@ -20,21 +25,15 @@ impl<'h> From<RequestBasicAuthHeader> for Header<'h> {
 #[derive(Responder)]
 #[response(status = 401)]
 pub struct RequestBasicAuth {
-    inner: (),
+    inner: Problem,
     basic_auth: RequestBasicAuthHeader,
 }
 
 impl RequestBasicAuth {
-    fn new() -> RequestBasicAuth {
+    fn new(problem: Problem) -> RequestBasicAuth {
         RequestBasicAuth {
-            inner: (),
+            inner: problem,
             basic_auth: RequestBasicAuthHeader,
         }
     }
 }
-
-impl Default for RequestBasicAuth {
-    fn default() -> Self {
-        Self::new()
-    }
-}
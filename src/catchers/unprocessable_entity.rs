@@ -1,8 +1,11 @@
-use rocket::response::status;
-use rocket::response::status::BadRequest;
+use crate::problem::Problem;
+use rocket::http::Status;
 use rocket::Request;
 
 #[catch(422)]
-pub fn unprocessable_entity_to_bad_request(_req: &Request) -> BadRequest<()> {
-    status::BadRequest::<()>(None)
+pub fn unprocessable_entity_to_bad_request(req: &Request) -> Problem {
+    Problem::stashed(req, Status::BadRequest).unwrap_or_else(|| {
+        Problem::new(Status::BadRequest, "malformed_body")
+            .with_detail("The request body could not be parsed.")
+    })
 }
@@ -1,19 +1,44 @@
 use crate::domain::SubscriberEmail;
+use config::{Config, ConfigError};
+use secrecy::{ExposeSecret, Secret};
 use serde;
+use serde::de::DeserializeOwned;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use serde_aux::field_attributes::deserialize_option_number_from_string;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
+/// How the secrets in `configuration/base.yaml` start, so that they are
+/// refused in production.
+const DEVELOPMENT_SECRET_PREFIX: &str = "local-development-only-";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Environment {
     Local,
     Production,
 }
 
-#[derive(serde::Deserialize)]
+/// Loaded by `get_configuration`, one section at a time, so that problems in
+/// several sections are reported together.
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub subscription_tokens: SubscriptionTokenSettings,
+    pub email_policy: EmailPolicySettings,
+    pub cors: CorsSettings,
+    pub branding: BrandingSettings,
+    pub metrics: MetricsSettings,
+    pub health: HealthSettings,
+    pub telemetry: TelemetrySettings,
+    pub shutdown: ShutdownSettings,
+    pub ses_webhook: SesWebhookSettings,
+    pub tracking: TrackingSettings,
+    pub login_throttle: LoginThrottleSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub two_factor: TwoFactorSettings,
 }
 
 #[derive(serde::Deserialize)]
@@ -27,7 +52,7 @@ pub struct ApplicationSettings {
 #[derive(serde::Deserialize)]
 pub struct DatabaseSettings {
     pub username: String,
-    pub password: String,
+    pub password: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
@@ -41,6 +66,288 @@ pub struct EmailClientSettings {
     pub timeout_milliseconds: u64,
 }
 
+#[derive(serde::Deserialize)]
+pub struct SubscriptionTokenSettings {
+    /// Key for the HMAC the tokens are stored under.
+    pub hmac_secret: Secret<String>,
+    /// Entropy is `length` times 5.95 bits for alphanumeric tokens, 4 bits for hex.
+    #[serde(default = "default_token_length")]
+    pub length: usize,
+    #[serde(default = "default_token_alphabet")]
+    pub alphabet: TokenAlphabet,
+    #[serde(default = "default_token_expiry_hours")]
+    pub expiry_hours: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorSettings {
+    /// Key the TOTP secrets are encrypted under in the database. Changing it
+    /// makes every enrolled authenticator unusable.
+    pub encryption_key: Secret<String>,
+    /// How long a session started with a one-time code lasts.
+    #[serde(default = "default_session_minutes")]
+    pub session_minutes: i64,
+}
+
+fn default_session_minutes() -> i64 {
+    15
+}
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenAlphabet {
+    Alphanumeric,
+    Hex,
+}
+
+fn default_token_alphabet() -> TokenAlphabet {
+    TokenAlphabet::Alphanumeric
+}
+
+fn default_token_length() -> usize {
+    25
+}
+
+fn default_token_expiry_hours() -> i64 {
+    72
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct EmailPolicySettings {
+    #[serde(default)]
+    pub reject_role_accounts: bool,
+    /// When non-empty, only addresses on these domains (or their subdomains) are accepted.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub denied_domains: Vec<String>,
+    /// Extra disposable domains, one per line, on top of the bundled list.
+    pub disposable_domains_path: Option<String>,
+    /// Treat provider aliases (Gmail dots, plus tags) as the same subscriber.
+    #[serde(default)]
+    pub apply_provider_rules: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct CorsSettings {
+    /// Exact origins (scheme, host and port), or "*" for any origin.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight response.
+    pub max_age_seconds: u32,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".into(), "POST".into()],
+            allowed_headers: vec!["Content-Type".into()],
+            max_age_seconds: 3600,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct BrandingSettings {
+    pub site_name: String,
+    pub primary_color: String,
+    pub logo_url: Option<String>,
+    /// Directory with the Tera templates for the hosted pages.
+    pub templates_dir: String,
+}
+
+impl Default for BrandingSettings {
+    fn default() -> Self {
+        Self {
+            site_name: "Our newsletter".into(),
+            primary_color: "#2b6cb0".into(),
+            logo_url: None,
+            templates_dir: "templates".into(),
+        }
+    }
+}
+
+/// Without an allowed address or a token nobody can scrape `/metrics`.
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct MetricsSettings {
+    pub bearer_token: Option<Secret<String>>,
+    /// Clients connecting from these addresses may scrape without a token.
+    pub allowed_addresses: Vec<IpAddr>,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct HealthSettings {
+    /// Include the email backend in the readiness check. Off by default, as
+    /// every probe then costs a call to the provider.
+    pub check_email: bool,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct SesWebhookSettings {
+    /// ARNs of the SNS topics that SES publishes its events to. Messages from
+    /// any other topic are rejected, so the webhook is inert until this is set.
+    pub topic_arns: Vec<String>,
+    /// Read SNS signing certificates from this directory, by the file name in
+    /// their URL, instead of downloading them from AWS.
+    pub certificates_dir: Option<PathBuf>,
+}
+
+/// Whether newsletter issues track their readers. Both are off by default, for
+/// privacy; a single issue can still opt in or out when it is published.
+#[derive(serde::Deserialize)]
+pub struct TrackingSettings {
+    /// Key the tokens in tracking URLs are signed with. Changing it breaks the
+    /// links in issues already sent.
+    pub secret: Secret<String>,
+    /// Add a per-recipient pixel that records when an issue is opened.
+    #[serde(default)]
+    pub opens: bool,
+    /// Send the links in an issue's HTML through a redirect that records clicks.
+    #[serde(default)]
+    pub clicks: bool,
+}
+
+/// Argon2id parameters for new password hashes, and how much of the machine
+/// computing hashes may take up.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct PasswordHashingSettings {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// Threads dedicated to Argon2; every verification ties one up.
+    pub workers: usize,
+    /// How long a login may wait for a free worker before getting a 503.
+    pub queue_timeout_milliseconds: u64,
+}
+
+impl Default for PasswordHashingSettings {
+    fn default() -> Self {
+        Self {
+            memory_kib: 15000,
+            iterations: 2,
+            parallelism: 1,
+            workers: 4,
+            queue_timeout_milliseconds: 5000,
+        }
+    }
+}
+
+/// How failed admin logins are slowed down and locked out. Failures are
+/// counted per username and per client address, and forgotten once none has
+/// happened for `window_seconds`.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct LoginThrottleSettings {
+    pub max_failures_per_username: u32,
+    /// Higher than per username, as many people can share an address.
+    pub max_failures_per_ip: u32,
+    pub window_seconds: u64,
+    pub lockout_seconds: u64,
+    /// How long a username has to wait after its first failure; the wait
+    /// doubles with every further failure.
+    pub initial_delay_milliseconds: u64,
+    pub max_delay_seconds: u64,
+}
+
+impl Default for LoginThrottleSettings {
+    fn default() -> Self {
+        Self {
+            max_failures_per_username: 5,
+            max_failures_per_ip: 50,
+            window_seconds: 15 * 60,
+            lockout_seconds: 15 * 60,
+            initial_delay_milliseconds: 1000,
+            max_delay_seconds: 60,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    /// How long in-flight requests and background work get to finish once a
+    /// shutdown is requested, e.g. by SIGTERM.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub grace_period_seconds: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self {
+            grace_period_seconds: 30,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    /// Spans are exported to an OpenTelemetry collector only when this is set.
+    pub otlp: Option<OtlpSettings>,
+    /// How personal data is written to logs and traces.
+    pub redaction: RedactionMode,
+    /// Key of the HMAC that the hash mode records instead of personal data, so
+    /// that digests cannot be reversed by hashing guesses. Required in that mode.
+    pub redaction_key: Option<Secret<String>>,
+    /// Span and event fields that hold personal data. They are redacted by the
+    /// subscriber, whatever the code recording them does.
+    pub pii_fields: Vec<String>,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            otlp: None,
+            redaction: RedactionMode::Mask,
+            redaction_key: None,
+            pii_fields: ["subscriber_email", "subscriber_name", "recipient"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionMode {
+    /// Record personal data as is.
+    Off,
+    /// Replace it with a short keyed digest, so records about the same person
+    /// can still be correlated.
+    Hash,
+    /// Keep just enough to tell values apart by eye, e.g. `u***@gmail.com`.
+    Mask,
+}
+
+#[derive(serde::Deserialize)]
+pub struct OtlpSettings {
+    /// Full URL of the collector's OTLP/HTTP traces endpoint, e.g.
+    /// `http://localhost:4318/v1/traces`.
+    pub endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Fraction of new traces to record; traces started upstream follow the
+    /// caller's sampling decision.
+    #[serde(default = "default_sampling_ratio")]
+    pub sampling_ratio: f64,
+}
+
+fn default_service_name() -> String {
+    "zero2prod".into()
+}
+
+fn default_sampling_ratio() -> f64 {
+    1.0
+}
+
 impl EmailClientSettings {
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
@@ -72,27 +379,28 @@ impl TryFrom<String> for Environment {
 }
 
 impl DatabaseSettings {
-    pub fn connection_string(&self) -> String {
-        format!(
+    /// Secret, as it contains the password.
+    pub fn connection_string(&self) -> Secret<String> {
+        Secret::new(format!(
             "postgres://{}:{}@{}:{}/{}?sslmode={}",
             self.username,
-            self.password,
+            self.password.expose_secret(),
             self.host,
             self.port,
             self.database_name,
             ssl_mode(self.require_ssl)
-        )
+        ))
     }
 
-    pub fn connection_string_without_database(&self) -> String {
-        format!(
+    pub fn connection_string_without_database(&self) -> Secret<String> {
+        Secret::new(format!(
             "postgres://{}:{}@{}:{}?sslmode={}",
             self.username,
-            self.password,
+            self.password.expose_secret(),
             self.host,
             self.port,
             ssl_mode(self.require_ssl)
-        )
+        ))
     }
 }
 
@@ -103,19 +411,554 @@ fn ssl_mode(require_ssl: bool) -> &'static str {
     }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
-    let configuration_directory = base_path.join("configuration");
-    let environment: Environment = std::env::var("APP_ENVIRONMENT")
+/// Reads `configuration/base.yaml`, then the file for `APP_ENVIRONMENT`, then
+/// `APP_`-prefixed environment variables and files named by `APP_*_FILE`
+/// variables, and checks the result.
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    let mut problems = Problems::default();
+    let environment: Option<Environment> = match std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT");
-
-    let mut settings = config::Config::default();
-    settings.merge(config::File::from(configuration_directory.join("base")).required(true))?;
-    settings.merge(
-        config::File::from(configuration_directory.join(environment.as_str())).required(true),
-    )?;
-    settings.merge(config::Environment::with_prefix("app").separator("__"))?;
-    settings.try_into()
+    {
+        Ok(environment) => Some(environment),
+        Err(e) => {
+            problems.add("APP_ENVIRONMENT", e);
+            None
+        }
+    };
+    let configuration_directory = match std::env::current_dir() {
+        Ok(base_path) => base_path.join("configuration"),
+        Err(e) => {
+            problems.add(
+                "configuration",
+                format!("Failed to find the directory: {}", e),
+            );
+            return Err(problems.into_error());
+        }
+    };
+
+    let mut files = vec![configuration_directory.join("base")];
+    files.extend(environment.map(|e| configuration_directory.join(e.as_str())));
+    let mut config = Config::default();
+    for file in files {
+        if let Err(e) = config.merge(config::File::from(file.clone()).required(true)) {
+            problems.add(file.display().to_string(), e);
+        }
+    }
+    if let Err(e) = config.merge(config::Environment::with_prefix("app").separator("__")) {
+        problems.add("APP_*", e);
+    }
+    load_secret_files(&mut config, std::env::vars().collect(), &mut problems);
+    load(&config, environment, problems)
+}
+
+/// What is wrong with one setting.
+#[derive(Debug)]
+pub struct ConfigurationProblem {
+    /// The setting, e.g. `database.port`, or the file or environment variable
+    /// that could not be read.
+    pub key: String,
+    pub message: String,
+}
+
+/// Everything that is wrong with the configuration, so that it can be fixed in
+/// one go. Messages leave out the values of settings, which may be secrets.
+#[derive(Debug)]
+pub struct ConfigurationError {
+    pub problems: Vec<ConfigurationProblem>,
+}
+
+impl Display for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The configuration is invalid:")?;
+        for problem in &self.problems {
+            write!(f, "\n  {}: {}", problem.key, problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigurationError {}
+
+#[derive(Default)]
+struct Problems(Vec<ConfigurationProblem>);
+
+impl Problems {
+    fn add(&mut self, key: impl Into<String>, message: impl Display) {
+        self.0.push(ConfigurationProblem {
+            key: key.into(),
+            message: redact_values(&message.to_string()),
+        });
+    }
+
+    fn into_error(self) -> ConfigurationError {
+        ConfigurationError { problems: self.0 }
+    }
+}
+
+/// Serde quotes the offending value in its messages, e.g. `invalid type:
+/// string "hunter2", expected u16`; that might be a password in the wrong place.
+fn redact_values(message: &str) -> String {
+    let mut redacted = String::with_capacity(message.len());
+    let mut parts = message.split('"');
+    redacted.push_str(parts.next().unwrap_or_default());
+    let mut quoted = true;
+    for part in parts {
+        redacted.push('"');
+        redacted.push_str(if quoted { "[redacted]" } else { part });
+        quoted = !quoted;
+    }
+    redacted
+}
+
+/// `APP_DATABASE__PASSWORD_FILE=/run/secrets/db-password` sets
+/// `database.password` to the contents of that file, which is how Docker and
+/// Kubernetes hand out secrets.
+fn load_secret_files(config: &mut Config, vars: HashMap<String, String>, problems: &mut Problems) {
+    for (name, path) in &vars {
+        let key = match secret_file_key(name) {
+            Some(key) => key,
+            None => continue,
+        };
+        let direct = name.trim_end_matches("_FILE");
+        if vars.contains_key(direct) {
+            problems.add(
+                name.as_str(),
+                format!("Set either {} or {}, not both.", direct, name),
+            );
+            continue;
+        }
+        let contents = match std::fs::read_to_string(path) {
+            // editors and `echo` leave a newline at the end
+            Ok(contents) => contents.trim_end_matches(&['\r', '\n'][..]).to_string(),
+            Err(e) => {
+                problems.add(
+                    name.as_str(),
+                    format!("Failed to read {}: {}", Path::new(path).display(), e),
+                );
+                continue;
+            }
+        };
+        if let Err(e) = config.set(&key, contents) {
+            problems.add(name.as_str(), e);
+        }
+    }
+}
+
+fn secret_file_key(name: &str) -> Option<String> {
+    let key = name.strip_prefix("APP_")?.strip_suffix("_FILE")?;
+    if key.is_empty() {
+        return None;
+    }
+    Some(key.to_lowercase().replace("__", "."))
+}
+
+/// Deserializes each section on its own, so that a problem in one does not
+/// hide those in the others, then checks the values.
+fn load(
+    config: &Config,
+    environment: Option<Environment>,
+    mut problems: Problems,
+) -> Result<Settings, ConfigurationError> {
+    let p = &mut problems;
+    let database = required(config, "database", p);
+    let application = required(config, "application", p);
+    let email_client = required(config, "email_client", p);
+    let subscription_tokens = required(config, "subscription_tokens", p);
+    let email_policy = optional(config, "email_policy", p);
+    let cors = optional(config, "cors", p);
+    let branding = optional(config, "branding", p);
+    let metrics = optional(config, "metrics", p);
+    let health = optional(config, "health", p);
+    let telemetry = optional(config, "telemetry", p);
+    let shutdown = optional(config, "shutdown", p);
+    let ses_webhook = optional(config, "ses_webhook", p);
+    let tracking = required(config, "tracking", p);
+    let login_throttle = optional(config, "login_throttle", p);
+    let password_hashing = optional(config, "password_hashing", p);
+    let two_factor = required(config, "two_factor", p);
+    let settings = (|| {
+        Some(Settings {
+            database: database?,
+            application: application?,
+            email_client: email_client?,
+            subscription_tokens: subscription_tokens?,
+            email_policy: email_policy?,
+            cors: cors?,
+            branding: branding?,
+            metrics: metrics?,
+            health: health?,
+            telemetry: telemetry?,
+            shutdown: shutdown?,
+            ses_webhook: ses_webhook?,
+            tracking: tracking?,
+            login_throttle: login_throttle?,
+            password_hashing: password_hashing?,
+            two_factor: two_factor?,
+        })
+    })();
+
+    match settings {
+        Some(settings) if problems.0.is_empty() => {
+            validate(&settings, environment, &mut problems);
+            if problems.0.is_empty() {
+                Ok(settings)
+            } else {
+                Err(problems.into_error())
+            }
+        }
+        _ => Err(problems.into_error()),
+    }
+}
+
+fn required<T: DeserializeOwned>(config: &Config, key: &str, problems: &mut Problems) -> Option<T> {
+    match config.get(key) {
+        Ok(section) => Some(section),
+        Err(ConfigError::NotFound(_)) => {
+            problems.add(key, "Missing.");
+            None
+        }
+        Err(e) => {
+            problems.add(key, e);
+            None
+        }
+    }
+}
+
+fn optional<T: DeserializeOwned + Default>(
+    config: &Config,
+    key: &str,
+    problems: &mut Problems,
+) -> Option<T> {
+    match config.get(key) {
+        Ok(section) => Some(section),
+        Err(ConfigError::NotFound(_)) => Some(T::default()),
+        Err(e) => {
+            problems.add(key, e);
+            None
+        }
+    }
+}
+
+/// Checks what deserializing cannot, e.g. that URLs parse and files exist.
+fn validate(settings: &Settings, environment: Option<Environment>, problems: &mut Problems) {
+    let is_web_url = |url: &str| match reqwest::Url::parse(url) {
+        Ok(url) => matches!(url.scheme(), "http" | "https"),
+        Err(_) => false,
+    };
+
+    if !is_web_url(&settings.application.base_url) {
+        problems.add("application.base_url", "Must be an http or https URL.");
+    }
+    if settings.database.database_name.is_empty() {
+        problems.add("database.database_name", "Must not be empty.");
+    }
+    if settings.email_client.sender().is_err() {
+        problems.add(
+            "email_client.sender_email",
+            "Must be a valid email address.",
+        );
+    }
+    if settings.email_client.timeout_milliseconds == 0 {
+        problems.add("email_client.timeout_milliseconds", "Must be positive.");
+    }
+
+    let tokens = &settings.subscription_tokens;
+    if tokens.hmac_secret.expose_secret().is_empty() {
+        problems.add("subscription_tokens.hmac_secret", "Must not be empty.");
+    }
+    if tokens.length < 16 {
+        problems.add("subscription_tokens.length", "Must be at least 16.");
+    }
+    if tokens.expiry_hours < 1 {
+        problems.add("subscription_tokens.expiry_hours", "Must be at least 1.");
+    }
+
+    if let Some(path) = &settings.email_policy.disposable_domains_path {
+        if std::fs::File::open(path).is_err() {
+            problems.add(
+                "email_policy.disposable_domains_path",
+                "Must be a readable file.",
+            );
+        }
+    }
+    if settings
+        .cors
+        .allowed_origins
+        .iter()
+        .any(|origin| origin != "*" && !is_web_url(origin))
+    {
+        problems.add(
+            "cors.allowed_origins",
+            "Must each be an http or https origin, or \"*\".",
+        );
+    }
+    if !Path::new(&settings.branding.templates_dir).is_dir() {
+        problems.add("branding.templates_dir", "Must be a directory.");
+    }
+    if matches!(&settings.branding.logo_url, Some(url) if !is_web_url(url)) {
+        problems.add("branding.logo_url", "Must be an http or https URL.");
+    }
+    if matches!(&settings.metrics.bearer_token, Some(token) if token.expose_secret().is_empty()) {
+        problems.add("metrics.bearer_token", "Must not be empty when set.");
+    }
+    if matches!(&settings.ses_webhook.certificates_dir, Some(dir) if !dir.is_dir()) {
+        problems.add("ses_webhook.certificates_dir", "Must be a directory.");
+    }
+    if settings.telemetry.redaction == RedactionMode::Hash
+        && !matches!(&settings.telemetry.redaction_key, Some(key) if !key.expose_secret().is_empty())
+    {
+        problems.add(
+            "telemetry.redaction_key",
+            "Must be set when telemetry.redaction is hash.",
+        );
+    }
+    if let Some(otlp) = &settings.telemetry.otlp {
+        if !is_web_url(&otlp.endpoint) {
+            problems.add("telemetry.otlp.endpoint", "Must be an http or https URL.");
+        }
+        if !(0.0..=1.0).contains(&otlp.sampling_ratio) {
+            problems.add("telemetry.otlp.sampling_ratio", "Must be between 0 and 1.");
+        }
+    }
+
+    let throttle = &settings.login_throttle;
+    for (key, value) in [
+        (
+            "login_throttle.max_failures_per_username",
+            u64::from(throttle.max_failures_per_username),
+        ),
+        (
+            "login_throttle.max_failures_per_ip",
+            u64::from(throttle.max_failures_per_ip),
+        ),
+        ("login_throttle.window_seconds", throttle.window_seconds),
+        ("login_throttle.lockout_seconds", throttle.lockout_seconds),
+    ] {
+        if value == 0 {
+            problems.add(key, "Must be positive.");
+        }
+    }
+
+    let hashing = &settings.password_hashing;
+    if let Err(e) = argon2::Params::new(
+        hashing.memory_kib,
+        hashing.iterations,
+        hashing.parallelism,
+        None,
+    ) {
+        problems.add(
+            "password_hashing",
+            format!("Invalid Argon2 parameters: {}.", e),
+        );
+    }
+    if hashing.workers == 0 {
+        problems.add("password_hashing.workers", "Must be at least 1.");
+    }
+    if settings
+        .two_factor
+        .encryption_key
+        .expose_secret()
+        .is_empty()
+    {
+        problems.add("two_factor.encryption_key", "Must not be empty.");
+    }
+    if settings.two_factor.session_minutes < 1 {
+        problems.add("two_factor.session_minutes", "Must be at least 1.");
+    }
+    if settings.tracking.secret.expose_secret().is_empty() {
+        problems.add("tracking.secret", "Must not be empty.");
+    }
+    if environment == Some(Environment::Production) {
+        let secrets = [
+            ("subscription_tokens.hmac_secret", &tokens.hmac_secret),
+            (
+                "two_factor.encryption_key",
+                &settings.two_factor.encryption_key,
+            ),
+            ("tracking.secret", &settings.tracking.secret),
+        ];
+        for (key, secret) in secrets {
+            if secret
+                .expose_secret()
+                .starts_with(DEVELOPMENT_SECRET_PREFIX)
+            {
+                problems.add(key, "Must be set for production, not left at the default.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Config {
+        let mut config = Config::default();
+        config
+            .merge(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .unwrap();
+        config
+    }
+
+    const VALID: &str = r#"
+application:
+  port: 8000
+  host: 127.0.0.1
+  base_url: "http://127.0.0.1"
+database:
+  host: 127.0.0.1
+  port: 5432
+  username: postgres
+  password: password
+  database_name: newsletter
+  require_ssl: false
+email_client:
+  sender_email: "test@gmail.com"
+  timeout_milliseconds: 10000
+subscription_tokens:
+  hmac_secret: "secret"
+two_factor:
+  encryption_key: "key"
+tracking:
+  secret: "tracking"
+"#;
+
+    #[test]
+    fn valid_settings_load() {
+        let settings = load(
+            &config(VALID),
+            Some(Environment::Local),
+            Problems::default(),
+        )
+        .unwrap();
+        assert_eq!(settings.database.password.expose_secret(), "password");
+        assert_eq!(settings.login_throttle.max_failures_per_username, 5);
+    }
+
+    #[test]
+    fn every_section_that_fails_to_deserialize_is_reported() {
+        let yaml = VALID
+            .replace("port: 5432", "port: many")
+            .replace("host: 127.0.0.1\n  base_url", "host: somewhere\n  base_url");
+        let yaml = format!("{}password_hashing:\n  workers: lots\n", yaml);
+
+        let error = load(
+            &config(&yaml),
+            Some(Environment::Local),
+            Problems::default(),
+        )
+        .err()
+        .unwrap();
+
+        let keys: Vec<_> = error.problems.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["database", "application", "password_hashing"]);
+    }
+
+    #[test]
+    fn every_invalid_value_is_reported() {
+        let yaml = VALID.replace(r#"sender_email: "test@gmail.com""#, "sender_email: nope");
+        let yaml = format!(
+            "{}telemetry:\n  otlp:\n    endpoint: nope\nlogin_throttle:\n  window_seconds: 0\n",
+            yaml
+        );
+
+        let error = load(
+            &config(&yaml),
+            Some(Environment::Local),
+            Problems::default(),
+        )
+        .err()
+        .unwrap();
+
+        let keys: Vec<_> = error.problems.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "email_client.sender_email",
+                "telemetry.otlp.endpoint",
+                "login_throttle.window_seconds"
+            ]
+        );
+    }
+
+    #[test]
+    fn values_are_left_out_of_problems() {
+        let yaml = VALID
+            .replace(
+                "timeout_milliseconds: 10000",
+                r#"timeout_milliseconds: "hunter2""#,
+            )
+            .replace("hmac_secret: \"secret\"", "hmac_secret: 12");
+
+        let error = load(
+            &config(&yaml),
+            Some(Environment::Local),
+            Problems::default(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(error.problems.len(), 1);
+        assert!(!error.to_string().contains("hunter2"));
+        assert_eq!(
+            redact_values(r#"invalid type: string "hunter2", expected u16"#),
+            r#"invalid type: string "[redacted]", expected u16"#
+        );
+    }
+
+    #[test]
+    fn secrets_are_read_from_files_named_by_file_variables() {
+        assert_eq!(
+            secret_file_key("APP_DATABASE__PASSWORD_FILE").as_deref(),
+            Some("database.password")
+        );
+        assert_eq!(secret_file_key("APP_FILE"), None);
+        assert_eq!(secret_file_key("DATABASE_PASSWORD_FILE"), None);
+
+        let path = std::env::temp_dir().join(format!("db-password-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "from a file\n").unwrap();
+        let vars = HashMap::from([(
+            "APP_DATABASE__PASSWORD_FILE".to_string(),
+            path.display().to_string(),
+        )]);
+        let mut config = config(VALID);
+        let mut problems = Problems::default();
+
+        load_secret_files(&mut config, vars, &mut problems);
+        std::fs::remove_file(&path).unwrap();
+
+        let settings = load(&config, Some(Environment::Local), problems).unwrap();
+        assert_eq!(settings.database.password.expose_secret(), "from a file");
+    }
+
+    #[test]
+    fn development_secrets_are_refused_in_production() {
+        let yaml = VALID
+            .replace(
+                r#"hmac_secret: "secret""#,
+                r#"hmac_secret: "local-development-only-subscription-token-secret""#,
+            )
+            .replace(
+                r#"secret: "tracking""#,
+                r#"secret: "local-development-only-tracking-secret""#,
+            );
+
+        let local = load(
+            &config(&yaml),
+            Some(Environment::Local),
+            Problems::default(),
+        );
+        let error = load(
+            &config(&yaml),
+            Some(Environment::Production),
+            Problems::default(),
+        )
+        .err()
+        .unwrap();
+
+        assert!(local.is_ok());
+        let keys: Vec<_> = error.problems.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["subscription_tokens.hmac_secret", "tracking.secret"]);
+    }
 }
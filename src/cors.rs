@@ -0,0 +1,81 @@
+use crate::configuration::CorsSettings;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Build, Request, Response, Rocket};
+
+/// Adds CORS headers for the configured origins and answers preflight requests.
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_methods: String,
+    allowed_headers: String,
+    max_age_seconds: u32,
+}
+
+impl Cors {
+    pub fn new(settings: &CorsSettings) -> Self {
+        Self {
+            allowed_origins: settings.allowed_origins.clone(),
+            allowed_methods: settings.allowed_methods.join(", "),
+            allowed_headers: settings.allowed_headers.join(", "),
+            max_age_seconds: settings.max_age_seconds,
+        }
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+    }
+}
+
+// without a matching route Rocket would answer preflight requests with a 404
+#[options("/<_..>")]
+fn preflight() -> Status {
+    Status::NoContent
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket.mount("/", routes![preflight]))
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if self.allowed_origins.is_empty() {
+            return;
+        }
+        // whatever the origin, a cache must not hand this response to another
+        // one; adjoined, so as not to replace what the route varies on
+        response.adjoin_header(Header::new("Vary", "Origin"));
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) if self.allows(origin) => origin.to_string(),
+            _ => return,
+        };
+
+        response.set_header(Header::new("Access-Control-Allow-Origin", origin));
+
+        let is_preflight = request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method");
+        if is_preflight {
+            response.set_header(Header::new(
+                "Access-Control-Allow-Methods",
+                self.allowed_methods.clone(),
+            ));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Headers",
+                self.allowed_headers.clone(),
+            ));
+            response.set_header(Header::new(
+                "Access-Control-Max-Age",
+                self.max_age_seconds.to_string(),
+            ));
+        }
+    }
+}
@@ -0,0 +1,5 @@
+/// Compares secrets, e.g. tokens and one-time codes, in a time that depends
+/// only on their length, so that a mismatch does not tell where it starts.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
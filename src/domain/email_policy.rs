@@ -0,0 +1,233 @@
+use crate::configuration::EmailPolicySettings;
+use crate::domain::SubscriberEmail;
+use std::collections::HashSet;
+
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+const ROLE_ACCOUNTS: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noreply",
+    "postmaster",
+    "root",
+    "webmaster",
+];
+
+/// The reason an otherwise valid email address was refused by the `EmailPolicy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailPolicyViolation {
+    DisposableDomain,
+    RoleAccount,
+    DomainNotAllowed,
+    DomainDenied,
+}
+
+impl EmailPolicyViolation {
+    /// Stable, machine-readable identifier for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EmailPolicyViolation::DisposableDomain => "disposable_domain",
+            EmailPolicyViolation::RoleAccount => "role_account",
+            EmailPolicyViolation::DomainNotAllowed => "domain_not_allowed",
+            EmailPolicyViolation::DomainDenied => "domain_denied",
+        }
+    }
+}
+
+impl std::fmt::Display for EmailPolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            EmailPolicyViolation::DisposableDomain => "Disposable email domains are not accepted.",
+            EmailPolicyViolation::RoleAccount => "Role accounts are not accepted.",
+            EmailPolicyViolation::DomainNotAllowed => "The email domain is not on the allow list.",
+            EmailPolicyViolation::DomainDenied => "The email domain is on the deny list.",
+        };
+        f.write_str(description)
+    }
+}
+
+impl std::error::Error for EmailPolicyViolation {}
+
+#[derive(Clone)]
+pub struct EmailPolicy {
+    disposable_domains: HashSet<String>,
+    reject_role_accounts: bool,
+    allowed_domains: HashSet<String>,
+    denied_domains: HashSet<String>,
+    apply_provider_rules: bool,
+}
+
+impl EmailPolicy {
+    pub fn new(settings: &EmailPolicySettings) -> Result<Self, std::io::Error> {
+        let mut disposable_domains = parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS);
+        if let Some(path) = &settings.disposable_domains_path {
+            disposable_domains.extend(parse_domain_list(&std::fs::read_to_string(path)?));
+        }
+
+        Ok(Self {
+            disposable_domains,
+            reject_role_accounts: settings.reject_role_accounts,
+            allowed_domains: normalize_domains(&settings.allowed_domains),
+            denied_domains: normalize_domains(&settings.denied_domains),
+            apply_provider_rules: settings.apply_provider_rules,
+        })
+    }
+
+    /// The key subscribers are deduplicated on.
+    pub fn canonicalize(&self, email: &SubscriberEmail) -> String {
+        if self.apply_provider_rules {
+            email.canonical_with_provider_rules()
+        } else {
+            email.canonical()
+        }
+    }
+
+    /// The deny list wins over the allow list, and an explicitly allowed domain
+    /// is never rejected as disposable.
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), EmailPolicyViolation> {
+        let domain = email.domain().to_lowercase();
+
+        if matches_any(&domain, &self.denied_domains) {
+            return Err(EmailPolicyViolation::DomainDenied);
+        }
+        let explicitly_allowed = matches_any(&domain, &self.allowed_domains);
+        if !self.allowed_domains.is_empty() && !explicitly_allowed {
+            return Err(EmailPolicyViolation::DomainNotAllowed);
+        }
+        if !explicitly_allowed && matches_any(&domain, &self.disposable_domains) {
+            return Err(EmailPolicyViolation::DisposableDomain);
+        }
+        if self.reject_role_accounts && is_role_account(email.local_part()) {
+            return Err(EmailPolicyViolation::RoleAccount);
+        }
+        Ok(())
+    }
+}
+
+fn parse_domain_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn normalize_domains(domains: &[String]) -> HashSet<String> {
+    domains.iter().map(|d| d.trim().to_lowercase()).collect()
+}
+
+/// Matches the domain itself or any of its parent domains, so that listing
+/// `mailinator.com` also covers `eu.mailinator.com`.
+fn matches_any(domain: &str, domains: &HashSet<String>) -> bool {
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+fn is_role_account(local_part: &str) -> bool {
+    let mailbox = local_part
+        .split_once('+')
+        .map_or(local_part, |(mailbox, _tag)| mailbox)
+        .to_lowercase();
+    ROLE_ACCOUNTS.contains(&mailbox.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailPolicy, EmailPolicyViolation};
+    use crate::configuration::EmailPolicySettings;
+    use crate::domain::SubscriberEmail;
+    use claim::assert_ok;
+
+    fn policy(settings: EmailPolicySettings) -> EmailPolicy {
+        EmailPolicy::new(&settings).unwrap()
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn regular_addresses_are_accepted() {
+        let policy = policy(EmailPolicySettings::default());
+        assert_ok!(policy.check(&email("ursula@gmail.com")));
+    }
+
+    #[test]
+    fn bundled_disposable_domains_and_their_subdomains_are_rejected() {
+        let policy = policy(EmailPolicySettings::default());
+        assert_eq!(
+            policy.check(&email("ursula@mailinator.com")),
+            Err(EmailPolicyViolation::DisposableDomain)
+        );
+        assert_eq!(
+            policy.check(&email("ursula@EU.Mailinator.com")),
+            Err(EmailPolicyViolation::DisposableDomain)
+        );
+    }
+
+    #[test]
+    fn role_accounts_are_only_rejected_when_enabled() {
+        let lenient = policy(EmailPolicySettings::default());
+        assert_ok!(lenient.check(&email("postmaster@domain.com")));
+
+        let strict = policy(EmailPolicySettings {
+            reject_role_accounts: true,
+            ..EmailPolicySettings::default()
+        });
+        assert_eq!(
+            strict.check(&email("postmaster@domain.com")),
+            Err(EmailPolicyViolation::RoleAccount)
+        );
+        assert_eq!(
+            strict.check(&email("NoReply+news@domain.com")),
+            Err(EmailPolicyViolation::RoleAccount)
+        );
+    }
+
+    #[test]
+    fn only_allowed_domains_are_accepted_when_an_allow_list_is_configured() {
+        let policy = policy(EmailPolicySettings {
+            allowed_domains: vec!["domain.com".into()],
+            ..EmailPolicySettings::default()
+        });
+        assert_ok!(policy.check(&email("ursula@domain.com")));
+        assert_eq!(
+            policy.check(&email("ursula@gmail.com")),
+            Err(EmailPolicyViolation::DomainNotAllowed)
+        );
+    }
+
+    #[test]
+    fn denied_domains_are_rejected_even_if_allowed() {
+        let policy = policy(EmailPolicySettings {
+            allowed_domains: vec!["domain.com".into()],
+            denied_domains: vec!["Domain.com".into()],
+            ..EmailPolicySettings::default()
+        });
+        assert_eq!(
+            policy.check(&email("ursula@domain.com")),
+            Err(EmailPolicyViolation::DomainDenied)
+        );
+    }
+
+    #[test]
+    fn allowed_domains_override_the_disposable_list() {
+        let policy = policy(EmailPolicySettings {
+            allowed_domains: vec!["mailinator.com".into()],
+            ..EmailPolicySettings::default()
+        });
+        assert_ok!(policy.check(&email("ursula@mailinator.com")));
+    }
+}
@@ -1,7 +1,24 @@
+mod api_tokens;
+mod constant_time;
+mod email_policy;
 mod new_subscriber;
+pub mod permissions;
+mod roles;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_tokens;
+mod totp;
 
+pub use api_tokens::{hash_api_token, GeneratedApiToken, Scope};
+pub(crate) use constant_time::constant_time_eq;
+pub use email_policy::{EmailPolicy, EmailPolicyViolation};
 pub use new_subscriber::NewSubscriber;
+pub use permissions::Permission;
+pub use roles::Role;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscription_tokens::SubscriptionTokens;
+pub use totp::{
+    generate_recovery_codes, generate_session_token, hash_recovery_code, hash_session_token,
+    TotpSecret, TotpSecretKey,
+};
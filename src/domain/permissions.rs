@@ -0,0 +1,125 @@
+//! What admin routes require, one type per permission, for use as
+//! `Authorized<Publish>` and the like.
+use crate::domain::{Role, Scope};
+
+pub trait Permission {
+    /// Completes "may not ..." in the explanation of a refusal.
+    const DESCRIPTION: &'static str;
+    /// The API token scope that grants it, if tokens can be granted it at all.
+    const SCOPE: Option<Scope>;
+
+    fn granted_to(role: Role) -> bool;
+}
+
+pub struct Publish;
+
+impl Permission for Publish {
+    const DESCRIPTION: &'static str = "publish newsletters";
+    const SCOPE: Option<Scope> = Some(Scope::Publish);
+
+    fn granted_to(role: Role) -> bool {
+        matches!(role, Role::Owner | Role::Editor)
+    }
+}
+
+pub struct ReadSubscribers;
+
+impl Permission for ReadSubscribers {
+    const DESCRIPTION: &'static str = "read subscriber data";
+    const SCOPE: Option<Scope> = Some(Scope::ReadSubscribers);
+
+    fn granted_to(_role: Role) -> bool {
+        true
+    }
+}
+
+pub struct ManageSubscribers;
+
+impl Permission for ManageSubscribers {
+    const DESCRIPTION: &'static str = "manage subscribers";
+    const SCOPE: Option<Scope> = Some(Scope::ManageSubscribers);
+
+    fn granted_to(role: Role) -> bool {
+        matches!(role, Role::Owner | Role::Editor)
+    }
+}
+
+pub struct ViewStats;
+
+impl Permission for ViewStats {
+    const DESCRIPTION: &'static str = "view issue statistics";
+    const SCOPE: Option<Scope> = None;
+
+    fn granted_to(_role: Role) -> bool {
+        true
+    }
+}
+
+pub struct ManageApiTokens;
+
+impl Permission for ManageApiTokens {
+    const DESCRIPTION: &'static str = "manage API tokens";
+    const SCOPE: Option<Scope> = None;
+
+    fn granted_to(role: Role) -> bool {
+        role == Role::Owner
+    }
+}
+
+pub struct ManageUsers;
+
+impl Permission for ManageUsers {
+    const DESCRIPTION: &'static str = "manage users";
+    const SCOPE: Option<Scope> = None;
+
+    fn granted_to(role: Role) -> bool {
+        role == Role::Owner
+    }
+}
+
+pub struct ManageSettings;
+
+impl Permission for ManageSettings {
+    const DESCRIPTION: &'static str = "change server settings";
+    const SCOPE: Option<Scope> = None;
+
+    fn granted_to(role: Role) -> bool {
+        role == Role::Owner
+    }
+}
+
+pub struct ViewAuditLog;
+
+impl Permission for ViewAuditLog {
+    const DESCRIPTION: &'static str = "view the audit log";
+    const SCOPE: Option<Scope> = None;
+
+    fn granted_to(role: Role) -> bool {
+        role == Role::Owner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editors_can_publish_but_viewers_cannot() {
+        assert!(Publish::granted_to(Role::Owner));
+        assert!(Publish::granted_to(Role::Editor));
+        assert!(!Publish::granted_to(Role::Viewer));
+        assert!(ReadSubscribers::granted_to(Role::Viewer));
+        assert!(!ManageSubscribers::granted_to(Role::Viewer));
+    }
+
+    #[test]
+    fn only_owners_can_manage_users_tokens_and_settings() {
+        for role in [Role::Editor, Role::Viewer] {
+            assert!(!ManageUsers::granted_to(role));
+            assert!(!ManageApiTokens::granted_to(role));
+            assert!(!ManageSettings::granted_to(role));
+            assert!(!ViewAuditLog::granted_to(role));
+        }
+        assert!(ManageUsers::granted_to(Role::Owner));
+    }
+}
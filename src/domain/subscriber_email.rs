@@ -1,7 +1,9 @@
+use crate::telemetry::Pii;
+use secrecy::ExposeSecret;
 use std::fmt::Formatter;
 use validator::validate_email;
 
-#[derive(Debug)]
+/// Formatting redacts the address; use `expose_secret` to get at it.
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
@@ -12,17 +14,85 @@ impl SubscriberEmail {
             Err(format!("{} is not a valid subscriber email.", s))
         }
     }
+
+    pub fn local_part(&self) -> &str {
+        self.split().0
+    }
+
+    pub fn domain(&self) -> &str {
+        self.split().1
+    }
+
+    /// The form used to detect duplicate subscribers: the address lowercased
+    /// and an internationalized domain converted to punycode.
+    pub fn canonical(&self) -> String {
+        let (local_part, domain) = self.split();
+        format!("{}@{}", local_part.to_lowercase(), ascii_domain(domain))
+    }
+
+    /// `canonical` plus the aliasing rules of well-known providers, e.g. Gmail
+    /// ignores dots in the mailbox name and everything after a '+'.
+    pub fn canonical_with_provider_rules(&self) -> String {
+        let (local_part, domain) = self.split();
+        let mut local_part = local_part.to_lowercase();
+        let mut domain = ascii_domain(domain);
+
+        if GOOGLE_DOMAINS.contains(&domain.as_str()) {
+            local_part = strip_plus_tag(&local_part).replace('.', "");
+            domain = "gmail.com".into();
+        } else if PLUS_TAG_DOMAINS.contains(&domain.as_str()) {
+            local_part = strip_plus_tag(&local_part).to_string();
+        }
+        format!("{}@{}", local_part, domain)
+    }
+
+    fn split(&self) -> (&str, &str) {
+        // validate_email guarantees there is an '@', the last one separates the domain
+        self.0
+            .rsplit_once('@')
+            .expect("A parsed email always contains an '@'.")
+    }
+}
+
+const GOOGLE_DOMAINS: &[&str] = &["gmail.com", "googlemail.com"];
+
+const PLUS_TAG_DOMAINS: &[&str] = &[
+    "fastmail.com",
+    "hotmail.com",
+    "icloud.com",
+    "live.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+];
+
+fn ascii_domain(domain: &str) -> String {
+    idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_lowercase())
 }
 
-impl AsRef<str> for SubscriberEmail {
-    fn as_ref(&self) -> &str {
+fn strip_plus_tag(local_part: &str) -> &str {
+    local_part
+        .split_once('+')
+        .map_or(local_part, |(mailbox, _tag)| mailbox)
+}
+
+impl ExposeSecret<String> for SubscriberEmail {
+    fn expose_secret(&self) -> &String {
         &self.0
     }
 }
 
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        Pii(&self.0).fmt(f)
+    }
+}
+
+impl std::fmt::Debug for SubscriberEmail {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SubscriberEmail")
+            .field(&Pii(&self.0))
+            .finish()
     }
 }
 
@@ -32,6 +102,7 @@ mod tests {
     use claim::assert_err;
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
+    use secrecy::ExposeSecret;
 
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
@@ -61,6 +132,59 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn canonical_form_is_case_insensitive() {
+        let a = SubscriberEmail::parse("Ursula@Domain.com".to_string()).unwrap();
+        let b = SubscriberEmail::parse("ursula@domain.com".to_string()).unwrap();
+        assert_eq!(a.canonical(), "ursula@domain.com");
+        assert_eq!(a.canonical(), b.canonical());
+    }
+
+    #[test]
+    fn canonical_form_converts_internationalized_domains_to_punycode() {
+        let email = SubscriberEmail::parse("ursula@bücher.example".to_string()).unwrap();
+        assert_eq!(email.canonical(), "ursula@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn canonical_form_keeps_dots_and_plus_tags_without_provider_rules() {
+        let email = SubscriberEmail::parse("ursula.le.guin+news@gmail.com".to_string()).unwrap();
+        assert_eq!(email.canonical(), "ursula.le.guin+news@gmail.com");
+    }
+
+    #[test]
+    fn provider_rules_collapse_gmail_aliases() {
+        let email =
+            SubscriberEmail::parse("Ursula.Le.Guin+news@googlemail.com".to_string()).unwrap();
+        assert_eq!(
+            email.canonical_with_provider_rules(),
+            "ursulaleguin@gmail.com"
+        );
+    }
+
+    #[test]
+    fn provider_rules_only_strip_plus_tags_for_known_providers() {
+        let outlook = SubscriberEmail::parse("ursula.le+news@outlook.com".to_string()).unwrap();
+        assert_eq!(
+            outlook.canonical_with_provider_rules(),
+            "ursula.le@outlook.com"
+        );
+
+        let other = SubscriberEmail::parse("ursula+news@domain.com".to_string()).unwrap();
+        assert_eq!(
+            other.canonical_with_provider_rules(),
+            "ursula+news@domain.com"
+        );
+    }
+
+    #[test]
+    fn formatting_does_not_reveal_the_address() {
+        let email = SubscriberEmail::parse("ursula_le_guin@gmail.com".to_string()).unwrap();
+        assert!(!format!("{}", email).contains("ursula_le_guin"));
+        assert!(!format!("{:?}", email).contains("ursula_le_guin"));
+        assert_eq!(email.expose_secret(), "ursula_le_guin@gmail.com");
+    }
+
     #[quickcheck_macros::quickcheck]
     fn valid_emails_are_parsed_successfully(valid_email: ValidEmailFixture) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
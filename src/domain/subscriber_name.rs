@@ -1,6 +1,9 @@
+use crate::telemetry::Pii;
+use secrecy::ExposeSecret;
+use std::fmt::Formatter;
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Debug)]
+/// Formatting redacts the name; use `expose_secret` to get at it.
 pub struct SubscriberName(String);
 
 impl SubscriberName {
@@ -17,12 +20,26 @@ impl SubscriberName {
     }
 }
 
-impl AsRef<str> for SubscriberName {
-    fn as_ref(&self) -> &str {
+impl ExposeSecret<String> for SubscriberName {
+    fn expose_secret(&self) -> &String {
         &self.0
     }
 }
 
+impl std::fmt::Display for SubscriberName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Pii(&self.0).fmt(f)
+    }
+}
+
+impl std::fmt::Debug for SubscriberName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SubscriberName")
+            .field(&Pii(&self.0))
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::domain::SubscriberName;
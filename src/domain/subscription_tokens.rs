@@ -0,0 +1,96 @@
+use crate::configuration::{SubscriptionTokenSettings, TokenAlphabet};
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, Distribution, Uniform};
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+/// Generates subscription tokens and derives the keyed hash they are stored
+/// under, so that database read access is not enough to confirm a subscriber.
+pub struct SubscriptionTokens {
+    key: Secret<String>,
+    length: usize,
+    alphabet: TokenAlphabet,
+    ttl: chrono::Duration,
+}
+
+impl SubscriptionTokens {
+    pub fn new(settings: &SubscriptionTokenSettings) -> Self {
+        Self {
+            key: settings.hmac_secret.clone(),
+            length: settings.length,
+            alphabet: settings.alphabet,
+            ttl: chrono::Duration::hours(settings.expiry_hours),
+        }
+    }
+
+    pub fn generate(&self) -> String {
+        let mut rng = thread_rng();
+        match self.alphabet {
+            TokenAlphabet::Alphanumeric => std::iter::repeat_with(|| rng.sample(Alphanumeric))
+                .map(char::from)
+                .take(self.length)
+                .collect(),
+            TokenAlphabet::Hex => {
+                let digits = Uniform::from(0..16u32);
+                std::iter::repeat_with(|| digits.sample(&mut rng))
+                    .map(|d| std::char::from_digit(d, 16).unwrap())
+                    .take(self.length)
+                    .collect()
+            }
+        }
+    }
+
+    /// Hex-encoded HMAC-SHA256 of the token.
+    pub fn hash(&self, token: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(token.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    pub fn ttl(&self) -> chrono::Duration {
+        self.ttl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionTokens;
+    use crate::configuration::{SubscriptionTokenSettings, TokenAlphabet};
+    use secrecy::Secret;
+
+    fn tokens(secret: &str, length: usize, alphabet: TokenAlphabet) -> SubscriptionTokens {
+        SubscriptionTokens::new(&SubscriptionTokenSettings {
+            hmac_secret: Secret::new(secret.to_string()),
+            length,
+            alphabet,
+            expiry_hours: 24,
+        })
+    }
+
+    #[test]
+    fn generated_tokens_have_the_configured_length_and_alphabet() {
+        let alphanumeric = tokens("secret", 40, TokenAlphabet::Alphanumeric).generate();
+        assert_eq!(alphanumeric.len(), 40);
+        assert!(alphanumeric.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let hex = tokens("secret", 12, TokenAlphabet::Hex).generate();
+        assert_eq!(hex.len(), 12);
+        assert!(hex.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn hashing_is_deterministic_for_the_same_key() {
+        let tokens = tokens("secret", 25, TokenAlphabet::Alphanumeric);
+        assert_eq!(tokens.hash("token"), tokens.hash("token"));
+        assert_ne!(tokens.hash("token"), "token");
+    }
+
+    #[test]
+    fn hashes_depend_on_the_key() {
+        let a = tokens("secret", 25, TokenAlphabet::Alphanumeric);
+        let b = tokens("another secret", 25, TokenAlphabet::Alphanumeric);
+        assert_ne!(a.hash("token"), b.hash("token"));
+    }
+}
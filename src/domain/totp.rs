@@ -0,0 +1,342 @@
+use super::constant_time_eq;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, Uniform};
+use rand::{thread_rng, Rng, RngCore};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// RFC 6238 defaults, which is all that most authenticator apps support.
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from this many steps before or after the current one are accepted,
+/// to allow for clocks that are a little off.
+const ALLOWED_SKEW_STEPS: i64 = 1;
+/// 160 bits, as RFC 4226 recommends.
+const SECRET_LENGTH: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// 16 characters of 32 possible ones: 80 bits, enough for an unsalted hash.
+const RECOVERY_CODE_LENGTH: usize = 16;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// 32 alphanumeric characters: over 190 bits.
+const SESSION_TOKEN_LENGTH: usize = 32;
+
+/// The shared secret of a time-based one-time password (RFC 6238) setup.
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut secret = vec![0; SECRET_LENGTH];
+        thread_rng().fill_bytes(&mut secret);
+        Self(secret)
+    }
+
+    /// Parses a secret in the base32 form that authenticator apps take.
+    pub fn parse(base32: &str) -> Option<Self> {
+        base32_decode(base32).filter(|s| !s.is_empty()).map(Self)
+    }
+
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.0)
+    }
+
+    /// The URI that authenticator apps take, typically as a QR code.
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        let mut uri = reqwest::Url::parse("otpauth://totp/").expect("The base URI is valid.");
+        uri.path_segments_mut()
+            .expect("The base URI has a path.")
+            .pop()
+            .push(&format!("{}:{}", issuer, account));
+        uri.query_pairs_mut()
+            .append_pair("secret", &self.to_base32())
+            .append_pair("issuer", issuer)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &STEP_SECONDS.to_string());
+        uri.to_string()
+    }
+
+    /// The code for the time step that `at` falls in.
+    pub fn code_at(&self, at: DateTime<Utc>) -> String {
+        self.code_for_step(step(at))
+    }
+
+    /// The time step that `code` is the code for, if it is one within the
+    /// allowed skew of `at`. Callers must refuse steps that were already used,
+    /// or a code that was overheard could be replayed until it expires.
+    pub fn verify(&self, code: &str, at: DateTime<Utc>) -> Option<i64> {
+        let code = code.trim();
+        if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let current = step(at);
+        // every step is compared, so that the time taken does not tell which matched
+        (-ALLOWED_SKEW_STEPS..=ALLOWED_SKEW_STEPS)
+            .map(|skew| current + skew)
+            .fold(None, |matched, step| {
+                let expected = self.code_for_step(step);
+                if constant_time_eq(expected.as_bytes(), code.as_bytes()) {
+                    Some(step)
+                } else {
+                    matched
+                }
+            })
+    }
+
+    /// HOTP (RFC 4226) for the counter `step`.
+    fn code_for_step(&self, step: i64) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.0).expect("HMAC takes keys of any size.");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let truncated = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            truncated % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+}
+
+/// Encrypts TOTP secrets for storage, with AES-256-GCM under a key derived
+/// from the configured one. Unlike passwords they cannot be hashed, as codes
+/// are computed from them, and a leaked database should not be enough to
+/// compute codes.
+#[derive(Clone)]
+pub struct TotpSecretKey(Arc<LessSafeKey>);
+
+impl TotpSecretKey {
+    pub fn new(key: &Secret<String>) -> Self {
+        let key = Sha256::digest(key.expose_secret().as_bytes());
+        let key = UnboundKey::new(&AES_256_GCM, &key).expect("SHA-256 makes a valid AES-256 key.");
+        Self(Arc::new(LessSafeKey::new(key)))
+    }
+
+    /// The secret as stored for `user_id`: base64 of a random nonce followed
+    /// by the ciphertext. The user id is authenticated along with it, so a
+    /// secret copied to another user's row does not decrypt.
+    pub fn seal(&self, user_id: Uuid, secret: &TotpSecret) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+        let mut sealed = secret.0.clone();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(user_id.as_bytes()),
+                &mut sealed,
+            )
+            .expect("Secrets are far below the AES-GCM size limit.");
+        base64::encode([&nonce[..], &sealed].concat())
+    }
+
+    /// Decrypts what `seal` stored for `user_id`.
+    pub fn open(&self, user_id: Uuid, sealed: &str) -> Option<TotpSecret> {
+        let sealed = base64::decode(sealed).ok()?;
+        if sealed.len() <= NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut in_out = ciphertext.to_vec();
+        let secret = self
+            .0
+            .open_in_place(nonce, Aad::from(user_id.as_bytes()), &mut in_out)
+            .ok()?;
+        Some(TotpSecret(secret.to_vec())).filter(|s| !s.0.is_empty())
+    }
+}
+
+fn step(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(STEP_SECONDS)
+}
+
+/// Fresh recovery codes, formatted as `xxxx-xxxx-xxxx-xxxx` for readability.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let alphabet = Uniform::from(0..BASE32_ALPHABET.len());
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let characters: Vec<char> = thread_rng()
+                .sample_iter(alphabet)
+                .take(RECOVERY_CODE_LENGTH)
+                .map(|i| BASE32_ALPHABET[i].to_ascii_lowercase() as char)
+                .collect();
+            characters
+                .chunks(4)
+                .map(|chunk| chunk.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Hex-encoded SHA-256 of the code, ignoring case, dashes and spaces, so that
+/// it can be typed in however it was written down.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// A token for a session started with a one-time code; only its hash is
+/// stored.
+pub fn generate_session_token() -> String {
+    thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(SESSION_TOKEN_LENGTH)
+        .collect()
+}
+
+/// Hex-encoded SHA-256 of a session token, which is random enough to need no
+/// salt or slow hash.
+pub fn hash_session_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// RFC 4648 base32, without padding.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 8 / 5 + 1);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes base32 with or without padding, ignoring case and spaces.
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.trim_end_matches('=').chars() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// The SHA-1 secret of the RFC 6238 test vectors.
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(b"12345678901234567890".to_vec())
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_test_vectors() {
+        let secret = rfc_secret();
+        // the RFC lists 8-digit codes; these are their last 6 digits
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ];
+        for (timestamp, code) in vectors {
+            assert_eq!(
+                secret.code_at(Utc.timestamp_opt(timestamp, 0).unwrap()),
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn codes_are_accepted_within_the_allowed_skew() {
+        let secret = rfc_secret();
+        let at = Utc.timestamp_opt(1111111109, 0).unwrap();
+        let code = secret.code_at(at);
+
+        assert_eq!(secret.verify(&code, at), Some(step(at)));
+        assert_eq!(
+            secret.verify(&code, at + chrono::Duration::seconds(30)),
+            Some(step(at))
+        );
+        assert_eq!(
+            secret.verify(&code, at - chrono::Duration::seconds(30)),
+            Some(step(at))
+        );
+        assert_eq!(
+            secret.verify(&code, at + chrono::Duration::seconds(90)),
+            None
+        );
+        assert_eq!(secret.verify("12345", at), None);
+        assert_eq!(secret.verify("abcdef", at), None);
+    }
+
+    #[test]
+    fn secrets_round_trip_through_base32() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+        let secret = TotpSecret::generate();
+        assert_eq!(TotpSecret::parse(&secret.to_base32()).unwrap().0, secret.0);
+        assert!(TotpSecret::parse("not base32!").is_none());
+    }
+
+    #[test]
+    fn sealed_secrets_only_open_for_their_user_and_key() {
+        let key = TotpSecretKey::new(&Secret::new("key".to_string()));
+        let (user_id, secret) = (Uuid::new_v4(), rfc_secret());
+
+        let sealed = key.seal(user_id, &secret);
+
+        assert!(!sealed.contains(&secret.to_base32()));
+        assert_eq!(key.open(user_id, &sealed).unwrap().0, secret.0);
+        assert!(key.open(Uuid::new_v4(), &sealed).is_none());
+        let other_key = TotpSecretKey::new(&Secret::new("other key".to_string()));
+        assert!(other_key.open(user_id, &sealed).is_none());
+        assert!(key.open(user_id, &secret.to_base32()).is_none());
+    }
+
+    #[test]
+    fn recovery_codes_are_hashed_however_they_are_typed() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), 10);
+        assert_eq!(codes[0].len(), 19);
+        let typed = codes[0].replace('-', " ").to_uppercase();
+        assert_eq!(hash_recovery_code(&typed), hash_recovery_code(&codes[0]));
+        assert_ne!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[1]));
+    }
+
+    #[test]
+    fn provisioning_uris_name_the_issuer_and_account() {
+        let uri = rfc_secret().provisioning_uri("Our newsletter", "admin");
+        assert_eq!(
+            uri,
+            "otpauth://totp/Our%20newsletter:admin?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=Our+newsletter&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
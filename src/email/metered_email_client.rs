@@ -0,0 +1,52 @@
+use crate::domain::SubscriberEmail;
+use crate::email::{Delivery, Email};
+use crate::metrics::{EMAILS_SENT, EMAIL_SEND_DURATION};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Records the outcome and latency of every email sent through the wrapped backend.
+pub struct MeteredEmailClient {
+    inner: Arc<dyn Email>,
+}
+
+impl MeteredEmailClient {
+    pub fn new(inner: Arc<dyn Email>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl Email for MeteredEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<Delivery, anyhow::Error> {
+        let backend = self.inner.backend();
+        let timer = EMAIL_SEND_DURATION
+            .with_label_values(&[backend])
+            .start_timer();
+        let result = self
+            .inner
+            .send_email(recipient, subject, html_content, text_content)
+            .await;
+        timer.observe_duration();
+
+        let outcome = match result {
+            Ok(_) => "success",
+            Err(_) => "failure",
+        };
+        EMAILS_SENT.with_label_values(&[backend, outcome]).inc();
+        result
+    }
+
+    fn backend(&self) -> &'static str {
+        self.inner.backend()
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.inner.check_health().await
+    }
+}
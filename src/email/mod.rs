@@ -1,8 +1,23 @@
+mod metered_email_client;
 mod ses_email_client;
+mod suppressing_email_client;
+mod trace_context_connector;
 
 use crate::domain::SubscriberEmail;
 use async_trait::async_trait;
+pub use metered_email_client::MeteredEmailClient;
 pub use ses_email_client::SesEmailClient;
+pub use suppressing_email_client::SuppressingEmailClient;
+pub use trace_context_connector::TraceContextConnector;
+
+/// What became of an email handed to `Email::send_email`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    /// Accepted by the backend, under the message id it assigned, if any.
+    Sent { message_id: Option<String> },
+    /// Not sent, as the recipient is on the suppression list.
+    Suppressed,
+}
 
 #[async_trait]
 pub trait Email: Send + Sync {
@@ -12,5 +27,13 @@ pub trait Email: Send + Sync {
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), anyhow::Error>;
+    ) -> Result<Delivery, anyhow::Error>;
+
+    /// Short name of the delivery backend, used to label metrics.
+    fn backend(&self) -> &'static str;
+
+    /// Confirms that the backend is reachable and accepts our credentials.
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
 }
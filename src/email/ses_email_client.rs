@@ -1,10 +1,13 @@
 use crate::configuration::Settings;
 use crate::domain::SubscriberEmail;
-use crate::email::Email;
+use crate::email::{Delivery, Email, TraceContextConnector};
 use async_trait::async_trait;
 use aws_config::TimeoutConfig;
 use aws_sdk_sesv2 as ses;
 use aws_sdk_sesv2::model::{Body, Content, Destination, EmailContent, Message};
+use aws_smithy_client::erase::DynConnector;
+use aws_smithy_client::hyper_ext::Adapter;
+use secrecy::ExposeSecret;
 use std::time::Duration;
 
 pub struct SesEmailClient {
@@ -27,7 +30,10 @@ impl SesEmailClient {
             .timeout_config(timeout_config)
             .load()
             .await;
-        let ses_client = ses::Client::new(&shared_config);
+        let connector =
+            TraceContextConnector::new(Adapter::builder().build(aws_smithy_client::conns::https()));
+        let ses_client =
+            ses::Client::from_conf_conn((&shared_config).into(), DynConnector::new(connector));
         Self { ses_client, sender }
     }
 }
@@ -40,7 +46,7 @@ impl Email for SesEmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<Delivery, anyhow::Error> {
         let html_content = Content::builder()
             .data(html_content)
             .charset("UTF-8")
@@ -57,16 +63,28 @@ impl Email for SesEmailClient {
         let message = Message::builder().subject(subject).body(body).build();
         let content = EmailContent::builder().simple(message).build();
         let destination = Destination::builder()
-            .to_addresses(recipient.as_ref())
+            .to_addresses(recipient.expose_secret())
             .build();
 
-        self.ses_client
+        let output = self
+            .ses_client
             .send_email()
-            .from_email_address(self.sender.as_ref())
+            .from_email_address(self.sender.expose_secret())
             .destination(destination)
             .content(content)
             .send()
             .await?;
+        Ok(Delivery::Sent {
+            message_id: output.message_id,
+        })
+    }
+
+    fn backend(&self) -> &'static str {
+        "ses"
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.ses_client.get_account().send().await?;
         Ok(())
     }
 }
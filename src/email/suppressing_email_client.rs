@@ -0,0 +1,77 @@
+use crate::domain::{EmailPolicy, SubscriberEmail};
+use crate::email::{Delivery, Email};
+use crate::metrics::EMAILS_SENT;
+use crate::startup::NewsletterDbPool;
+use anyhow::Context;
+use async_trait::async_trait;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use std::sync::Arc;
+
+/// Drops emails to addresses on the suppression list before they reach the
+/// wrapped backend.
+///
+/// Sends are not tied to a request's connection, so the client checks the
+/// list on one of its own from the app's pool.
+pub struct SuppressingEmailClient {
+    inner: Arc<dyn Email>,
+    pool: NewsletterDbPool,
+    email_policy: EmailPolicy,
+}
+
+impl SuppressingEmailClient {
+    pub fn new(inner: Arc<dyn Email>, pool: NewsletterDbPool, email_policy: EmailPolicy) -> Self {
+        Self {
+            inner,
+            pool,
+            email_policy,
+        }
+    }
+}
+
+#[async_trait]
+impl Email for SuppressingEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<Delivery, anyhow::Error> {
+        let canonical_email = self.email_policy.canonicalize(recipient);
+        // an address whose status is unknown is not mailed
+        let conn = self.pool.get().await?;
+        let suppressed = conn
+            .run(move |c: &mut PgConnection| {
+                use crate::schema::suppressions;
+                suppressions::table
+                    .filter(suppressions::canonical_email.eq(&canonical_email))
+                    .count()
+                    .get_result::<i64>(c)
+            })
+            .await
+            .context("Failed to check the suppression list.")?
+            > 0;
+
+        if suppressed {
+            tracing::info!(
+                recipient = %recipient,
+                "Not sending to an address on the suppression list."
+            );
+            EMAILS_SENT
+                .with_label_values(&[self.inner.backend(), "suppressed"])
+                .inc();
+            return Ok(Delivery::Suppressed);
+        }
+        self.inner
+            .send_email(recipient, subject, html_content, text_content)
+            .await
+    }
+
+    fn backend(&self) -> &'static str {
+        self.inner.backend()
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.inner.check_health().await
+    }
+}
@@ -0,0 +1,83 @@
+use aws_smithy_http::body::SdkBody;
+use opentelemetry::global;
+use opentelemetry_http::HeaderInjector;
+use std::task::{Context, Poll};
+use tower::Service;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Connector for the AWS SDK that adds the W3C trace context of the current
+/// span to every request sent to the email provider.
+#[derive(Clone)]
+pub struct TraceContextConnector<C>(C);
+
+impl<C> TraceContextConnector<C> {
+    pub fn new(inner: C) -> Self {
+        Self(inner)
+    }
+}
+
+impl<C> Service<http::Request<SdkBody>> for TraceContextConnector<C>
+where
+    C: Service<http::Request<SdkBody>>,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<SdkBody>) -> Self::Future {
+        let context = tracing::Span::current().context();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut HeaderInjector(request.headers_mut()))
+        });
+        self.0.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceContextConnector;
+    use aws_smithy_http::body::SdkBody;
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::trace::TracerProvider;
+    use opentelemetry::trace::TracerProvider as _;
+    use std::convert::Infallible;
+    use tower::{Service, ServiceExt};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    #[tokio::test]
+    async fn requests_carry_the_trace_context_of_the_current_span() {
+        // arrange
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        // tracers only hold a weak reference to their provider
+        let provider = TracerProvider::default();
+        let tracer = provider.tracer("test");
+        let subscriber =
+            Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let mut connector = TraceContextConnector::new(tower::service_fn(
+            |request: http::Request<SdkBody>| async move {
+                Ok::<_, Infallible>(request.headers().get("traceparent").cloned())
+            },
+        ));
+        let span = tracing::info_span!("Send an email");
+        let _entered = span.enter();
+
+        // act
+        let traceparent = connector
+            .ready()
+            .await
+            .unwrap()
+            .call(http::Request::new(SdkBody::empty()))
+            .await
+            .unwrap();
+
+        // assert
+        let traceparent = traceparent.expect("No traceparent header was added.");
+        assert!(traceparent.to_str().unwrap().starts_with("00-"));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::domain::{hash_api_token, Scope};
+use crate::guards::{auth_failure, BearerToken, OrStatus};
+use crate::problem::Problem;
+use crate::startup::NewsletterDbConn;
+use anyhow::anyhow;
+use chrono::{Duration, Utc};
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
+    RunQueryDsl,
+};
+use rocket::http::Status;
+use rocket::outcome::try_outcome;
+use rocket::outcome::Outcome::{Failure, Success};
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+/// How stale `last_used_at` may get, so that a busy token does not cost a
+/// write on every request.
+fn last_used_resolution() -> Duration {
+    Duration::minutes(1)
+}
+
+/// A program authenticated by an API token that is neither expired nor
+/// revoked.
+// prevents construction outside of this module
+#[non_exhaustive]
+pub struct ApiClient {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl ApiClient {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for ApiClient {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let conn = try_outcome!(request.guard::<NewsletterDbConn>().await.map_failure(|_| {
+            auth_failure(
+                request,
+                Problem::new(Status::InternalServerError, "internal_error"),
+                anyhow!("Failed to retrieve a connection from the DB pool."),
+            )
+        }));
+        let bearer_token = try_outcome!(request.guard::<BearerToken>().await.map_failure(|_| {
+            auth_failure(
+                request,
+                Problem::new(Status::Unauthorized, "missing_credentials")
+                    .with_detail("A bearer token is required."),
+                anyhow!("Client did not supply a bearer token."),
+            )
+        }));
+
+        match validate_token(conn, bearer_token).await {
+            Ok(client) => Success(client),
+            Err((status, err)) => {
+                // unknown, expired and revoked tokens are deliberately indistinguishable
+                let problem = if status == Status::Unauthorized {
+                    Problem::new(status, "invalid_token")
+                        .with_detail("The bearer token is invalid, expired or revoked.")
+                } else {
+                    Problem::new(status, "internal_error")
+                };
+                Failure(auth_failure(request, problem, err))
+            }
+        }
+    }
+}
+
+#[tracing::instrument(name = "Validate API token", skip(conn, bearer_token))]
+async fn validate_token(
+    conn: NewsletterDbConn,
+    bearer_token: BearerToken,
+) -> Result<ApiClient, (Status, anyhow::Error)> {
+    let token_hash = hash_api_token(bearer_token.token.expose_secret());
+    let token = conn
+        .run(move |conn: &mut PgConnection| {
+            use crate::schema::api_tokens;
+            let now = Utc::now();
+            let token = api_tokens::table
+                .select((api_tokens::id, api_tokens::name, api_tokens::scopes))
+                .filter(api_tokens::token_hash.eq(token_hash))
+                .filter(api_tokens::revoked_at.is_null())
+                .filter(api_tokens::expires_at.gt(now))
+                .first::<(Uuid, String, Vec<String>)>(conn)
+                .optional()?;
+            if let Some((id, _, _)) = &token {
+                diesel::update(
+                    api_tokens::table.find(id).filter(
+                        api_tokens::last_used_at
+                            .is_null()
+                            .or(api_tokens::last_used_at.lt(now - last_used_resolution())),
+                    ),
+                )
+                .set(api_tokens::last_used_at.eq(now))
+                .execute(conn)?;
+            }
+            Ok::<_, diesel::result::Error>(token)
+        })
+        .await
+        .or_status(
+            Status::InternalServerError,
+            "Failed to perform a query to validate an API token.",
+        )?;
+
+    let (token_id, name, scopes) = token.or_status(
+        Status::Unauthorized,
+        "Unknown, expired or revoked API token.",
+    )?;
+    Ok(ApiClient {
+        token_id,
+        name,
+        // scopes that this version does not know grant nothing
+        scopes: scopes.iter().filter_map(|s| Scope::parse(s)).collect(),
+    })
+}
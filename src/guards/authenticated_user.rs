@@ -1,17 +1,26 @@
-use crate::guards::{BasicAuth, OrStatus};
+use crate::audit::{Actor, AuditEntry};
+use crate::clock::Clock;
+use crate::domain::{hash_recovery_code, hash_session_token, Role, TotpSecretKey};
+use crate::guards::{BasicAuth, OrStatus, RequestId};
+use crate::login_throttle::{Attempt, LoginThrottle, Refusal};
 use crate::models::User;
+use crate::password_hashing::{PasswordError, PasswordHashing};
+use crate::problem::Problem;
 use crate::startup::NewsletterDbConn;
-use crate::telemetry::spawn_blocking_with_tracing;
 use anyhow::anyhow;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use chrono::Utc;
 use diesel::OptionalExtension;
-use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
 use rocket::http::Status;
 use rocket::outcome::try_outcome;
 use rocket::outcome::Outcome::{Failure, Success};
 use rocket::request::{FromRequest, Outcome};
 use rocket::Request;
 use secrecy::{ExposeSecret, Secret};
+use std::net::IpAddr;
+use std::sync::Arc;
 use uuid::Uuid;
 
 // prevents construction outside of this module
@@ -19,6 +28,18 @@ use uuid::Uuid;
 pub struct AuthenticatedUser {
     pub user_id: Uuid,
     pub username: String,
+    pub role: Role,
+    pub second_factor: SecondFactor,
+}
+
+/// How the user passed two-factor authentication on this request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecondFactor {
+    NotEnabled,
+    /// A TOTP code, or a recovery code.
+    OneTimeCode,
+    /// A session started earlier with a one-time code.
+    Session,
 }
 
 #[async_trait]
@@ -26,78 +47,414 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
     type Error = anyhow::Error;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let conn = try_outcome!(request.guard::<NewsletterDbConn>().await.map_failure(|_| (
-            Status::InternalServerError,
-            anyhow!("Failed to retrieve a connection from the DB pool.")
-        )));
-        let basic_auth = try_outcome!(request.guard::<BasicAuth>().await.map_failure(|_| (
-            Status::Unauthorized,
-            anyhow!("User did not supply Basic Auth credentials.")
-        )));
-
-        match validate_credentials(conn, basic_auth).await {
-            Ok(user) => Success(user),
-            Err((status, err)) => Failure((status, err)),
+        let conn = try_outcome!(request.guard::<NewsletterDbConn>().await.map_failure(|_| {
+            auth_failure(
+                request,
+                Problem::new(Status::InternalServerError, "internal_error"),
+                anyhow!("Failed to retrieve a connection from the DB pool."),
+            )
+        }));
+        let basic_auth = try_outcome!(request.guard::<BasicAuth>().await.map_failure(|_| {
+            auth_failure(
+                request,
+                Problem::new(Status::Unauthorized, "missing_credentials")
+                    .with_detail("Basic Auth credentials are required."),
+                anyhow!("User did not supply Basic Auth credentials."),
+            )
+        }));
+
+        let throttle = try_outcome!(managed::<LoginThrottle>(request)).clone();
+        let hashing = try_outcome!(managed::<PasswordHashing>(request));
+        // guesses are throttled before Argon2 runs, so they cost next to nothing
+        // the peer address, not `client_ip`, which trusts X-Real-IP: a guesser
+        // could otherwise send a new one with every attempt
+        let ip = request.remote().map(|remote| remote.ip());
+        let username = basic_auth.username.clone();
+        let reservation = {
+            let (throttle, username) = (throttle.clone(), username.clone());
+            conn.run(move |c: &mut PgConnection| throttle.reserve(c, &username, ip, Utc::now()))
+                .await
+        };
+        match reservation {
+            Ok(None) => {}
+            Ok(Some(refusal)) => {
+                return Failure(auth_failure(
+                    request,
+                    throttled(&refusal),
+                    anyhow!("Login attempt refused: {:?}.", refusal),
+                ))
+            }
+            Err(e) => {
+                return Failure(auth_failure(
+                    request,
+                    Problem::new(Status::InternalServerError, "internal_error"),
+                    anyhow::Error::new(e).context("Failed to check the login throttle."),
+                ))
+            }
+        }
+
+        let result = match validate_credentials(&conn, hashing, basic_auth).await {
+            Ok(user) => second_factor(request, &conn, user).await,
+            Err((status, err)) => Err((credentials_problem(status), err)),
+        };
+        let attempt = match &result {
+            Ok(_) => Attempt::Succeeded,
+            Err((problem, _)) if problem.status() == Status::Unauthorized => Attempt::Failed,
+            Err(_) => Attempt::Abandoned,
+        };
+        record_outcome(
+            &conn,
+            RequestId::get(request),
+            throttle,
+            username,
+            ip,
+            attempt,
+        )
+        .await;
+        let (user, second_factor) = match result {
+            Ok(result) => result,
+            Err((problem, err)) => return Failure(auth_failure(request, problem, err)),
+        };
+        match Role::parse(&user.role) {
+            Some(role) => Success(AuthenticatedUser {
+                user_id: user.user_id,
+                username: user.username,
+                role,
+                second_factor,
+            }),
+            None => Failure(auth_failure(
+                request,
+                Problem::new(Status::InternalServerError, "internal_error"),
+                anyhow!("The user has an unknown role."),
+            )),
         }
     }
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(conn, basic_auth))]
+fn credentials_problem(status: Status) -> Problem {
+    // unknown usernames and wrong passwords are deliberately indistinguishable
+    if status == Status::Unauthorized {
+        Problem::new(status, "invalid_credentials").with_detail("Invalid username or password.")
+    } else if status == Status::ServiceUnavailable {
+        Problem::new(status, "verification_busy")
+            .with_detail("Too many logins are being checked; try again shortly.")
+            .with_retry_after(1)
+    } else {
+        Problem::new(status, "internal_error")
+    }
+}
+
+/// Users with two-factor authentication enabled must also send a current
+/// TOTP code, or one of their unused recovery codes, in the `X-OTP` header.
+/// Each code is accepted once: a request needs a code from a later time step
+/// than the last one accepted. A code can start a session instead, whose
+/// token in the `X-2FA-Session` header stands in for codes until it expires.
+#[tracing::instrument(name = "Check second factor", skip(request, conn, user))]
+async fn second_factor(
+    request: &Request<'_>,
+    conn: &NewsletterDbConn,
+    user: User,
+) -> Result<(User, SecondFactor), (Problem, anyhow::Error)> {
+    if user.totp_enabled_at.is_none() {
+        return Ok((user, SecondFactor::NotEnabled));
+    }
+    let internal_error = |err: anyhow::Error| {
+        (
+            Problem::new(Status::InternalServerError, "internal_error"),
+            err,
+        )
+    };
+    let clock = request
+        .rocket()
+        .state::<Arc<dyn Clock>>()
+        .ok_or_else(|| internal_error(anyhow!("The clock is not being managed.")))?;
+    if let Some(session) = request.headers().get_one("X-2FA-Session") {
+        let (user_id, token_hash, now) = (user.user_id, hash_session_token(session), clock.now());
+        let found = conn
+            .run(move |c: &mut PgConnection| {
+                use crate::schema::two_factor_sessions;
+                two_factor_sessions::table
+                    .find(token_hash)
+                    .filter(two_factor_sessions::user_id.eq(user_id))
+                    .filter(two_factor_sessions::expires_at.gt(now))
+                    .count()
+                    .get_result::<i64>(c)
+            })
+            .await
+            .map_err(|e| {
+                internal_error(anyhow::Error::new(e).context("Failed to look up a session."))
+            })?;
+        return if found == 1 {
+            Ok((user, SecondFactor::Session))
+        } else {
+            Err((
+                Problem::new(Status::Unauthorized, "invalid_session")
+                    .with_detail("The two-factor session is invalid or has expired."),
+                anyhow!("User supplied an invalid two-factor session."),
+            ))
+        };
+    }
+    let code = match request.headers().get_one("X-OTP") {
+        Some(code) => code.trim().to_string(),
+        None => {
+            return Err((
+                Problem::new(Status::Unauthorized, "otp_required").with_detail(
+                    "A one-time code is required in the X-OTP header, \
+                    or a session in the X-2FA-Session header.",
+                ),
+                anyhow!("User did not supply a one-time code."),
+            ))
+        }
+    };
+    let invalid_otp = || {
+        (
+            Problem::new(Status::Unauthorized, "invalid_otp")
+                .with_detail("The one-time code is invalid or has expired."),
+            anyhow!("User supplied an invalid one-time code."),
+        )
+    };
+
+    if code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit()) {
+        let key = request
+            .rocket()
+            .state::<TotpSecretKey>()
+            .ok_or_else(|| internal_error(anyhow!("The TOTP secret key is not being managed.")))?;
+        let user_id = user.user_id;
+        let secret = user
+            .totp_secret
+            .as_deref()
+            .and_then(|s| key.open(user_id, s))
+            .ok_or_else(|| internal_error(anyhow!("The user has an invalid TOTP secret.")))?;
+        let step = match secret.verify(&code, clock.now()) {
+            Some(step) => step,
+            None => return Err(invalid_otp()),
+        };
+        // accepting the code and moving past its step is one statement, so
+        // that concurrent requests cannot both use it
+        let accepted = conn
+            .run(move |c: &mut PgConnection| {
+                use crate::schema::users;
+                diesel::update(
+                    users::table.find(user_id).filter(
+                        users::totp_last_step
+                            .is_null()
+                            .or(users::totp_last_step.lt(step)),
+                    ),
+                )
+                .set(users::totp_last_step.eq(step))
+                .execute(c)
+            })
+            .await
+            .map_err(|e| {
+                internal_error(anyhow::Error::new(e).context("Failed to accept a one-time code."))
+            })?;
+        return if accepted == 1 {
+            Ok((user, SecondFactor::OneTimeCode))
+        } else {
+            Err(invalid_otp())
+        };
+    }
+
+    let (user_id, code_hash) = (user.user_id, hash_recovery_code(&code));
+    let audit = AuditEntry::new(
+        Actor::User {
+            id: user.user_id,
+            name: user.username.clone(),
+        },
+        "two_factor.recovery_code_used",
+        &RequestId::get(request),
+    )
+    .with_target_type("user")
+    .with_target(user.user_id);
+    let used = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::recovery_codes;
+            c.transaction(|| {
+                // only one of two concurrent logins gets to use a code
+                let used = diesel::update(
+                    recovery_codes::table
+                        .find((user_id, code_hash))
+                        .filter(recovery_codes::used_at.is_null()),
+                )
+                .set(recovery_codes::used_at.eq(Utc::now()))
+                .execute(c)?;
+                if used == 0 {
+                    return Ok(false);
+                }
+                let remaining = recovery_codes::table
+                    .filter(recovery_codes::user_id.eq(user_id))
+                    .filter(recovery_codes::used_at.is_null())
+                    .count()
+                    .get_result::<i64>(c)?;
+                audit
+                    .with_after(serde_json::json!({ "recovery_codes_left": remaining }))
+                    .record(c)?;
+                Ok::<_, diesel::result::Error>(true)
+            })
+        })
+        .await
+        .map_err(|e| {
+            internal_error(anyhow::Error::new(e).context("Failed to use a recovery code."))
+        })?;
+    if !used {
+        return Err(invalid_otp());
+    }
+    Ok((user, SecondFactor::OneTimeCode))
+}
+
+fn managed<'r, T: Send + Sync + 'static>(
+    request: &'r Request<'_>,
+) -> Outcome<&'r T, anyhow::Error> {
+    match request.rocket().state::<T>() {
+        Some(state) => Success(state),
+        None => Failure(auth_failure(
+            request,
+            Problem::new(Status::InternalServerError, "internal_error"),
+            anyhow!("{} is not being managed.", std::any::type_name::<T>()),
+        )),
+    }
+}
+
+pub(super) fn auth_failure(
+    request: &Request<'_>,
+    problem: Problem,
+    error: anyhow::Error,
+) -> (Status, anyhow::Error) {
+    let status = problem.status();
+    problem.stash(request);
+    (status, error)
+}
+
+/// The problem for an attempt refused by the login throttle. Which of the
+/// username or the address is throttled is not revealed.
+fn throttled(refusal: &Refusal) -> Problem {
+    let (code, detail, until) = match refusal {
+        Refusal::LockedOut { until } => (
+            "locked_out",
+            "Too many failed logins; logging in is locked for a while.",
+            until,
+        ),
+        Refusal::TooSoon { until } => (
+            "login_throttled",
+            "Wait a moment after a failed login before trying again.",
+            until,
+        ),
+        Refusal::InProgress => {
+            return Problem::new(Status::TooManyRequests, "login_in_progress")
+                .with_detail("Another login for this username is being checked; try again shortly.")
+                .with_retry_after(1)
+        }
+    };
+    let wait = (*until - Utc::now()).num_milliseconds().max(0) as f64 / 1000.0;
+    Problem::new(Status::TooManyRequests, code)
+        .with_detail(detail)
+        .with_retry_after((wait.ceil() as u64).max(1))
+}
+
+/// Updates the login throttle; a failure to do so does not change the outcome
+/// of the login.
+async fn record_outcome(
+    conn: &NewsletterDbConn,
+    request_id: RequestId,
+    throttle: LoginThrottle,
+    username: String,
+    ip: Option<IpAddr>,
+    attempt: Attempt,
+) {
+    let recorded = conn
+        .run(move |c: &mut PgConnection| {
+            match attempt {
+                Attempt::Succeeded => return throttle.record_success(c, &username),
+                Attempt::Abandoned => return throttle.record_abandoned(c, &username),
+                Attempt::Failed => {}
+            }
+            let lockouts = throttle.record_failure(c, &username, ip, Utc::now())?;
+            for lockout in lockouts {
+                tracing::warn!(
+                    kind = lockout.kind.as_str(),
+                    "Repeated failed logins started a lockout."
+                );
+                AuditEntry::new(Actor::System, "login.locked_out", &request_id)
+                    .with_target_type(lockout.kind.as_str())
+                    .with_target(&lockout.subject)
+                    .with_after(serde_json::json!({ "locked_until": lockout.until }))
+                    .record(c)?;
+            }
+            Ok(())
+        })
+        .await;
+    if let Err(e) = recorded {
+        tracing::error!(error.cause_chain = ?e, "Failed to update the login throttle.")
+    }
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(conn, hashing, basic_auth))]
 async fn validate_credentials(
-    conn: NewsletterDbConn,
+    conn: &NewsletterDbConn,
+    hashing: &PasswordHashing,
     basic_auth: BasicAuth,
-) -> Result<AuthenticatedUser, (Status, anyhow::Error)> {
+) -> Result<User, (Status, anyhow::Error)> {
     let user: Option<User> = get_stored_credentials(conn, basic_auth.username).await?;
 
-    let expected_password_hash = Secret::new(
-        user.as_ref()
-            .map(|user| user.password_hash.clone())
-            .unwrap_or_else(|| {
-                "$argon2id$v=19$m=15000,t=2,p=1$\
-                gZiV/M1gPc22ElAH/Jh1Hw$\
-                CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-                    .to_string()
-            }),
-    );
-
-    spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash, basic_auth.password)
-    })
-    .await
-    .or_status(Status::InternalServerError, "Failed to spawn/join thread.")??;
+    let expected_password_hash = user
+        .as_ref()
+        .map(|user| Secret::new(user.password_hash.clone()));
+    let password = Secret::new(basic_auth.password.expose_secret().clone());
+    match hashing
+        .verify(expected_password_hash, basic_auth.password)
+        .await
+    {
+        Ok(()) => {}
+        Err(PasswordError::Mismatch) => {
+            return Err((
+                Status::Unauthorized,
+                anyhow!("Invalid username or password."),
+            ))
+        }
+        Err(e @ PasswordError::Busy) => return Err((Status::ServiceUnavailable, e.into())),
+        Err(PasswordError::Unexpected(e)) => return Err((Status::InternalServerError, e)),
+    }
 
     let user = user.or_status(Status::Unauthorized, "Unknown username.")?;
-    Ok(AuthenticatedUser {
-        user_id: user.user_id,
-        username: user.username,
-    })
+    if hashing.needs_rehash(&user.password_hash) {
+        rehash_password(conn, hashing, user.user_id, password).await;
+    }
+    Ok(user)
 }
 
-#[tracing::instrument(
-    name = "Verify password hash",
-    skip(expected_password_hash, password_candidate)
-)]
-fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
-) -> Result<(), (Status, anyhow::Error)> {
-    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
-        .or_status(
-            Status::InternalServerError,
-            "Failed to parse hash in PHC string format.",
-        )?;
-
-    Argon2::default()
-        .verify_password(
-            password_candidate.expose_secret().as_bytes(),
-            &expected_password_hash,
-        )
-        .or_status(Status::Unauthorized, "Invalid password.")
+/// Brings a stored hash up to the configured parameters, now that the
+/// password is known. Failing to do so does not fail the login: it will be
+/// tried again the next time.
+#[tracing::instrument(name = "Rehash password", skip(conn, hashing, password))]
+async fn rehash_password(
+    conn: &NewsletterDbConn,
+    hashing: &PasswordHashing,
+    user_id: Uuid,
+    password: Secret<String>,
+) {
+    let password_hash = match hashing.hash(password).await {
+        Ok(password_hash) => password_hash,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Failed to rehash a password.");
+            return;
+        }
+    };
+    let stored = conn
+        .run(move |conn: &mut PgConnection| {
+            use crate::schema::users;
+            diesel::update(users::table.find(user_id))
+                .set(users::password_hash.eq(password_hash.expose_secret()))
+                .execute(conn)
+        })
+        .await;
+    if let Err(e) = stored {
+        tracing::warn!(error.cause_chain = ?e, "Failed to store a rehashed password.");
+    }
 }
 
 #[tracing::instrument(name = "Get stored credentials", skip(conn, username))]
 async fn get_stored_credentials(
-    conn: NewsletterDbConn,
+    conn: &NewsletterDbConn,
     username: String,
 ) -> Result<Option<User>, (Status, anyhow::Error)> {
     conn.run(move |conn: &mut PgConnection| {
@@ -0,0 +1,69 @@
+use crate::domain::Permission;
+use crate::guards::{auth_failure, Caller};
+use crate::problem::Problem;
+use anyhow::anyhow;
+use rocket::http::Status;
+use rocket::outcome::try_outcome;
+use rocket::outcome::Outcome::{Failure, Success};
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use std::marker::PhantomData;
+
+/// A caller that holds permission `P`: a user whose role grants it, or an API
+/// token with the matching scope.
+///
+/// Callers that authenticate but lack the permission get a 403 explaining why,
+/// rather than a 401 that would have browsers prompt for other credentials.
+///
+/// Authenticating takes a connection of its own, so routes list this guard
+/// before their `NewsletterDbConn`: a request then never holds one connection
+/// while waiting for another, which concurrent requests could exhaust the pool
+/// with.
+pub struct Authorized<P: Permission> {
+    pub caller: Caller,
+    permission: PhantomData<P>,
+}
+
+#[async_trait]
+impl<'r, P: Permission> FromRequest<'r> for Authorized<P> {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let caller = try_outcome!(request.guard::<Caller>().await);
+        let refusal = match &caller {
+            Caller::User(user) if P::granted_to(user.role) => None,
+            Caller::User(user) => Some(Problem::new(Status::Forbidden, "forbidden").with_detail(
+                format!("The '{}' role may not {}.", user.role, P::DESCRIPTION),
+            )),
+            Caller::Client(client) => match P::SCOPE {
+                Some(scope) if client.has_scope(scope) => None,
+                Some(scope) => Some(
+                    Problem::new(Status::Forbidden, "insufficient_scope")
+                        .with_detail(format!("The API token lacks the '{}' scope.", scope)),
+                ),
+                None => Some(
+                    Problem::new(Status::Forbidden, "forbidden")
+                        .with_detail(format!("API tokens may not {}.", P::DESCRIPTION)),
+                ),
+            },
+        };
+
+        match refusal {
+            None => Success(Authorized {
+                caller,
+                permission: PhantomData,
+            }),
+            Some(problem) => Failure(auth_failure(
+                request,
+                problem,
+                anyhow!("{} may not {}.", caller, P::DESCRIPTION),
+            )),
+        }
+    }
+}
+
+impl<P: Permission> std::fmt::Display for Authorized<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.caller.fmt(f)
+    }
+}
@@ -0,0 +1,36 @@
+use super::credentials;
+use anyhow::Context;
+use rocket::http::Status;
+use rocket::outcome::IntoOutcome;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use secrecy::Secret;
+
+pub struct BearerToken {
+    pub token: Secret<String>,
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        from_request_result(request).into_outcome(Status::Unauthorized)
+    }
+}
+
+fn from_request_result(request: &Request) -> Result<BearerToken, anyhow::Error> {
+    let header_value = request
+        .headers()
+        .get_one("Authorization")
+        .context("The 'Authorization' header was missing")?;
+
+    let token = credentials(header_value, "Bearer")
+        .context("The authorization scheme was not 'Bearer'.")?
+        .trim();
+    anyhow::ensure!(!token.is_empty(), "The bearer token was empty.");
+
+    Ok(BearerToken {
+        token: Secret::new(token.to_string()),
+    })
+}
@@ -0,0 +1,46 @@
+use crate::guards::{credentials, ApiClient, AuthenticatedUser};
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+/// Whoever is making an admin request: a user with Basic Auth credentials, or
+/// a program with an API token in `Authorization: Bearer`.
+pub enum Caller {
+    User(AuthenticatedUser),
+    Client(ApiClient),
+}
+
+impl Caller {
+    pub fn user(&self) -> Option<&AuthenticatedUser> {
+        match self {
+            Caller::User(user) => Some(user),
+            Caller::Client(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Caller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Caller::User(user) => write!(f, "user {} ({})", user.username, user.user_id),
+            Caller::Client(client) => write!(f, "token {} ({})", client.name, client.token_id),
+        }
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let bearer = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| credentials(value, "Bearer"))
+            .is_some();
+        if bearer {
+            request.guard::<ApiClient>().await.map(Caller::Client)
+        } else {
+            request.guard::<AuthenticatedUser>().await.map(Caller::User)
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::domain::constant_time_eq;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use rocket::http::{Cookie, CookieJar, SameSite};
+
+const CSRF_COOKIE: &str = "csrf_token";
+
+/// Double-submit CSRF protection: the token is stored in a same-site cookie and
+/// must be echoed back in the submitted form.
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// Reuses the token from the cookie, or issues a new one.
+    pub fn issue(cookies: &CookieJar<'_>) -> CsrfToken {
+        if let Some(cookie) = cookies.get(CSRF_COOKIE) {
+            return CsrfToken(cookie.value().to_string());
+        }
+
+        let mut rng = thread_rng();
+        let token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(32)
+            .collect();
+        cookies.add(
+            Cookie::build(CSRF_COOKIE, token.clone())
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .finish(),
+        );
+        CsrfToken(token)
+    }
+
+    pub fn verify(cookies: &CookieJar<'_>, submitted: &str) -> bool {
+        match cookies.get(CSRF_COOKIE) {
+            Some(cookie) => constant_time_eq(cookie.value().as_bytes(), submitted.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+impl AsRef<str> for CsrfToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
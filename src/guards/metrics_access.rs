@@ -0,0 +1,73 @@
+use super::credentials;
+use crate::configuration::MetricsSettings;
+use crate::domain::constant_time_eq;
+use crate::problem::Problem;
+use rocket::http::Status;
+use rocket::outcome::Outcome::{Failure, Success};
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use secrecy::{ExposeSecret, Secret};
+use std::net::IpAddr;
+
+/// Who may scrape `/metrics`: clients connecting from an allowed address, or
+/// presenting the configured bearer token.
+pub struct MetricsAccess {
+    bearer_token: Option<Secret<String>>,
+    allowed_addresses: Vec<IpAddr>,
+}
+
+impl MetricsAccess {
+    pub fn new(settings: &MetricsSettings) -> Self {
+        Self {
+            bearer_token: settings.bearer_token.clone(),
+            allowed_addresses: settings.allowed_addresses.clone(),
+        }
+    }
+
+    fn allows(&self, request: &Request<'_>) -> bool {
+        // the peer address, not `client_ip`, which trusts X-Real-IP
+        let from_allowed_address = matches!(
+            request.remote(),
+            Some(remote) if self.allowed_addresses.contains(&remote.ip())
+        );
+        from_allowed_address || self.has_valid_token(request)
+    }
+
+    fn has_valid_token(&self, request: &Request<'_>) -> bool {
+        let expected = match &self.bearer_token {
+            Some(token) => token.expose_secret(),
+            None => return false,
+        };
+        matches!(
+            request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|value| credentials(value, "Bearer")),
+            Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes())
+        )
+    }
+}
+
+/// Request guard for the metrics endpoint.
+pub struct MetricsScraper;
+
+#[async_trait]
+impl<'r> FromRequest<'r> for MetricsScraper {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let allowed = matches!(
+            request.rocket().state::<MetricsAccess>(),
+            Some(access) if access.allows(request)
+        );
+        if allowed {
+            return Success(MetricsScraper);
+        }
+
+        let problem = Problem::new(Status::Forbidden, "metrics_forbidden")
+            .with_detail("Metrics are only available to configured scrapers.");
+        let status = problem.status();
+        problem.stash(request);
+        Failure((status, anyhow::anyhow!("Metrics scrape was not allowed.")))
+    }
+}
@@ -1,9 +1,23 @@
+mod api_client;
 mod authenticated_user;
+mod authorized;
 mod basic_auth;
+mod bearer_token;
+mod caller;
+mod csrf;
+mod metrics_access;
+mod request_id;
 
 use anyhow::{anyhow, Context};
+pub use api_client::*;
 pub use authenticated_user::*;
+pub use authorized::*;
 pub use basic_auth::*;
+pub use bearer_token::*;
+pub use caller::*;
+pub use csrf::*;
+pub use metrics_access::*;
+pub use request_id::*;
 use rocket::http::Status;
 
 trait OrStatus<T> {
@@ -33,3 +47,12 @@ impl<T> OrStatus<T> for Option<T> {
         self.ok_or_else(|| (status, anyhow!(context)))
     }
 }
+
+/// The credentials in an `Authorization` header value that uses `scheme`,
+/// which is compared case-insensitively, as RFC 7235 has it.
+fn credentials<'a>(header_value: &'a str, scheme: &str) -> Option<&'a str> {
+    let (given, credentials) = header_value.split_once(' ')?;
+    given
+        .eq_ignore_ascii_case(scheme)
+        .then(|| credentials.trim_start_matches(' '))
+}
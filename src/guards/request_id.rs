@@ -0,0 +1,73 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request, Response};
+use std::convert::Infallible;
+use uuid::Uuid;
+
+/// Identifies a single request across error bodies and logs.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+impl RequestId {
+    pub fn get(request: &Request<'_>) -> RequestId {
+        request
+            .local_cache(|| RequestId(Uuid::new_v4().to_string()))
+            .clone()
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::get(request))
+    }
+}
+
+/// Adopts the `X-Request-Id` sent by the client or a proxy in front of us, or
+/// generates one, and echoes it on every response.
+pub struct RequestIdHeader;
+
+#[rocket::async_trait]
+impl Fairing for RequestIdHeader {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request id",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let incoming = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .filter(|id| is_acceptable(id))
+            .map(String::from);
+        if let Some(id) = incoming {
+            request.local_cache(|| RequestId(id));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_header(Header::new(REQUEST_ID_HEADER, RequestId::get(request).0));
+    }
+}
+
+// the id ends up in logs and headers, so anything unusual is replaced
+fn is_acceptable(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
@@ -3,14 +3,28 @@ extern crate rocket;
 #[macro_use]
 extern crate diesel;
 
+pub mod audit;
+pub mod canonical_emails;
 pub mod catchers;
+pub mod clock;
 pub mod configuration;
+pub mod cors;
 pub mod domain;
 pub mod email;
 pub mod guards;
+pub mod log_filter;
+pub mod login_throttle;
+pub mod metrics;
 pub mod models;
+pub mod pages;
+pub mod password_hashing;
 pub mod port_saver;
+pub mod problem;
 pub mod routes;
 pub mod schema;
+pub mod shutdown;
+pub mod sns;
 pub mod startup;
 pub mod telemetry;
+pub mod trace_context;
+pub mod tracking;
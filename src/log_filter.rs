@@ -0,0 +1,156 @@
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing_subscriber::filter::ParseError;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+static LOG_FILTER: OnceCell<LogFilter> = OnceCell::new();
+
+/// The process-wide log filter, which can be replaced while the app runs.
+///
+/// A change may come with a time to live, after which the filter goes back to
+/// the last change that had none, or to the filter the process started with.
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    state: Mutex<State>,
+}
+
+struct State {
+    directives: String,
+    baseline: String,
+    revert_at: Option<DateTime<Utc>>,
+    // bumped on every change, so a pending revert can tell it is stale
+    generation: u64,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct LogFilterStatus {
+    pub directives: String,
+    pub revert_at: Option<DateTime<Utc>>,
+}
+
+/// A change that undoes itself once its time to live has elapsed.
+#[must_use = "the change is only undone once the revert is waited for"]
+pub struct PendingRevert {
+    filter: &'static LogFilter,
+    generation: u64,
+    ttl: Duration,
+}
+
+/// A temporary change that was undone.
+#[derive(Debug)]
+pub struct Reverted {
+    pub previous: String,
+    pub directives: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogFilterError {
+    #[error("The directives must not be empty.")]
+    Empty,
+    #[error("Invalid directives: {0}")]
+    Invalid(#[from] ParseError),
+    #[error("Failed to reload the log filter: {0}")]
+    Reload(#[from] reload::Error),
+}
+
+impl LogFilter {
+    /// Makes `handle` the global log filter. Only the first call has an
+    /// effect, so the filter belongs to the subscriber that was built first.
+    pub(crate) fn install(handle: reload::Handle<EnvFilter, Registry>, directives: String) {
+        let _ = LOG_FILTER.set(LogFilter {
+            handle,
+            state: Mutex::new(State {
+                directives: directives.clone(),
+                baseline: directives,
+                revert_at: None,
+                generation: 0,
+            }),
+        });
+    }
+
+    /// `None` until a subscriber has been built with `get_subscriber`.
+    pub fn global() -> Option<&'static LogFilter> {
+        LOG_FILTER.get()
+    }
+
+    pub fn status(&self) -> LogFilterStatus {
+        let state = self.state.lock().unwrap();
+        LogFilterStatus {
+            directives: state.directives.clone(),
+            revert_at: state.revert_at,
+        }
+    }
+
+    /// Replaces the filter with `directives`, in `RUST_LOG` syntax. With a
+    /// `ttl` the change comes with a revert, which undoes it once the ttl has
+    /// elapsed.
+    pub fn set(
+        &'static self,
+        directives: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(LogFilterStatus, Option<PendingRevert>), LogFilterError> {
+        let directives = directives.trim();
+        if directives.is_empty() {
+            return Err(LogFilterError::Empty);
+        }
+        let filter = EnvFilter::try_new(directives)?;
+
+        let mut state = self.state.lock().unwrap();
+        self.handle.reload(filter)?;
+        state.directives = directives.to_string();
+        state.generation += 1;
+        let revert = match ttl {
+            Some(ttl) => {
+                state.revert_at = chrono::Duration::from_std(ttl)
+                    .ok()
+                    .map(|ttl| Utc::now() + ttl);
+                Some(PendingRevert {
+                    filter: self,
+                    generation: state.generation,
+                    ttl,
+                })
+            }
+            None => {
+                state.baseline = state.directives.clone();
+                state.revert_at = None;
+                None
+            }
+        };
+        let status = LogFilterStatus {
+            directives: state.directives.clone(),
+            revert_at: state.revert_at,
+        };
+        Ok((status, revert))
+    }
+
+    fn revert(&self, generation: u64) -> Option<Reverted> {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return None;
+        }
+        let baseline = state.baseline.clone();
+        // the baseline was valid when it was applied, so it still parses
+        if let Err(e) = self.handle.reload(EnvFilter::new(&baseline)) {
+            tracing::error!(error.cause_chain = ?e, "Failed to revert the log filter.");
+            return None;
+        }
+        let previous = std::mem::replace(&mut state.directives, baseline.clone());
+        state.revert_at = None;
+        state.generation += 1;
+        Some(Reverted {
+            previous,
+            directives: baseline,
+        })
+    }
+}
+
+impl PendingRevert {
+    /// Waits out the time to live, then undoes the change, unless another
+    /// change came after it.
+    pub async fn wait(self) -> Option<Reverted> {
+        tokio::time::sleep(self.ttl).await;
+        self.filter.revert(self.generation)
+    }
+}
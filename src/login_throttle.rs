@@ -0,0 +1,404 @@
+use crate::configuration::LoginThrottleSettings;
+use crate::models::{LoginFailures, NewLoginFailures};
+use chrono::{DateTime, Duration, Utc};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, RunQueryDsl,
+};
+use std::net::IpAddr;
+
+/// What failed logins are counted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleKind {
+    Username,
+    Ip,
+}
+
+impl ThrottleKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "username" => Some(ThrottleKind::Username),
+            "ip" => Some(ThrottleKind::Ip),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleKind::Username => "username",
+            ThrottleKind::Ip => "ip",
+        }
+    }
+}
+
+/// Why a login attempt was refused before its password was even checked.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Refusal {
+    LockedOut {
+        until: DateTime<Utc>,
+    },
+    TooSoon {
+        until: DateTime<Utc>,
+    },
+    /// Another attempt for the username is still being checked.
+    InProgress,
+}
+
+/// How an attempt that was let through ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attempt {
+    Succeeded,
+    Failed,
+    /// Ended without the credentials being judged, e.g. because verifying
+    /// them was too busy; it is neither forgiven nor counted.
+    Abandoned,
+}
+
+/// How long an attempt holds off the others for its username at most, should
+/// its outcome never be recorded, e.g. because the instance checking it died.
+const ATTEMPT_TIMEOUT_SECONDS: u64 = 60;
+
+/// A lockout that a failed login has just started.
+pub struct Lockout {
+    pub kind: ThrottleKind,
+    pub subject: String,
+    pub until: DateTime<Utc>,
+}
+
+/// Slows down and locks out password guessing, so that neither credential
+/// stuffing nor the cost of Argon2 can be scaled up at will.
+///
+/// Failures are kept in the database rather than in memory, so they survive
+/// restarts and are shared by every instance of the application. Unknown
+/// usernames are throttled like known ones, which keeps them
+/// indistinguishable.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    settings: LoginThrottleSettings,
+}
+
+impl LoginThrottle {
+    pub fn new(settings: &LoginThrottleSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+        }
+    }
+
+    /// Whether an attempt to log in as `username` from `ip` must be refused.
+    ///
+    /// While `username` has recent failures, an attempt that is let through
+    /// also claims it, in one transaction on its locked row, so that no other
+    /// attempt is let through until the outcome of this one is recorded:
+    /// concurrent guesses cannot all get past the check before the first of
+    /// them is counted. Usernames without failures are only read, so that
+    /// concurrent requests of their owner are neither held up nor slowed down.
+    pub fn reserve(
+        &self,
+        conn: &PgConnection,
+        username: &str,
+        ip: Option<IpAddr>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Refusal>, diesel::result::Error> {
+        use crate::schema::login_failures;
+        let failures = find(conn, ThrottleKind::Username, username)?;
+        if !matches!(&failures, Some(failures) if self.is_current(failures, now)) {
+            return self.check(conn, failures.as_ref(), ip, now);
+        }
+        conn.transaction(|| {
+            let row = login_failures::table.find((ThrottleKind::Username.as_str(), username));
+            // the failures may have been forgiven in the meantime
+            let failures = row.for_update().first::<LoginFailures>(conn).optional()?;
+            let refusal = self.check(conn, failures.as_ref(), ip, now)?;
+            if refusal.is_none() && failures.is_some() {
+                diesel::update(row)
+                    .set(login_failures::attempt_started_at.eq(now))
+                    .execute(conn)?;
+            }
+            Ok(refusal)
+        })
+    }
+
+    fn check(
+        &self,
+        conn: &PgConnection,
+        username_failures: Option<&LoginFailures>,
+        ip: Option<IpAddr>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Refusal>, diesel::result::Error> {
+        let ip_failures = match ip {
+            Some(ip) => find(conn, ThrottleKind::Ip, &ip.to_string())?,
+            None => None,
+        };
+        // a lockout wins over a delay, and the longer wait over the shorter
+        let mut locked_until = None;
+        let mut wait_until = None;
+        let mut in_progress = false;
+        for failures in username_failures.into_iter().chain(ip_failures.as_ref()) {
+            match self.refusal(failures, now) {
+                Some(Refusal::LockedOut { until }) => locked_until = locked_until.max(Some(until)),
+                Some(Refusal::TooSoon { until }) => wait_until = wait_until.max(Some(until)),
+                Some(Refusal::InProgress) => in_progress = true,
+                None => {}
+            }
+        }
+        Ok(match (locked_until, wait_until) {
+            (Some(until), _) => Some(Refusal::LockedOut { until }),
+            (None, Some(until)) => Some(Refusal::TooSoon { until }),
+            (None, None) if in_progress => Some(Refusal::InProgress),
+            (None, None) => None,
+        })
+    }
+
+    /// Counts a failed login, returning the lockouts it started.
+    pub fn record_failure(
+        &self,
+        conn: &PgConnection,
+        username: &str,
+        ip: Option<IpAddr>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Lockout>, diesel::result::Error> {
+        self.forget_stale(conn, now)?;
+        let mut lockouts = Vec::new();
+        for (kind, subject) in subjects(username, ip) {
+            if let Some(until) = self.count_failure(conn, kind, &subject, now)? {
+                lockouts.push(Lockout {
+                    kind,
+                    subject,
+                    until,
+                });
+            }
+        }
+        Ok(lockouts)
+    }
+
+    /// Forgets the failures of `username`. Those of the address are kept: one
+    /// valid account must not let an address guess the passwords of others.
+    pub fn record_success(
+        &self,
+        conn: &PgConnection,
+        username: &str,
+    ) -> Result<(), diesel::result::Error> {
+        use crate::schema::login_failures;
+        diesel::delete(login_failures::table.find((ThrottleKind::Username.as_str(), username)))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Lets the next attempt for `username` through, without counting this one.
+    pub fn record_abandoned(
+        &self,
+        conn: &PgConnection,
+        username: &str,
+    ) -> Result<(), diesel::result::Error> {
+        use crate::schema::login_failures;
+        diesel::update(login_failures::table.find((ThrottleKind::Username.as_str(), username)))
+            .set(login_failures::attempt_started_at.eq(None::<DateTime<Utc>>))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Whether `failures` still count at `now`.
+    pub fn is_current(&self, failures: &LoginFailures, now: DateTime<Utc>) -> bool {
+        failures.locked_until.filter(|until| *until > now).is_some()
+            || failures.last_failure_at > now - self.window()
+    }
+
+    /// How many of `failures` a further failure adds to: none if they are
+    /// stale or ended in a lockout that is over.
+    fn counted(&self, failures: &LoginFailures, now: DateTime<Utc>) -> i32 {
+        let lockout_over = failures
+            .locked_until
+            .filter(|until| *until <= now)
+            .is_some();
+        if lockout_over || !self.is_current(failures, now) {
+            0
+        } else {
+            failures.failures
+        }
+    }
+
+    /// How long a username waits after its `failures`-th failure in a row.
+    fn delay(&self, failures: i32) -> Duration {
+        let doublings = failures.saturating_sub(1).clamp(0, 32) as u32;
+        let delay = self
+            .settings
+            .initial_delay_milliseconds
+            .saturating_mul(1 << doublings)
+            .min(self.settings.max_delay_seconds.saturating_mul(1000));
+        Duration::milliseconds(delay.min(i64::MAX as u64) as i64)
+    }
+
+    fn max_failures(&self, kind: ThrottleKind) -> i32 {
+        let max = match kind {
+            ThrottleKind::Username => self.settings.max_failures_per_username,
+            ThrottleKind::Ip => self.settings.max_failures_per_ip,
+        };
+        max.min(i32::MAX as u32) as i32
+    }
+
+    fn window(&self) -> Duration {
+        seconds(self.settings.window_seconds)
+    }
+
+    fn refusal(&self, failures: &LoginFailures, now: DateTime<Utc>) -> Option<Refusal> {
+        match failures.locked_until {
+            Some(until) if until > now => Some(Refusal::LockedOut { until }),
+            _ if failures.next_attempt_at > now => Some(Refusal::TooSoon {
+                until: failures.next_attempt_at,
+            }),
+            _ if failures.attempt_started_at > Some(now - seconds(ATTEMPT_TIMEOUT_SECONDS)) => {
+                Some(Refusal::InProgress)
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds a failure, returning the end of the lockout it starts, if any.
+    fn count_failure(
+        &self,
+        conn: &PgConnection,
+        kind: ThrottleKind,
+        subject: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, diesel::result::Error> {
+        use crate::schema::login_failures;
+        conn.transaction(|| {
+            diesel::insert_into(login_failures::table)
+                .values(NewLoginFailures {
+                    kind: kind.as_str(),
+                    subject,
+                    failures: 0,
+                    last_failure_at: &now,
+                    next_attempt_at: &now,
+                })
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            let current = login_failures::table
+                .find((kind.as_str(), subject))
+                .for_update()
+                .first::<LoginFailures>(conn)?;
+            let failures = self.counted(&current, now).saturating_add(1);
+            let locked_until = if failures >= self.max_failures(kind) {
+                Some(now + seconds(self.settings.lockout_seconds))
+            } else {
+                None
+            };
+            // addresses are only ever locked out: delaying them would hold up
+            // everyone behind a shared address after a single typo
+            let next_attempt_at = match kind {
+                ThrottleKind::Username => now + self.delay(failures),
+                ThrottleKind::Ip => now,
+            };
+            diesel::update(login_failures::table.find((kind.as_str(), subject)))
+                .set((
+                    login_failures::failures.eq(failures),
+                    login_failures::last_failure_at.eq(now),
+                    login_failures::next_attempt_at.eq(next_attempt_at),
+                    login_failures::locked_until.eq(locked_until),
+                    login_failures::attempt_started_at.eq(None::<DateTime<Utc>>),
+                ))
+                .execute(conn)?;
+            Ok(locked_until)
+        })
+    }
+
+    fn forget_stale(
+        &self,
+        conn: &PgConnection,
+        now: DateTime<Utc>,
+    ) -> Result<(), diesel::result::Error> {
+        use crate::schema::login_failures;
+        diesel::delete(
+            login_failures::table
+                .filter(login_failures::last_failure_at.lt(now - self.window()))
+                .filter(
+                    login_failures::locked_until
+                        .is_null()
+                        .or(login_failures::locked_until.lt(now)),
+                ),
+        )
+        .execute(conn)?;
+        Ok(())
+    }
+}
+
+fn subjects(username: &str, ip: Option<IpAddr>) -> Vec<(ThrottleKind, String)> {
+    let mut subjects = vec![(ThrottleKind::Username, username.to_string())];
+    if let Some(ip) = ip {
+        subjects.push((ThrottleKind::Ip, ip.to_string()));
+    }
+    subjects
+}
+
+fn find(
+    conn: &PgConnection,
+    kind: ThrottleKind,
+    subject: &str,
+) -> Result<Option<LoginFailures>, diesel::result::Error> {
+    use crate::schema::login_failures;
+    login_failures::table
+        .find((kind.as_str(), subject))
+        .first::<LoginFailures>(conn)
+        .optional()
+}
+
+fn seconds(seconds: u64) -> Duration {
+    Duration::seconds(seconds.min(i64::MAX as u64 / 1000) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let throttle = LoginThrottle::new(&LoginThrottleSettings {
+            initial_delay_milliseconds: 500,
+            max_delay_seconds: 3,
+            ..LoginThrottleSettings::default()
+        });
+
+        let delays: Vec<_> = (1..=5)
+            .map(|failures| throttle.delay(failures).num_milliseconds())
+            .collect();
+
+        assert_eq!(delays, vec![500, 1000, 2000, 3000, 3000]);
+        assert_eq!(throttle.delay(i32::MAX).num_seconds(), 3);
+    }
+
+    #[test]
+    fn lockouts_outrank_delays() {
+        let throttle = LoginThrottle::new(&LoginThrottleSettings::default());
+        let now = Utc::now();
+        let failures = LoginFailures {
+            kind: "username".into(),
+            subject: "admin".into(),
+            failures: 5,
+            last_failure_at: now,
+            next_attempt_at: now + Duration::seconds(30),
+            locked_until: Some(now + Duration::seconds(10)),
+            attempt_started_at: Some(now + Duration::seconds(40)),
+        };
+
+        assert_eq!(
+            throttle.refusal(&failures, now),
+            Some(Refusal::LockedOut {
+                until: now + Duration::seconds(10)
+            })
+        );
+        assert_eq!(
+            throttle.refusal(&failures, now + Duration::seconds(20)),
+            Some(Refusal::TooSoon {
+                until: now + Duration::seconds(30)
+            })
+        );
+        assert_eq!(
+            throttle.refusal(&failures, now + Duration::seconds(50)),
+            Some(Refusal::InProgress)
+        );
+        assert_eq!(
+            throttle.refusal(&failures, now + Duration::minutes(2)),
+            None
+        );
+    }
+}
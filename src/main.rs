@@ -1,20 +1,47 @@
 use std::sync::Arc;
+use zero2prod::clock::SystemClock;
 use zero2prod::configuration::get_configuration;
 use zero2prod::email::SesEmailClient;
 use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::telemetry::{get_subscriber, init_otlp_tracer, init_subscriber, Redaction};
 
 #[rocket::main]
 async fn main() -> Result<(), rocket::Error> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+    // logging is configured by the settings, so problems with them can only
+    // be reported on stderr
+    let configuration = match get_configuration() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    Redaction::new(&configuration.telemetry).install();
+
+    let tracer = configuration
+        .telemetry
+        .otlp
+        .as_ref()
+        .map(|otlp| init_otlp_tracer(otlp).expect("Failed to set up the OTLP exporter."));
+    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout, tracer);
     init_subscriber(subscriber);
 
-    let configuration = get_configuration().expect("Failed to read configuration.");
     let email_client = SesEmailClient::new(&configuration).await;
 
-    Application::build(&configuration, Arc::new(email_client))
-        .await?
-        .server
-        .launch()
-        .await
+    let result = Application::build(
+        &configuration,
+        Arc::new(email_client),
+        Arc::new(SystemClock),
+    )
+    .await?
+    .run_until_stopped()
+    .await;
+    // flushes spans that are still queued for export
+    opentelemetry::global::shutdown_tracer_provider();
+    // the report itself was logged when the grace period ended; the exit
+    // status lets the orchestrator tell that work was abandoned
+    if !result?.is_clean() {
+        std::process::exit(1);
+    }
+    Ok(())
 }
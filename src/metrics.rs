@@ -0,0 +1,145 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::time::Instant;
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests handled, by route and response status.",
+        &["method", "route", "status"]
+    )
+    .expect("Failed to register http_requests_total.")
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time spent handling HTTP requests, by route.",
+        &["method", "route"]
+    )
+    .expect("Failed to register http_request_duration_seconds.")
+});
+
+pub static EMAILS_SENT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "emails_sent_total",
+        "Emails handed to the email backend, by outcome.",
+        &["backend", "outcome"]
+    )
+    .expect("Failed to register emails_sent_total.")
+});
+
+pub static EMAIL_SEND_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "email_send_duration_seconds",
+        "Time spent sending a single email.",
+        &["backend"]
+    )
+    .expect("Failed to register email_send_duration_seconds.")
+});
+
+pub static PASSWORD_VERIFICATION_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "password_verification_duration_seconds",
+        "Time spent verifying an Argon2 password hash."
+    )
+    .expect("Failed to register password_verification_duration_seconds.")
+});
+
+pub static ARGON2_QUEUE_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "argon2_queue_wait_seconds",
+        "Time spent waiting for a free Argon2 worker."
+    )
+    .expect("Failed to register argon2_queue_wait_seconds.")
+});
+
+pub static ARGON2_JOBS_IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "argon2_jobs_in_flight",
+        "Password hashes being computed or verified."
+    )
+    .expect("Failed to register argon2_jobs_in_flight.")
+});
+
+pub static ARGON2_JOBS_REJECTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "argon2_jobs_rejected_total",
+        "Password hashing turned away because no Argon2 worker came free in time."
+    )
+    .expect("Failed to register argon2_jobs_rejected_total.")
+});
+
+pub static DB_POOL_CHECKOUT_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "db_pool_checkout_duration_seconds",
+        "Time spent waiting for a connection from the database pool."
+    )
+    .expect("Failed to register db_pool_checkout_duration_seconds.")
+});
+
+/// Renders every registered metric in the Prometheus text format, together
+/// with the subscriber counts taken for this scrape.
+pub fn render(subscriber_counts: &[(&str, i64)]) -> Result<String, anyhow::Error> {
+    // a registry per scrape, so the gauges only ever hold this scrape's counts
+    let scrape = Registry::new();
+    let subscribers = IntGaugeVec::new(
+        Opts::new("subscribers", "Subscribers by status."),
+        &["status"],
+    )?;
+    scrape.register(Box::new(subscribers.clone()))?;
+    for (status, count) in subscriber_counts {
+        subscribers.with_label_values(&[status]).set(*count);
+    }
+
+    let mut families = prometheus::gather();
+    families.extend(scrape.gather());
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&families, &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+struct RequestStart(Instant);
+
+/// Records the count and latency of every request, labelled by the matched
+/// route rather than the raw path to keep cardinality bounded.
+pub struct RequestMetrics;
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let elapsed = request
+            .local_cache(|| RequestStart(Instant::now()))
+            .0
+            .elapsed();
+        let method = request.method().as_str();
+        let route = request
+            .route()
+            .map(|route| route.uri.path())
+            .unwrap_or("unmatched");
+
+        HTTP_REQUESTS
+            .with_label_values(&[method, route, &response.status().code.to_string()])
+            .inc();
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+}
@@ -0,0 +1,31 @@
+use crate::schema::api_tokens;
+use chrono::offset::Utc;
+use chrono::DateTime;
+use uuid::Uuid;
+
+#[derive(Queryable)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub token_hash: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub created_by: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_tokens"]
+pub struct NewApiToken<'a> {
+    pub id: &'a Uuid,
+    pub name: &'a str,
+    pub token_hash: &'a str,
+    pub token_prefix: &'a str,
+    pub scopes: &'a [String],
+    pub created_by: &'a Uuid,
+    pub created_at: &'a DateTime<Utc>,
+    pub expires_at: &'a DateTime<Utc>,
+}
@@ -0,0 +1,34 @@
+use crate::schema::audit_log;
+use chrono::offset::Utc;
+use chrono::DateTime;
+use uuid::Uuid;
+
+#[derive(Queryable)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor_type: String,
+    pub actor_id: Option<Uuid>,
+    pub actor_name: String,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub request_id: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+pub struct NewAuditLogEntry<'a> {
+    pub occurred_at: &'a DateTime<Utc>,
+    pub actor_type: &'a str,
+    pub actor_id: Option<&'a Uuid>,
+    pub actor_name: &'a str,
+    pub action: &'a str,
+    pub target_type: &'a str,
+    pub target_id: Option<&'a str>,
+    pub before: Option<&'a serde_json::Value>,
+    pub after: Option<&'a serde_json::Value>,
+    pub request_id: Option<&'a str>,
+}
@@ -0,0 +1,24 @@
+use crate::schema::login_failures;
+use chrono::offset::Utc;
+use chrono::DateTime;
+
+#[derive(Queryable)]
+pub struct LoginFailures {
+    pub kind: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub attempt_started_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_failures"]
+pub struct NewLoginFailures<'a> {
+    pub kind: &'a str,
+    pub subject: &'a str,
+    pub failures: i32,
+    pub last_failure_at: &'a DateTime<Utc>,
+    pub next_attempt_at: &'a DateTime<Utc>,
+}
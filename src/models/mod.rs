@@ -1,7 +1,17 @@
+mod api_token;
+mod audit_log;
+mod login_failures;
+mod newsletter_issue;
 mod subscription;
 mod subscription_token;
+mod suppression;
 mod user;
 
+pub use api_token::*;
+pub use audit_log::*;
+pub use login_failures::*;
+pub use newsletter_issue::*;
 pub use subscription::*;
 pub use subscription_token::*;
+pub use suppression::*;
 pub use user::*;
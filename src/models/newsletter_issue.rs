@@ -0,0 +1,58 @@
+use crate::schema::{issue_deliveries, issue_links, newsletter_issues, tracking_events};
+use chrono::offset::Utc;
+use chrono::DateTime;
+
+#[derive(Queryable)]
+pub struct NewsletterIssue {
+    pub id: uuid::Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub published_at: DateTime<Utc>,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "newsletter_issues"]
+pub struct NewNewsletterIssue<'a> {
+    pub id: &'a uuid::Uuid,
+    pub title: &'a str,
+    pub text_content: &'a str,
+    pub html_content: &'a str,
+    pub published_at: &'a DateTime<Utc>,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "issue_links"]
+pub struct NewIssueLink<'a> {
+    pub issue_id: &'a uuid::Uuid,
+    pub position: i32,
+    pub url: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "issue_deliveries"]
+pub struct NewIssueDelivery<'a> {
+    pub issue_id: &'a uuid::Uuid,
+    pub subscriber_id: &'a uuid::Uuid,
+    /// `queued`, `sent`, `failed`, `suppressed` or `bounced`.
+    pub status: &'a str,
+    pub error: Option<&'a str>,
+    pub message_id: Option<&'a str>,
+    pub updated_at: &'a DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "tracking_events"]
+pub struct NewTrackingEvent<'a> {
+    pub id: &'a uuid::Uuid,
+    pub issue_id: &'a uuid::Uuid,
+    pub subscriber_id: &'a uuid::Uuid,
+    /// `open` or `click`.
+    pub kind: &'a str,
+    pub link_position: Option<i32>,
+    pub occurred_at: &'a DateTime<Utc>,
+}
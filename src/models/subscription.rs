@@ -9,6 +9,7 @@ pub struct Subscription {
     pub name: String,
     pub subscribed_at: DateTime<Utc>,
     pub status: String,
+    pub canonical_email: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -19,4 +20,5 @@ pub struct NewSubscription<'a> {
     pub name: &'a str,
     pub subscribed_at: &'a DateTime<Utc>,
     pub status: &'a str,
+    pub canonical_email: &'a str,
 }
@@ -1,9 +1,14 @@
 use crate::schema::subscription_tokens;
+use chrono::offset::Utc;
+use chrono::DateTime;
 
 #[derive(Queryable)]
 pub struct SubscriptionToken {
     pub subscription_token: String,
     pub subscriber_id: uuid::Uuid,
+    pub created_at: DateTime<Utc>,
+    /// Tokens issued before hashing was introduced are stored in plaintext.
+    pub hashed: bool,
 }
 
 #[derive(Insertable)]
@@ -11,4 +16,6 @@ pub struct SubscriptionToken {
 pub struct NewSubscriptionToken<'a> {
     pub subscription_token: &'a str,
     pub subscriber_id: &'a uuid::Uuid,
+    pub created_at: &'a DateTime<Utc>,
+    pub hashed: bool,
 }
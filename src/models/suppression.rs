@@ -0,0 +1,45 @@
+use crate::schema::suppressions;
+use chrono::offset::Utc;
+use chrono::DateTime;
+use uuid::Uuid;
+
+#[derive(Queryable, serde::Serialize)]
+pub struct Suppression {
+    pub canonical_email: String,
+    pub reason: String,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub source: String,
+    pub id: Uuid,
+}
+
+#[derive(Insertable)]
+#[table_name = "suppressions"]
+pub struct NewSuppression<'a> {
+    pub canonical_email: &'a str,
+    pub reason: &'a str,
+    pub detail: Option<&'a str>,
+    pub created_at: &'a DateTime<Utc>,
+    pub source: &'a str,
+    pub id: Uuid,
+}
+
+/// A suppression as recorded in the audit log, which cannot be erased from:
+/// the address is left out, and so is the detail, which for bounces quotes the
+/// receiving server and often the address along with it.
+#[derive(serde::Serialize)]
+pub struct AuditedSuppression {
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+    pub source: String,
+}
+
+impl From<&Suppression> for AuditedSuppression {
+    fn from(suppression: &Suppression) -> Self {
+        Self {
+            reason: suppression.reason.clone(),
+            created_at: suppression.created_at,
+            source: suppression.source.clone(),
+        }
+    }
+}
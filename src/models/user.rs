@@ -1,4 +1,6 @@
-use crate::schema::users;
+use crate::schema::{recovery_codes, two_factor_sessions, users};
+use chrono::offset::Utc;
+use chrono::DateTime;
 use uuid::Uuid;
 
 #[derive(Queryable)]
@@ -6,6 +8,10 @@ pub struct User {
     pub user_id: Uuid,
     pub username: String,
     pub password_hash: String,
+    pub role: String,
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    pub totp_last_step: Option<i64>,
 }
 
 #[derive(Insertable)]
@@ -14,4 +20,21 @@ pub struct NewUser<'a> {
     pub user_id: &'a uuid::Uuid,
     pub username: &'a str,
     pub password_hash: &'a str,
+    pub role: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_codes"]
+pub struct NewRecoveryCode<'a> {
+    pub user_id: &'a Uuid,
+    pub code_hash: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "two_factor_sessions"]
+pub struct NewTwoFactorSession<'a> {
+    pub token_hash: &'a str,
+    pub user_id: &'a Uuid,
+    pub created_at: &'a DateTime<Utc>,
+    pub expires_at: &'a DateTime<Utc>,
 }
@@ -0,0 +1,93 @@
+use crate::configuration::BrandingSettings;
+use crate::problem::Problem;
+use rocket::http::Status;
+use rocket::response::{status, Responder};
+use rocket::serde::json::{json, Value};
+use rocket::{Request, Response};
+use rocket_dyn_templates::Template;
+
+/// Site-wide values available to every template as `branding`.
+#[derive(Clone, serde::Serialize)]
+pub struct Branding {
+    pub site_name: String,
+    pub primary_color: String,
+    pub logo_url: Option<String>,
+}
+
+impl Branding {
+    pub fn new(settings: &BrandingSettings) -> Self {
+        Self {
+            site_name: settings.site_name.clone(),
+            primary_color: settings.primary_color.clone(),
+            logo_url: settings.logo_url.clone(),
+        }
+    }
+
+    pub fn render(&self, template: &'static str, context: Value) -> Template {
+        let mut context = context;
+        if let Value::Object(map) = &mut context {
+            map.insert("branding".into(), json!(self));
+        }
+        Template::render(template, context)
+    }
+}
+
+/// Renders a template for browsers, while API clients keep getting an empty
+/// body or problem details, for errors or for pages only a browser can use.
+pub struct Page {
+    template: &'static str,
+    status: Status,
+    context: Value,
+    problem: Option<Problem>,
+}
+
+impl Page {
+    pub fn new(template: &'static str) -> Self {
+        Self {
+            template,
+            status: Status::Ok,
+            context: json!({}),
+            problem: None,
+        }
+    }
+
+    pub fn with_context(mut self, context: Value) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn with_problem(mut self, problem: Problem) -> Self {
+        self.status = problem.status();
+        self.problem = Some(problem);
+        self
+    }
+
+    /// What API clients get instead of the page, which browsers still get
+    /// with its own status.
+    pub fn with_api_problem(mut self, problem: Problem) -> Self {
+        self.problem = Some(problem);
+        self
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Page {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        if !prefers_html(request) {
+            return match self.problem {
+                Some(problem) => problem.respond_to(request),
+                None => Response::build().status(self.status).ok(),
+            };
+        }
+
+        let branding = request.rocket().state::<Branding>().ok_or_else(|| {
+            tracing::error!("Branding is not managed, cannot render pages.");
+            Status::InternalServerError
+        })?;
+        status::Custom(self.status, branding.render(self.template, self.context))
+            .respond_to(request)
+    }
+}
+
+pub fn prefers_html(request: &Request<'_>) -> bool {
+    matches!(request.accept(), Some(accept) if accept.preferred().media_type().is_html())
+}
@@ -0,0 +1,264 @@
+use crate::configuration::PasswordHashingSettings;
+use crate::metrics::{
+    ARGON2_JOBS_IN_FLIGHT, ARGON2_JOBS_REJECTED, ARGON2_QUEUE_DURATION,
+    PASSWORD_VERIFICATION_DURATION,
+};
+use anyhow::{anyhow, Context};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use std::panic::AssertUnwindSafe;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Semaphore};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PasswordError {
+    #[error("The password does not match.")]
+    Mismatch,
+    #[error("No Argon2 worker came free in time.")]
+    Busy,
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+/// Computes and verifies Argon2id password hashes on a few threads of its own.
+///
+/// Argon2 is slow on purpose, so a burst of logins must neither take over the
+/// blocking pool that database work runs on nor queue up without bound: at
+/// most `workers` hashes are computed at once, and callers that cannot get a
+/// worker within the queue timeout are turned away.
+pub struct PasswordHashing {
+    params: Params,
+    /// Verified against for unknown usernames, so that they take as long as
+    /// known ones; computed up front, as the first unknown username would
+    /// otherwise take twice as long as any other login.
+    dummy_hash: Arc<String>,
+    permits: Arc<Semaphore>,
+    queue_timeout: Duration,
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl PasswordHashing {
+    pub fn new(settings: &PasswordHashingSettings) -> Result<Self, anyhow::Error> {
+        let params = Params::new(
+            settings.memory_kib,
+            settings.iterations,
+            settings.parallelism,
+            None,
+        )
+        .map_err(|e| anyhow!("Invalid Argon2 parameters: {}", e))?;
+        anyhow::ensure!(
+            settings.workers > 0,
+            "At least one Argon2 worker is needed."
+        );
+
+        let dummy_hash = hash_with(&argon2(params.clone()), "not anybody's password")?;
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for worker in 0..settings.workers {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("argon2-{}", worker))
+                .spawn(move || {
+                    // the workers end once the sender is dropped with `self`
+                    while let Some(job) = next_job(&receiver) {
+                        // a panicking job leaves its caller with an error, not
+                        // the pool with a worker less
+                        let _ = std::panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                })
+                .context("Failed to start an Argon2 worker.")?;
+        }
+
+        Ok(Self {
+            params,
+            dummy_hash: Arc::new(dummy_hash),
+            permits: Arc::new(Semaphore::new(settings.workers)),
+            queue_timeout: Duration::from_millis(settings.queue_timeout_milliseconds),
+            jobs: Mutex::new(sender),
+        })
+    }
+
+    /// Checks `candidate` against `expected`, or against a dummy hash when
+    /// there is no expected hash, in which case it never matches.
+    #[tracing::instrument(name = "Verify password hash", skip(self, expected, candidate))]
+    pub async fn verify(
+        &self,
+        expected: Option<Secret<String>>,
+        candidate: Secret<String>,
+    ) -> Result<(), PasswordError> {
+        let known = expected.is_some();
+        let dummy_hash = self.dummy_hash.clone();
+        let argon2 = argon2(self.params.clone());
+        let matched = self
+            .run(move || {
+                let expected = match &expected {
+                    Some(expected) => expected.expose_secret(),
+                    None => dummy_hash.as_str(),
+                };
+                let expected = PasswordHash::new(expected)
+                    .map_err(|e| anyhow!("Failed to parse hash in PHC string format: {}", e))?;
+                let _timer = PASSWORD_VERIFICATION_DURATION.start_timer();
+                Ok::<_, anyhow::Error>(
+                    argon2
+                        .verify_password(candidate.expose_secret().as_bytes(), &expected)
+                        .is_ok(),
+                )
+            })
+            .await??;
+        if matched && known {
+            Ok(())
+        } else {
+            Err(PasswordError::Mismatch)
+        }
+    }
+
+    /// A new hash of `password`, with the configured parameters.
+    #[tracing::instrument(name = "Hash password", skip(self, password))]
+    pub async fn hash(&self, password: Secret<String>) -> Result<Secret<String>, PasswordError> {
+        let argon2 = argon2(self.params.clone());
+        let hash = self
+            .run(move || hash_with(&argon2, password.expose_secret()))
+            .await??;
+        Ok(Secret::new(hash))
+    }
+
+    /// Whether `hash` was computed with other parameters than the configured
+    /// ones, and so should be replaced once the password is known.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let hash = match PasswordHash::new(hash) {
+            Ok(hash) => hash,
+            Err(_) => return true,
+        };
+        let same_params = Params::try_from(&hash)
+            .map(|params| {
+                params.m_cost() == self.params.m_cost()
+                    && params.t_cost() == self.params.t_cost()
+                    && params.p_cost() == self.params.p_cost()
+            })
+            .unwrap_or(false);
+        hash.algorithm != Algorithm::Argon2id.ident() || !same_params
+    }
+
+    /// Runs `f` on a worker, once one is free.
+    async fn run<R: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> R + Send + 'static,
+    ) -> Result<R, PasswordError> {
+        let queued_at = Instant::now();
+        let permit = tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| {
+                ARGON2_JOBS_REJECTED.inc();
+                PasswordError::Busy
+            })?
+            .context("The Argon2 workers were shut down.")?;
+        ARGON2_QUEUE_DURATION.observe(queued_at.elapsed().as_secs_f64());
+
+        let (sender, receiver) = oneshot::channel();
+        let span = tracing::Span::current();
+        let job: Job = Box::new(move || {
+            ARGON2_JOBS_IN_FLIGHT.inc();
+            let result = span.in_scope(f);
+            ARGON2_JOBS_IN_FLIGHT.dec();
+            drop(permit);
+            // the caller may have given up waiting
+            let _ = sender.send(result);
+        });
+        self.jobs
+            .lock()
+            .map_err(|_| anyhow!("The Argon2 job queue is poisoned."))?
+            .send(job)
+            .map_err(|_| anyhow!("The Argon2 workers were shut down."))?;
+        Ok(receiver
+            .await
+            .context("An Argon2 job failed without a result.")?)
+    }
+}
+
+fn next_job(receiver: &Mutex<mpsc::Receiver<Job>>) -> Option<Job> {
+    receiver.lock().ok()?.recv().ok()
+}
+
+fn argon2(params: Params) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+fn hash_with(argon2: &Argon2, password: &str) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    argon2
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash a password: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashing(workers: usize, queue_timeout_milliseconds: u64) -> PasswordHashing {
+        PasswordHashing::new(&PasswordHashingSettings {
+            // cheap, so that the tests stay fast
+            memory_kib: 64,
+            iterations: 1,
+            workers,
+            queue_timeout_milliseconds,
+            ..PasswordHashingSettings::default()
+        })
+        .unwrap()
+    }
+
+    fn secret(s: &str) -> Secret<String> {
+        Secret::new(s.to_string())
+    }
+
+    #[tokio::test]
+    async fn hashes_verify_only_their_own_password() {
+        let hashing = hashing(1, 1000);
+        let hash = hashing.hash(secret("hunter2")).await.unwrap();
+        let expected = || Some(secret(hash.expose_secret()));
+
+        assert!(hashing.verify(expected(), secret("hunter2")).await.is_ok());
+        assert!(matches!(
+            hashing.verify(expected(), secret("hunter3")).await,
+            Err(PasswordError::Mismatch)
+        ));
+        assert!(matches!(
+            hashing.verify(None, secret("not anybody's password")).await,
+            Err(PasswordError::Mismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn callers_are_turned_away_when_no_worker_comes_free() {
+        let hashing = Arc::new(hashing(1, 50));
+        let busy = hashing.clone();
+        let blocker = tokio::spawn(async move {
+            busy.run(|| std::thread::sleep(Duration::from_millis(500)))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let result = hashing.hash(secret("hunter2")).await;
+
+        assert!(matches!(result, Err(PasswordError::Busy)));
+        assert!(blocker.await.unwrap().is_ok());
+        assert!(hashing.hash(secret("hunter2")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn hashes_with_other_parameters_need_rehashing() {
+        let hashing = hashing(1, 1000);
+        let current = hashing.hash(secret("hunter2")).await.unwrap();
+        let stronger =
+            hash_with(&argon2(Params::new(128, 2, 1, None).unwrap()), "hunter2").unwrap();
+
+        assert!(!hashing.needs_rehash(current.expose_secret()));
+        assert!(hashing.needs_rehash(&stronger));
+        assert!(hashing.needs_rehash("not a hash"));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::guards::RequestId;
+use rocket::http::{ContentType, Status};
+use rocket::response::Responder;
+use rocket::serde::json::serde_json;
+use rocket::{Request, Response};
+use std::io::Cursor;
+
+/// An RFC 7807 `application/problem+json` error response.
+///
+/// `code` is a stable, machine-readable identifier; `title` and `detail` are
+/// meant for humans and may change.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    status: Status,
+    code: String,
+    detail: Option<String>,
+    errors: Vec<FieldError>,
+    retry_after: Option<u64>,
+    allow: Option<&'static str>,
+}
+
+/// A problem with a single input field.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn missing(field: &'static str) -> Self {
+        Self {
+            field,
+            code: "missing".into(),
+            message: format!("The '{}' field is required.", field),
+        }
+    }
+
+    pub fn invalid(field: &'static str, message: String) -> Self {
+        Self {
+            field,
+            code: "invalid".into(),
+            message,
+        }
+    }
+}
+
+impl Problem {
+    pub fn new(status: Status, code: &str) -> Self {
+        Self {
+            status,
+            code: code.into(),
+            detail: None,
+            errors: Vec::new(),
+            retry_after: None,
+            allow: None,
+        }
+    }
+
+    /// A problem for a bare status, coded after its reason phrase, e.g. `not_found`.
+    pub fn from_status(status: Status) -> Self {
+        let code = status
+            .reason_lossy()
+            .to_lowercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        Self::new(status, &code)
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Tells the client how many seconds to wait, in a `Retry-After` header.
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    /// Lists the methods the resource does support, in an `Allow` header, as
+    /// a 405 must.
+    pub fn with_allow(mut self, methods: &'static str) -> Self {
+        self.allow = Some(methods);
+        self
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Request guards can only fail with a status, so they leave the details
+    /// behind for the catcher that ends up handling it.
+    pub fn stash(self, request: &Request<'_>) {
+        request.local_cache(|| StashedProblem(Some(self)));
+    }
+
+    pub fn stashed(request: &Request<'_>, status: Status) -> Option<Problem> {
+        request
+            .local_cache(|| StashedProblem(None))
+            .0
+            .clone()
+            .filter(|problem| problem.status == status)
+    }
+}
+
+struct StashedProblem(Option<Problem>);
+
+#[derive(serde::Serialize)]
+struct ProblemBody<'a> {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'static str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    code: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [FieldError],
+    request_id: String,
+}
+
+impl<'r> Responder<'r, 'static> for Problem {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let body = serde_json::to_string(&ProblemBody {
+            problem_type: format!("/problems/{}", self.code),
+            title: self.status.reason_lossy(),
+            status: self.status.code,
+            detail: self.detail.as_deref(),
+            code: &self.code,
+            errors: &self.errors,
+            request_id: RequestId::get(request).0,
+        })
+        .map_err(|e| {
+            tracing::error!("Failed to serialize a problem: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+        let mut response = Response::build();
+        response
+            .status(self.status)
+            .header(ContentType::new("application", "problem+json"))
+            .sized_body(body.len(), Cursor::new(body));
+        if let Some(seconds) = self.retry_after {
+            response.raw_header("Retry-After", seconds.to_string());
+        }
+        if let Some(methods) = self.allow {
+            response.raw_header("Allow", methods);
+        }
+        response.ok()
+    }
+}
@@ -0,0 +1,226 @@
+use crate::audit::{Actor, AuditEntry};
+use crate::domain::permissions::ManageApiTokens;
+use crate::domain::{GeneratedApiToken, Scope};
+use crate::guards::{Authorized, RequestId};
+use crate::models::{ApiToken, NewApiToken};
+use crate::problem::{FieldError, Problem};
+use crate::startup::NewsletterDbConn;
+use chrono::{DateTime, Duration, Utc};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use uuid::Uuid;
+
+const DEFAULT_LIFETIME_DAYS: i64 = 90;
+const MAX_LIFETIME_DAYS: i64 = 365;
+
+#[derive(serde::Deserialize)]
+pub struct ApiTokenRequest {
+    name: String,
+    scopes: Vec<String>,
+    expires_in_days: Option<i64>,
+}
+
+/// A token as listed; the token itself is only ever returned on creation.
+#[derive(serde::Serialize)]
+pub struct ApiTokenSummary {
+    id: String,
+    name: String,
+    prefix: String,
+    scopes: Vec<String>,
+    created_by: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiToken> for ApiTokenSummary {
+    fn from(token: ApiToken) -> Self {
+        Self {
+            id: token.id.to_string(),
+            name: token.name,
+            prefix: token.token_prefix,
+            scopes: token.scopes,
+            created_by: token.created_by.to_string(),
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+            revoked_at: token.revoked_at,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct CreatedApiToken {
+    /// Shown once: only its hash is stored.
+    token: String,
+    #[serde(flatten)]
+    summary: ApiTokenSummary,
+}
+
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(body, conn, caller),
+    fields(caller = %caller)
+)]
+#[post("/admin/api-tokens", data = "<body>")]
+pub async fn create_api_token(
+    body: Json<ApiTokenRequest>,
+    caller: Authorized<ManageApiTokens>,
+    conn: NewsletterDbConn,
+    request_id: RequestId,
+) -> Result<(Status, Json<CreatedApiToken>), Problem> {
+    // tokens are never granted this permission, so this is always a user
+    let created_by = caller
+        .caller
+        .user()
+        .map(|user| user.user_id)
+        .ok_or_else(|| Problem::from_status(Status::Forbidden))?;
+    let body = body.into_inner();
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return Err(invalid(FieldError::missing("name")));
+    }
+    if body.scopes.is_empty() {
+        return Err(invalid(FieldError::missing("scopes")));
+    }
+    let mut scopes = Vec::new();
+    for scope in &body.scopes {
+        let scope = Scope::parse(scope).ok_or_else(|| {
+            let known: Vec<_> = Scope::ALL.iter().map(Scope::as_str).collect();
+            invalid(FieldError::invalid(
+                "scopes",
+                format!("Must each be one of {}.", known.join(", ")),
+            ))
+        })?;
+        if !scopes.contains(&scope.as_str().to_string()) {
+            scopes.push(scope.as_str().to_string());
+        }
+    }
+    let lifetime_days = body.expires_in_days.unwrap_or(DEFAULT_LIFETIME_DAYS);
+    if !(1..=MAX_LIFETIME_DAYS).contains(&lifetime_days) {
+        return Err(invalid(FieldError::invalid(
+            "expires_in_days",
+            format!("Must be between 1 and {} days.", MAX_LIFETIME_DAYS),
+        )));
+    }
+
+    let generated = GeneratedApiToken::generate();
+    let (id, created_at) = (Uuid::new_v4(), Utc::now());
+    let expires_at = created_at + Duration::days(lifetime_days);
+    let (hash, prefix) = (generated.hash, generated.prefix);
+    let audit = AuditEntry::new(
+        Actor::from(&caller.caller),
+        "api_token.created",
+        &request_id,
+    )
+    .with_target(id);
+    let summary = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::api_tokens;
+            c.transaction(|| {
+                let token = diesel::insert_into(api_tokens::table)
+                    .values(NewApiToken {
+                        id: &id,
+                        name: &name,
+                        token_hash: &hash,
+                        token_prefix: &prefix,
+                        scopes: &scopes,
+                        created_by: &created_by,
+                        created_at: &created_at,
+                        expires_at: &expires_at,
+                    })
+                    .get_result::<ApiToken>(c)?;
+                let summary = ApiTokenSummary::from(token);
+                audit.with_after(&summary).record(c)?;
+                Ok(summary)
+            })
+        })
+        .await
+        .map_err(internal_error)?;
+    Ok((
+        Status::Created,
+        Json(CreatedApiToken {
+            token: generated.token,
+            summary,
+        }),
+    ))
+}
+
+#[tracing::instrument(name = "List API tokens", skip(conn, _caller))]
+#[get("/admin/api-tokens")]
+pub async fn list_api_tokens(
+    _caller: Authorized<ManageApiTokens>,
+    conn: NewsletterDbConn,
+) -> Result<Json<Vec<ApiTokenSummary>>, Problem> {
+    conn.run(|c: &mut PgConnection| {
+        use crate::schema::api_tokens;
+        api_tokens::table
+            .order((api_tokens::created_at.desc(), api_tokens::id))
+            .load::<ApiToken>(c)
+    })
+    .await
+    .map(|tokens| Json(tokens.into_iter().map(ApiTokenSummary::from).collect()))
+    .map_err(internal_error)
+}
+
+#[tracing::instrument(
+    name = "Revoke an API token",
+    skip(conn, caller),
+    fields(caller = %caller)
+)]
+#[delete("/admin/api-tokens/<id>")]
+pub async fn revoke_api_token(
+    id: &str,
+    caller: Authorized<ManageApiTokens>,
+    conn: NewsletterDbConn,
+    request_id: RequestId,
+) -> Result<Status, Problem> {
+    let id = Uuid::parse_str(id).map_err(|_| Problem::from_status(Status::NotFound))?;
+    let audit = AuditEntry::new(
+        Actor::from(&caller.caller),
+        "api_token.revoked",
+        &request_id,
+    )
+    .with_target(id);
+    let revoked = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::api_tokens;
+            c.transaction(|| {
+                let revoked = diesel::update(
+                    api_tokens::table
+                        .find(id)
+                        .filter(api_tokens::revoked_at.is_null()),
+                )
+                .set(api_tokens::revoked_at.eq(Utc::now()))
+                .get_result::<ApiToken>(c)
+                .optional()?;
+                if let Some(token) = revoked {
+                    audit.with_after(ApiTokenSummary::from(token)).record(c)?;
+                    return Ok(true);
+                }
+                Ok(false)
+            })
+        })
+        .await
+        .map_err(internal_error)?;
+    if !revoked {
+        return Err(Problem::new(Status::NotFound, "unknown_api_token")
+            .with_detail("No such API token, or it was already revoked."));
+    }
+    Ok(Status::NoContent)
+}
+
+fn invalid(error: FieldError) -> Problem {
+    Problem::new(Status::BadRequest, "invalid_api_token")
+        .with_detail("The API token request was rejected.")
+        .with_errors(vec![error])
+}
+
+fn internal_error(e: diesel::result::Error) -> Problem {
+    tracing::error!(error.cause_chain = ?e, "Failed to access the API tokens.");
+    Problem::new(Status::InternalServerError, "internal_error")
+}
@@ -0,0 +1,148 @@
+use crate::domain::permissions::ViewAuditLog;
+use crate::guards::Authorized;
+use crate::models::AuditLogEntry;
+use crate::problem::{FieldError, Problem};
+use crate::startup::NewsletterDbConn;
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Serialize)]
+pub struct AuditLogRow {
+    id: i64,
+    occurred_at: DateTime<Utc>,
+    actor_type: String,
+    actor_id: Option<String>,
+    actor_name: String,
+    action: String,
+    target_type: String,
+    target_id: Option<String>,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+    request_id: Option<String>,
+}
+
+impl From<AuditLogEntry> for AuditLogRow {
+    fn from(entry: AuditLogEntry) -> Self {
+        Self {
+            id: entry.id,
+            occurred_at: entry.occurred_at,
+            actor_type: entry.actor_type,
+            actor_id: entry.actor_id.map(|id| id.to_string()),
+            actor_name: entry.actor_name,
+            action: entry.action,
+            target_type: entry.target_type,
+            target_id: entry.target_id,
+            before: entry.before,
+            after: entry.after,
+            request_id: entry.request_id,
+        }
+    }
+}
+
+/// Filters for the audit log; all of them are optional and must all match.
+#[derive(FromForm)]
+pub struct AuditFilter {
+    /// A username or API token name.
+    actor: Option<String>,
+    action: Option<String>,
+    target_type: Option<String>,
+    target_id: Option<String>,
+    request_id: Option<String>,
+    /// RFC 3339 timestamps; `since` is inclusive, `until` exclusive.
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Privileged actions, newest first.
+#[tracing::instrument(name = "List the audit log", skip(filter, conn, caller), fields(caller = %caller))]
+#[get("/admin/audit?<filter..>")]
+pub async fn list_audit_log(
+    filter: AuditFilter,
+    caller: Authorized<ViewAuditLog>,
+    conn: NewsletterDbConn,
+) -> Result<Json<Vec<AuditLogRow>>, Problem> {
+    let limit = filter.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(invalid(FieldError::invalid(
+            "limit",
+            format!("Must be between 1 and {}.", MAX_PAGE_SIZE),
+        )));
+    }
+    let offset = filter.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(invalid(FieldError::invalid(
+            "offset",
+            "Must not be negative.".into(),
+        )));
+    }
+    let since = parse_timestamp("since", filter.since.as_deref())?;
+    let until = parse_timestamp("until", filter.until.as_deref())?;
+
+    conn.run(move |c: &mut PgConnection| {
+        use crate::schema::audit_log;
+        let mut query = audit_log::table
+            .order((audit_log::occurred_at.desc(), audit_log::id.desc()))
+            .limit(limit)
+            .offset(offset)
+            .into_boxed();
+        if let Some(actor) = filter.actor {
+            query = query.filter(audit_log::actor_name.eq(actor));
+        }
+        if let Some(action) = filter.action {
+            query = query.filter(audit_log::action.eq(action));
+        }
+        if let Some(target_type) = filter.target_type {
+            query = query.filter(audit_log::target_type.eq(target_type));
+        }
+        if let Some(target_id) = filter.target_id {
+            query = query.filter(audit_log::target_id.eq(target_id));
+        }
+        if let Some(request_id) = filter.request_id {
+            query = query.filter(audit_log::request_id.eq(request_id));
+        }
+        if let Some(since) = since {
+            query = query.filter(audit_log::occurred_at.ge(since));
+        }
+        if let Some(until) = until {
+            query = query.filter(audit_log::occurred_at.lt(until));
+        }
+        query.load::<AuditLogEntry>(c)
+    })
+    .await
+    .map(|entries| Json(entries.into_iter().map(AuditLogRow::from).collect()))
+    .map_err(|e| {
+        tracing::error!(error.cause_chain = ?e, "Failed to read the audit log.");
+        Problem::new(Status::InternalServerError, "internal_error")
+    })
+}
+
+fn parse_timestamp(
+    field: &'static str,
+    value: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, Problem> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .map_err(|_| {
+                    invalid(FieldError::invalid(
+                        field,
+                        "Must be an RFC 3339 timestamp.".into(),
+                    ))
+                })
+        })
+        .transpose()
+}
+
+fn invalid(error: FieldError) -> Problem {
+    Problem::new(Status::BadRequest, "invalid_audit_filter")
+        .with_detail("The audit log filter was rejected.")
+        .with_errors(vec![error])
+}
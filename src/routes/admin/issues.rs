@@ -0,0 +1,323 @@
+use crate::domain::permissions::{ReadSubscribers, ViewStats};
+use crate::guards::Authorized;
+use crate::models::NewsletterIssue;
+use crate::problem::{FieldError, Problem};
+use crate::startup::NewsletterDbConn;
+use chrono::{DateTime, Utc};
+use diesel::dsl::sql;
+use diesel::query_dsl::GroupByDsl;
+use diesel::sql_types::BigInt;
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use rocket::http::{Header, Status};
+use rocket::serde::json::Json;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+const DELIVERY_STATUSES: &[&str] = &["queued", "sent", "failed", "suppressed", "bounced"];
+
+#[derive(serde::Serialize)]
+pub struct IssueStats {
+    issue_id: String,
+    title: String,
+    track_opens: bool,
+    track_clicks: bool,
+    unique_opens: usize,
+    total_opens: usize,
+    links: Vec<LinkStats>,
+}
+
+#[derive(serde::Serialize)]
+pub struct LinkStats {
+    url: String,
+    unique_clicks: usize,
+    total_clicks: usize,
+}
+
+/// Opens and clicks of an issue; "unique" counts subscribers rather than events.
+#[tracing::instrument(name = "Get issue stats", skip(conn, _caller))]
+#[get("/admin/issues/<id>/stats")]
+pub async fn issue_stats(
+    id: &str,
+    _caller: Authorized<ViewStats>,
+    conn: NewsletterDbConn,
+) -> Result<Json<IssueStats>, Problem> {
+    let id = Uuid::parse_str(id).map_err(|_| Problem::from_status(Status::NotFound))?;
+    conn.run(move |c: &mut PgConnection| load_stats(c, id))
+        .await
+        .map_err(|e| {
+            tracing::error!(error.cause_chain = ?e, "Failed to load issue stats.");
+            Problem::new(Status::InternalServerError, "internal_error")
+        })?
+        .map(Json)
+        .ok_or_else(|| Problem::from_status(Status::NotFound))
+}
+
+fn load_stats(conn: &PgConnection, id: Uuid) -> Result<Option<IssueStats>, diesel::result::Error> {
+    use crate::schema::{issue_links, newsletter_issues, tracking_events};
+    let issue = match newsletter_issues::table
+        .find(id)
+        .first::<NewsletterIssue>(conn)
+        .optional()?
+    {
+        Some(issue) => issue,
+        None => return Ok(None),
+    };
+    let links = issue_links::table
+        .select((issue_links::position, issue_links::url))
+        .filter(issue_links::issue_id.eq(id))
+        .order(issue_links::position)
+        .load::<(i32, String)>(conn)?;
+    // per kind and link: events, and the distinct subscribers behind them
+    let counts = tracking_events::table
+        .filter(tracking_events::issue_id.eq(id))
+        .group_by((tracking_events::kind, tracking_events::link_position))
+        .select((
+            tracking_events::kind,
+            tracking_events::link_position,
+            sql::<BigInt>("COUNT(*)"),
+            sql::<BigInt>("COUNT(DISTINCT subscriber_id)"),
+        ))
+        .load::<(String, Option<i32>, i64, i64)>(conn)?;
+
+    let (mut unique_opens, mut total_opens) = (0, 0);
+    let mut clicks: HashMap<i32, (usize, usize)> = HashMap::new();
+    for (kind, link_position, total, unique) in counts {
+        let (total, unique) = (total as usize, unique as usize);
+        match (kind.as_str(), link_position) {
+            ("open", _) => {
+                unique_opens += unique;
+                total_opens += total;
+            }
+            ("click", Some(position)) => {
+                clicks.insert(position, (unique, total));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Some(IssueStats {
+        issue_id: issue.id.to_string(),
+        title: issue.title,
+        track_opens: issue.track_opens,
+        track_clicks: issue.track_clicks,
+        unique_opens,
+        total_opens,
+        links: links
+            .into_iter()
+            .map(|(position, url)| {
+                let (unique_clicks, total_clicks) =
+                    clicks.get(&position).copied().unwrap_or_default();
+                LinkStats {
+                    url,
+                    unique_clicks,
+                    total_clicks,
+                }
+            })
+            .collect(),
+    }))
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueDeliveries {
+    issue_id: String,
+    /// By status, over all of the issue's recipients.
+    counts: BTreeMap<String, usize>,
+    deliveries: Vec<DeliveryRow>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryRow {
+    subscriber_id: String,
+    email: String,
+    status: String,
+    error: Option<String>,
+    message_id: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Responder)]
+pub enum DeliveryReport {
+    Json(Json<IssueDeliveries>),
+    Csv(CsvExport),
+}
+
+#[derive(Responder)]
+#[response(content_type = "text/csv")]
+pub struct CsvExport {
+    body: String,
+    disposition: Header<'static>,
+}
+
+/// Who an issue was sent to and how that went, as JSON or, with
+/// `format=csv`, as a CSV download.
+#[tracing::instrument(name = "Get issue deliveries", skip(conn, caller), fields(caller = %caller))]
+#[get("/admin/issues/<id>/deliveries?<status>&<format>")]
+pub async fn issue_deliveries(
+    id: &str,
+    status: Option<String>,
+    format: Option<String>,
+    caller: Authorized<ReadSubscribers>,
+    conn: NewsletterDbConn,
+) -> Result<DeliveryReport, Problem> {
+    let id = Uuid::parse_str(id).map_err(|_| Problem::from_status(Status::NotFound))?;
+    if let Some(status) = &status {
+        if !DELIVERY_STATUSES.contains(&status.as_str()) {
+            return Err(invalid_filter(FieldError::invalid(
+                "status",
+                format!("Must be one of {}.", DELIVERY_STATUSES.join(", ")),
+            )));
+        }
+    }
+    let csv = match format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(_) => {
+            return Err(invalid_filter(FieldError::invalid(
+                "format",
+                "Must be json or csv.".into(),
+            )))
+        }
+    };
+
+    let report = conn
+        .run(move |c: &mut PgConnection| load_deliveries(c, id, status))
+        .await
+        .map_err(|e| {
+            tracing::error!(error.cause_chain = ?e, "Failed to load issue deliveries.");
+            Problem::new(Status::InternalServerError, "internal_error")
+        })?
+        .ok_or_else(|| Problem::from_status(Status::NotFound))?;
+    if !csv {
+        return Ok(DeliveryReport::Json(Json(report)));
+    }
+
+    let mut body = String::from("subscriber_id,email,status,error,message_id,updated_at\r\n");
+    for row in &report.deliveries {
+        let fields = [
+            row.subscriber_id.as_str(),
+            row.email.as_str(),
+            row.status.as_str(),
+            row.error.as_deref().unwrap_or_default(),
+            row.message_id.as_deref().unwrap_or_default(),
+            &row.updated_at.to_rfc3339(),
+        ];
+        let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+        body.push_str(&fields.join(","));
+        body.push_str("\r\n");
+    }
+    Ok(DeliveryReport::Csv(CsvExport {
+        body,
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"issue-{}-deliveries.csv\"", id),
+        ),
+    }))
+}
+
+fn load_deliveries(
+    conn: &PgConnection,
+    id: Uuid,
+    status: Option<String>,
+) -> Result<Option<IssueDeliveries>, diesel::result::Error> {
+    use crate::schema::{issue_deliveries, newsletter_issues, subscriptions};
+    let exists = newsletter_issues::table
+        .find(id)
+        .select(newsletter_issues::id)
+        .first::<Uuid>(conn)
+        .optional()?
+        .is_some();
+    if !exists {
+        return Ok(None);
+    }
+
+    let counts = issue_deliveries::table
+        .filter(issue_deliveries::issue_id.eq(id))
+        .group_by(issue_deliveries::status)
+        .select((issue_deliveries::status, sql::<BigInt>("COUNT(*)")))
+        .load::<(String, i64)>(conn)?
+        .into_iter()
+        .map(|(status, count)| (status, count as usize))
+        .collect();
+    let mut query = issue_deliveries::table
+        .inner_join(subscriptions::table)
+        .select((
+            issue_deliveries::subscriber_id,
+            subscriptions::email,
+            issue_deliveries::status,
+            issue_deliveries::error,
+            issue_deliveries::message_id,
+            issue_deliveries::updated_at,
+        ))
+        .filter(issue_deliveries::issue_id.eq(id))
+        .order(subscriptions::email)
+        .into_boxed();
+    if let Some(status) = status {
+        query = query.filter(issue_deliveries::status.eq(status));
+    }
+    let deliveries = query
+        .load::<(
+            Uuid,
+            String,
+            String,
+            Option<String>,
+            Option<String>,
+            DateTime<Utc>,
+        )>(conn)?
+        .into_iter()
+        .map(
+            |(subscriber_id, email, status, error, message_id, updated_at)| DeliveryRow {
+                subscriber_id: subscriber_id.to_string(),
+                email,
+                status,
+                error,
+                message_id,
+                updated_at,
+            },
+        )
+        .collect();
+
+    Ok(Some(IssueDeliveries {
+        issue_id: id.to_string(),
+        counts,
+        deliveries,
+    }))
+}
+
+/// Quotes a CSV field where needed, and defuses values that spreadsheets
+/// would otherwise run as formulas; subscriber addresses are user input.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(&['=', '+', '-', '@', '\t', '\r'][..]) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn invalid_filter(error: FieldError) -> Problem {
+    Problem::new(Status::BadRequest, "invalid_delivery_filter")
+        .with_detail("The delivery filter was rejected.")
+        .with_errors(vec![error])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("sent"), "sent");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\"\n"), "\"say \"\"hi\"\"\n\"");
+    }
+
+    #[test]
+    fn formulas_are_defused() {
+        assert_eq!(csv_field("=1+2@example.com"), "'=1+2@example.com");
+        assert_eq!(csv_field("-sum(a1)"), "'-sum(a1)");
+    }
+}
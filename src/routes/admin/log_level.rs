@@ -0,0 +1,125 @@
+use crate::audit::{Actor, AuditEntry};
+use crate::domain::permissions::ManageSettings;
+use crate::guards::{Authorized, RequestId};
+use crate::log_filter::{LogFilter, LogFilterError, LogFilterStatus, Reverted};
+use crate::problem::{FieldError, Problem};
+use crate::startup::{NewsletterDbConn, NewsletterDbPool};
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::time::Duration;
+
+/// The longest a temporary log level may stay in effect.
+const MAX_TTL_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(serde::Deserialize)]
+pub struct LogLevelUpdate {
+    /// In `RUST_LOG` syntax, e.g. `info,zero2prod=debug`.
+    directives: String,
+    ttl_seconds: Option<u64>,
+}
+
+#[tracing::instrument(name = "Get the log level", skip(_caller))]
+#[get("/admin/log-level")]
+pub async fn get_log_level(
+    _caller: Authorized<ManageSettings>,
+) -> Result<Json<LogFilterStatus>, Problem> {
+    Ok(Json(log_filter()?.status()))
+}
+
+#[tracing::instrument(
+    name = "Change the log level",
+    skip(body, conn, db, caller),
+    fields(caller = %caller)
+)]
+#[put("/admin/log-level", data = "<body>")]
+pub async fn put_log_level(
+    body: Json<LogLevelUpdate>,
+    caller: Authorized<ManageSettings>,
+    conn: NewsletterDbConn,
+    db: &State<NewsletterDbPool>,
+    request_id: RequestId,
+) -> Result<Json<LogFilterStatus>, Problem> {
+    let log_filter = log_filter()?;
+    let ttl = match body.ttl_seconds {
+        Some(seconds) if (1..=MAX_TTL_SECONDS).contains(&seconds) => {
+            Some(Duration::from_secs(seconds))
+        }
+        Some(_) => {
+            return Err(invalid(FieldError::invalid(
+                "ttl_seconds",
+                format!("Must be between 1 and {} seconds.", MAX_TTL_SECONDS),
+            )))
+        }
+        None => None,
+    };
+
+    let previous = log_filter.status().directives;
+    let (status, revert) = log_filter.set(&body.directives, ttl).map_err(|e| match e {
+        LogFilterError::Empty | LogFilterError::Invalid(_) => {
+            invalid(FieldError::invalid("directives", e.to_string()))
+        }
+        LogFilterError::Reload(_) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to change the log level.");
+            Problem::new(Status::InternalServerError, "internal_error")
+        }
+    })?;
+    // the filter is not in the database, so this cannot share a transaction
+    // with the change; the entry is still logged if storing it fails
+    let audit = AuditEntry::new(
+        Actor::from(&caller.caller),
+        "log_level.changed",
+        &request_id,
+    )
+    .with_before(serde_json::json!({ "directives": previous }))
+    .with_after(serde_json::json!({
+        "directives": status.directives,
+        "ttl_seconds": body.ttl_seconds,
+    }));
+    conn.run(move |c: &mut PgConnection| audit.record(c))
+        .await
+        .map_err(|e| {
+            tracing::error!(error.cause_chain = ?e, "Failed to record a log level change.");
+            Problem::new(Status::InternalServerError, "internal_error")
+        })?;
+    if let Some(revert) = revert {
+        let db = db.inner().clone();
+        tokio::spawn(async move {
+            if let Some(reverted) = revert.wait().await {
+                record_revert(db, reverted, request_id).await;
+            }
+        });
+    }
+    Ok(Json(status))
+}
+
+/// Records that a temporary change was undone, under the request that made it.
+async fn record_revert(db: NewsletterDbPool, reverted: Reverted, request_id: RequestId) {
+    let audit = AuditEntry::new(Actor::System, "log_level.reverted", &request_id)
+        .with_before(serde_json::json!({ "directives": reverted.previous }))
+        .with_after(serde_json::json!({ "directives": reverted.directives }));
+    let recorded = match db.get().await {
+        Ok(conn) => conn
+            .run(move |c: &mut PgConnection| audit.record(c))
+            .await
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = recorded {
+        tracing::error!(error.cause_chain = ?e, "Failed to record a log level revert.");
+    }
+}
+
+fn log_filter() -> Result<&'static LogFilter, Problem> {
+    LogFilter::global().ok_or_else(|| {
+        Problem::new(Status::ServiceUnavailable, "log_level_unavailable")
+            .with_detail("Logging was not set up with a reloadable filter.")
+    })
+}
+
+fn invalid(error: FieldError) -> Problem {
+    Problem::new(Status::BadRequest, "invalid_log_level")
+        .with_detail("The log level change was rejected.")
+        .with_errors(vec![error])
+}
@@ -0,0 +1,122 @@
+use crate::audit::{Actor, AuditEntry};
+use crate::domain::permissions::ManageUsers;
+use crate::guards::{Authorized, RequestId};
+use crate::login_throttle::{LoginThrottle, ThrottleKind};
+use crate::models::LoginFailures;
+use crate::problem::{FieldError, Problem};
+use crate::startup::NewsletterDbConn;
+use chrono::{DateTime, Utc};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+
+#[derive(serde::Serialize)]
+pub struct ThrottledLogin {
+    kind: String,
+    subject: String,
+    failures: i32,
+    last_failure_at: DateTime<Utc>,
+    /// Only set while the lockout lasts.
+    locked_until: Option<DateTime<Utc>>,
+}
+
+/// Addresses are unlocked through the body rather than the path, which ends up
+/// in logs.
+#[derive(serde::Deserialize)]
+pub struct Unlock {
+    kind: String,
+    subject: String,
+}
+
+/// Usernames and addresses with recent failed logins, locked out ones first.
+#[tracing::instrument(name = "List throttled logins", skip(conn, throttle, _caller))]
+#[get("/admin/login-throttles")]
+pub async fn list_login_throttles(
+    _caller: Authorized<ManageUsers>,
+    conn: NewsletterDbConn,
+    throttle: &State<LoginThrottle>,
+) -> Result<Json<Vec<ThrottledLogin>>, Problem> {
+    let failures = conn
+        .run(|c: &mut PgConnection| {
+            use crate::schema::login_failures;
+            login_failures::table
+                .order(login_failures::last_failure_at.desc())
+                .load::<LoginFailures>(c)
+        })
+        .await
+        .map_err(internal_error)?;
+    let now = Utc::now();
+    let mut throttled: Vec<_> = failures
+        .into_iter()
+        .filter(|failures| throttle.is_current(failures, now))
+        .map(|failures| ThrottledLogin {
+            kind: failures.kind,
+            subject: failures.subject,
+            failures: failures.failures,
+            last_failure_at: failures.last_failure_at,
+            locked_until: failures.locked_until.filter(|until| *until > now),
+        })
+        .collect();
+    throttled.sort_by_key(|login| login.locked_until.is_none());
+    Ok(Json(throttled))
+}
+
+#[tracing::instrument(
+    name = "Unlock logins",
+    skip(body, conn, caller),
+    fields(caller = %caller)
+)]
+#[delete("/admin/login-throttles", data = "<body>")]
+pub async fn unlock_logins(
+    body: Json<Unlock>,
+    caller: Authorized<ManageUsers>,
+    conn: NewsletterDbConn,
+    request_id: RequestId,
+) -> Result<Status, Problem> {
+    let Unlock { kind, subject } = body.into_inner();
+    let kind = ThrottleKind::parse(&kind).ok_or_else(|| {
+        Problem::new(Status::BadRequest, "invalid_unlock")
+            .with_detail("The unlock was rejected.")
+            .with_errors(vec![FieldError::invalid(
+                "kind",
+                "Must be username or ip.".into(),
+            )])
+    })?;
+
+    let audit = AuditEntry::new(Actor::from(&caller.caller), "login.unlocked", &request_id)
+        .with_target_type(kind.as_str())
+        .with_target(&subject);
+    let removed = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::login_failures;
+            c.transaction(|| {
+                let removed = diesel::delete(login_failures::table.find((kind.as_str(), subject)))
+                    .get_result::<LoginFailures>(c)
+                    .optional()?;
+                if let Some(removed) = &removed {
+                    audit
+                        .with_before(serde_json::json!({
+                            "failures": removed.failures,
+                            "locked_until": removed.locked_until,
+                        }))
+                        .record(c)?;
+                }
+                Ok(removed.is_some())
+            })
+        })
+        .await
+        .map_err(internal_error)?;
+    if !removed {
+        return Err(Problem::new(Status::NotFound, "not_throttled")
+            .with_detail("There are no failed logins on record for it."));
+    }
+    Ok(Status::NoContent)
+}
+
+fn internal_error(e: diesel::result::Error) -> Problem {
+    tracing::error!(error.cause_chain = ?e, "Failed to access the failed logins.");
+    Problem::new(Status::InternalServerError, "internal_error")
+}
@@ -0,0 +1,17 @@
+mod api_tokens;
+mod audit;
+mod issues;
+mod log_level;
+mod login_throttles;
+mod suppressions;
+mod two_factor;
+mod users;
+
+pub use api_tokens::*;
+pub use audit::*;
+pub use issues::*;
+pub use log_level::*;
+pub use login_throttles::*;
+pub use suppressions::*;
+pub use two_factor::*;
+pub use users::*;
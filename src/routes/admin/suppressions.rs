@@ -0,0 +1,204 @@
+use crate::audit::{Actor, AuditEntry};
+use crate::domain::permissions::{ManageSubscribers, ReadSubscribers};
+use crate::domain::{EmailPolicy, SubscriberEmail};
+use crate::guards::{Authorized, RequestId};
+use crate::models::{AuditedSuppression, NewSuppression, Suppression};
+use crate::problem::{FieldError, Problem};
+use crate::startup::NewsletterDbConn;
+use chrono::Utc;
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use uuid::Uuid;
+
+/// Reasons an address can be suppressed for; `bounce` and `complaint` are also
+/// recorded by the SES webhook.
+const REASONS: &[&str] = &["bounce", "complaint", "forgotten", "manual"];
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionRequest {
+    email: String,
+    reason: Option<String>,
+    detail: Option<String>,
+}
+
+/// Addresses are removed through the body rather than the path, which ends up
+/// in logs.
+#[derive(serde::Deserialize)]
+pub struct SuppressionRemoval {
+    email: String,
+}
+
+#[tracing::instrument(name = "List suppressions", skip(conn, caller), fields(caller = %caller))]
+#[get("/admin/suppressions?<reason>&<limit>&<offset>")]
+pub async fn list_suppressions(
+    reason: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    caller: Authorized<ReadSubscribers>,
+    conn: NewsletterDbConn,
+) -> Result<Json<Vec<Suppression>>, Problem> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(invalid(FieldError::invalid(
+            "limit",
+            format!("Must be between 1 and {}.", MAX_PAGE_SIZE),
+        )));
+    }
+    let offset = offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(invalid(FieldError::invalid(
+            "offset",
+            "Must not be negative.".into(),
+        )));
+    }
+
+    conn.run(move |c: &mut PgConnection| {
+        use crate::schema::suppressions;
+        let mut query = suppressions::table
+            .order((
+                suppressions::created_at.desc(),
+                suppressions::canonical_email,
+            ))
+            .limit(limit)
+            .offset(offset)
+            .into_boxed();
+        if let Some(reason) = reason {
+            query = query.filter(suppressions::reason.eq(reason));
+        }
+        query.load::<Suppression>(c)
+    })
+    .await
+    .map(Json)
+    .map_err(internal_error)
+}
+
+#[tracing::instrument(
+    name = "Add a suppression",
+    skip(body, conn, email_policy, caller),
+    fields(caller = %caller)
+)]
+#[post("/admin/suppressions", data = "<body>")]
+pub async fn add_suppression(
+    body: Json<SuppressionRequest>,
+    caller: Authorized<ManageSubscribers>,
+    conn: NewsletterDbConn,
+    email_policy: &State<EmailPolicy>,
+    request_id: RequestId,
+) -> Result<(Status, Json<Suppression>), Problem> {
+    let body = body.into_inner();
+    let email = SubscriberEmail::parse(body.email)
+        .map_err(|_| invalid(FieldError::invalid("email", "Not a valid email.".into())))?;
+    let reason = body.reason.unwrap_or_else(|| "manual".into());
+    if !REASONS.contains(&reason.as_str()) {
+        return Err(invalid(FieldError::invalid(
+            "reason",
+            format!("Must be one of {}.", REASONS.join(", ")),
+        )));
+    }
+    let canonical_email = email_policy.canonicalize(&email);
+    let audit = AuditEntry::new(
+        Actor::from(&caller.caller),
+        "suppression.added",
+        &request_id,
+    );
+
+    let suppression = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::suppressions;
+            c.transaction(|| {
+                let suppression = diesel::insert_into(suppressions::table)
+                    .values(NewSuppression {
+                        canonical_email: &canonical_email,
+                        reason: &reason,
+                        detail: body.detail.as_deref(),
+                        created_at: &Utc::now(),
+                        source: "admin",
+                        id: Uuid::new_v4(),
+                    })
+                    .on_conflict_do_nothing()
+                    .get_result::<Suppression>(c)
+                    .optional()?;
+                if let Some(suppression) = &suppression {
+                    audit
+                        .with_target(suppression.id)
+                        .with_after(AuditedSuppression::from(suppression))
+                        .record(c)?;
+                }
+                Ok(suppression)
+            })
+        })
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| {
+            Problem::new(Status::Conflict, "already_suppressed")
+                .with_detail("The address is already on the suppression list.")
+        })?;
+    Ok((Status::Created, Json(suppression)))
+}
+
+#[tracing::instrument(
+    name = "Remove a suppression",
+    skip(body, conn, email_policy, caller),
+    fields(caller = %caller)
+)]
+#[delete("/admin/suppressions", data = "<body>")]
+pub async fn remove_suppression(
+    body: Json<SuppressionRemoval>,
+    caller: Authorized<ManageSubscribers>,
+    conn: NewsletterDbConn,
+    email_policy: &State<EmailPolicy>,
+    request_id: RequestId,
+) -> Result<Status, Problem> {
+    let email = SubscriberEmail::parse(body.into_inner().email)
+        .map_err(|_| invalid(FieldError::invalid("email", "Not a valid email.".into())))?;
+    let canonical_email = email_policy.canonicalize(&email);
+    let audit = AuditEntry::new(
+        Actor::from(&caller.caller),
+        "suppression.removed",
+        &request_id,
+    );
+
+    let removed = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::suppressions;
+            c.transaction(|| {
+                let removed = diesel::delete(
+                    suppressions::table.filter(suppressions::canonical_email.eq(canonical_email)),
+                )
+                .get_result::<Suppression>(c)
+                .optional()?;
+                if let Some(suppression) = &removed {
+                    audit
+                        .with_target(suppression.id)
+                        .with_before(AuditedSuppression::from(suppression))
+                        .record(c)?;
+                }
+                Ok(removed.is_some())
+            })
+        })
+        .await
+        .map_err(internal_error)?;
+    if !removed {
+        return Err(Problem::new(Status::NotFound, "not_suppressed")
+            .with_detail("The address is not on the suppression list."));
+    }
+    Ok(Status::NoContent)
+}
+
+fn invalid(error: FieldError) -> Problem {
+    Problem::new(Status::BadRequest, "invalid_suppression")
+        .with_detail("The suppression was rejected.")
+        .with_errors(vec![error])
+}
+
+fn internal_error(e: diesel::result::Error) -> Problem {
+    tracing::error!(error.cause_chain = ?e, "Failed to access the suppression list.");
+    Problem::new(Status::InternalServerError, "internal_error")
+}
@@ -0,0 +1,296 @@
+use crate::audit::{Actor, AuditEntry};
+use crate::clock::Clock;
+use crate::domain::{
+    generate_recovery_codes, generate_session_token, hash_recovery_code, hash_session_token,
+    TotpSecret, TotpSecretKey,
+};
+use crate::guards::{AuthenticatedUser, RequestId, SecondFactor};
+use crate::models::{NewRecoveryCode, NewTwoFactorSession};
+use crate::pages::Branding;
+use crate::problem::Problem;
+use crate::startup::{NewsletterDbConn, TwoFactorSessionLifetime};
+use chrono::{DateTime, Utc};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct Enrollment {
+    secret: String,
+    provisioning_uri: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Confirmation {
+    code: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct TwoFactorSession {
+    /// Shown once: only its hash is stored.
+    session: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Starts enrolling the caller in two-factor authentication with a new
+/// secret, which only takes effect once a code for it is confirmed.
+#[tracing::instrument(
+    name = "Enroll in two-factor authentication",
+    skip(conn, user, branding, key)
+)]
+#[post("/admin/2fa/enrollment")]
+pub async fn enroll_two_factor(
+    user: AuthenticatedUser,
+    conn: NewsletterDbConn,
+    branding: &State<Branding>,
+    key: &State<TotpSecretKey>,
+) -> Result<Json<Enrollment>, Problem> {
+    let secret = TotpSecret::generate();
+    let user_id = user.user_id;
+    let stored = key.seal(user_id, &secret);
+    let updated = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::users;
+            diesel::update(
+                users::table
+                    .find(user_id)
+                    .filter(users::totp_enabled_at.is_null()),
+            )
+            .set(users::totp_secret.eq(stored))
+            .execute(c)
+        })
+        .await
+        .map_err(internal_error)?;
+    if updated == 0 {
+        return Err(already_enabled());
+    }
+    Ok(Json(Enrollment {
+        provisioning_uri: secret.provisioning_uri(&branding.site_name, &user.username),
+        secret: secret.to_base32(),
+    }))
+}
+
+enum Confirmed {
+    Enabled,
+    AlreadyEnabled,
+    NotEnrolled,
+    InvalidCode,
+}
+
+/// Enables two-factor authentication once the caller proves that their
+/// authenticator app has the secret, and hands out fresh recovery codes.
+#[tracing::instrument(
+    name = "Confirm two-factor authentication",
+    skip(body, conn, user, clock, key)
+)]
+#[post("/admin/2fa/confirmation", data = "<body>")]
+pub async fn confirm_two_factor(
+    body: Json<Confirmation>,
+    user: AuthenticatedUser,
+    conn: NewsletterDbConn,
+    clock: &State<Arc<dyn Clock>>,
+    key: &State<TotpSecretKey>,
+    request_id: RequestId,
+) -> Result<Json<RecoveryCodes>, Problem> {
+    let key = key.inner().clone();
+    let recovery_codes = generate_recovery_codes();
+    let code_hashes: Vec<_> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+    let (user_id, now) = (user.user_id, clock.now());
+    let code = body.into_inner().code;
+    let audit = AuditEntry::new(Actor::from(&user), "two_factor.enabled", &request_id)
+        .with_target_type("user")
+        .with_target(user_id);
+    let confirmed = conn
+        .run(move |c: &mut PgConnection| confirm(c, &key, user_id, &code, now, &code_hashes, audit))
+        .await
+        .map_err(internal_error)?;
+    match confirmed {
+        Confirmed::Enabled => Ok(Json(RecoveryCodes { recovery_codes })),
+        Confirmed::AlreadyEnabled => Err(already_enabled()),
+        Confirmed::NotEnrolled => Err(Problem::new(Status::Conflict, "not_enrolled")
+            .with_detail("Start an enrollment before confirming it.")),
+        Confirmed::InvalidCode => Err(Problem::new(Status::BadRequest, "invalid_otp")
+            .with_detail("The one-time code is invalid or has expired.")),
+    }
+}
+
+fn confirm(
+    conn: &PgConnection,
+    key: &TotpSecretKey,
+    user_id: Uuid,
+    code: &str,
+    now: chrono::DateTime<Utc>,
+    code_hashes: &[String],
+    audit: AuditEntry,
+) -> Result<Confirmed, diesel::result::Error> {
+    use crate::schema::{recovery_codes, users};
+    conn.transaction(|| {
+        let (secret, enabled_at) = users::table
+            .find(user_id)
+            .select((users::totp_secret, users::totp_enabled_at))
+            .for_update()
+            .first::<(Option<String>, Option<chrono::DateTime<Utc>>)>(conn)?;
+        if enabled_at.is_some() {
+            return Ok(Confirmed::AlreadyEnabled);
+        }
+        let secret = match secret.and_then(|s| key.open(user_id, &s)) {
+            Some(secret) => secret,
+            None => return Ok(Confirmed::NotEnrolled),
+        };
+        let step = match secret.verify(code, now) {
+            Some(step) => step,
+            None => return Ok(Confirmed::InvalidCode),
+        };
+        // the confirming code may not be used again to log in
+        diesel::update(users::table.find(user_id))
+            .set((
+                users::totp_enabled_at.eq(now),
+                users::totp_last_step.eq(step),
+            ))
+            .execute(conn)?;
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+            .execute(conn)?;
+        let new_codes: Vec<_> = code_hashes
+            .iter()
+            .map(|code_hash| NewRecoveryCode {
+                user_id: &user_id,
+                code_hash,
+            })
+            .collect();
+        diesel::insert_into(recovery_codes::table)
+            .values(&new_codes)
+            .execute(conn)?;
+        audit
+            .with_after(serde_json::json!({ "enabled_at": now }))
+            .record(conn)?;
+        Ok(Confirmed::Enabled)
+    })
+}
+
+/// Turns two-factor authentication off for the caller, which takes a second
+/// factor like any other request of theirs.
+#[tracing::instrument(name = "Disable two-factor authentication", skip(conn, user))]
+#[delete("/admin/2fa")]
+pub async fn disable_two_factor(
+    user: AuthenticatedUser,
+    conn: NewsletterDbConn,
+    request_id: RequestId,
+) -> Result<Status, Problem> {
+    let user_id = user.user_id;
+    let audit = AuditEntry::new(Actor::from(&user), "two_factor.disabled", &request_id)
+        .with_target_type("user")
+        .with_target(user_id);
+    let disabled = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::two_factor_sessions;
+            use crate::schema::{recovery_codes, users};
+            c.transaction(|| {
+                let updated = diesel::update(
+                    users::table
+                        .find(user_id)
+                        .filter(users::totp_enabled_at.is_not_null()),
+                )
+                .set((
+                    users::totp_secret.eq(None::<String>),
+                    users::totp_enabled_at.eq(None::<chrono::DateTime<Utc>>),
+                    users::totp_last_step.eq(None::<i64>),
+                ))
+                .execute(c)?;
+                if updated == 0 {
+                    return Ok(false);
+                }
+                diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                    .execute(c)?;
+                diesel::delete(
+                    two_factor_sessions::table.filter(two_factor_sessions::user_id.eq(user_id)),
+                )
+                .execute(c)?;
+                audit.record(c)?;
+                Ok::<_, diesel::result::Error>(true)
+            })
+        })
+        .await
+        .map_err(internal_error)?;
+    if !disabled {
+        return Err(Problem::new(Status::Conflict, "two_factor_not_enabled")
+            .with_detail("Two-factor authentication is not enabled."));
+    }
+    Ok(Status::NoContent)
+}
+
+/// Starts a session for the caller, so that their next requests can send its
+/// token in `X-2FA-Session` rather than a one-time code each: codes are only
+/// accepted once per time step. Only a one-time code can start a session, so
+/// that sessions cannot be extended indefinitely.
+#[tracing::instrument(name = "Start a two-factor session", skip(conn, user, clock, lifetime))]
+#[post("/admin/2fa/session")]
+pub async fn start_two_factor_session(
+    user: AuthenticatedUser,
+    conn: NewsletterDbConn,
+    clock: &State<Arc<dyn Clock>>,
+    lifetime: &State<TwoFactorSessionLifetime>,
+) -> Result<(Status, Json<TwoFactorSession>), Problem> {
+    match user.second_factor {
+        SecondFactor::OneTimeCode => {}
+        SecondFactor::NotEnabled => {
+            return Err(Problem::new(Status::Conflict, "two_factor_not_enabled")
+                .with_detail("Two-factor authentication is not enabled."))
+        }
+        SecondFactor::Session => {
+            return Err(Problem::new(Status::Forbidden, "otp_required")
+                .with_detail("Only a one-time code can start a session."))
+        }
+    }
+    let session = generate_session_token();
+    let token_hash = hash_session_token(&session);
+    let (user_id, created_at) = (user.user_id, clock.now());
+    let expires_at = created_at + lifetime.0;
+    conn.run(move |c: &mut PgConnection| {
+        use crate::schema::two_factor_sessions;
+        // the user's expired sessions are cleared out as new ones start
+        diesel::delete(
+            two_factor_sessions::table
+                .filter(two_factor_sessions::user_id.eq(user_id))
+                .filter(two_factor_sessions::expires_at.le(created_at)),
+        )
+        .execute(c)?;
+        diesel::insert_into(two_factor_sessions::table)
+            .values(NewTwoFactorSession {
+                token_hash: &token_hash,
+                user_id: &user_id,
+                created_at: &created_at,
+                expires_at: &expires_at,
+            })
+            .execute(c)
+    })
+    .await
+    .map_err(internal_error)?;
+    Ok((
+        Status::Created,
+        Json(TwoFactorSession {
+            session,
+            expires_at,
+        }),
+    ))
+}
+
+fn already_enabled() -> Problem {
+    Problem::new(Status::Conflict, "two_factor_enabled")
+        .with_detail("Two-factor authentication is already enabled; disable it first.")
+}
+
+fn internal_error(e: diesel::result::Error) -> Problem {
+    tracing::error!(error.cause_chain = ?e, "Failed to access the two-factor settings.");
+    Problem::new(Status::InternalServerError, "internal_error")
+}
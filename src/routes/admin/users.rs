@@ -0,0 +1,147 @@
+use crate::audit::{Actor, AuditEntry};
+use crate::domain::permissions::ManageUsers;
+use crate::domain::Role;
+use crate::guards::{Authorized, RequestId};
+use crate::problem::{FieldError, Problem};
+use crate::startup::NewsletterDbConn;
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct UserSummary {
+    user_id: String,
+    username: String,
+    role: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleUpdate {
+    role: String,
+}
+
+#[tracing::instrument(name = "List users", skip(conn, _caller))]
+#[get("/admin/users")]
+pub async fn list_users(
+    _caller: Authorized<ManageUsers>,
+    conn: NewsletterDbConn,
+) -> Result<Json<Vec<UserSummary>>, Problem> {
+    conn.run(|c: &mut PgConnection| {
+        use crate::schema::users;
+        users::table
+            .select((users::user_id, users::username, users::role))
+            .order(users::username)
+            .load::<(Uuid, String, String)>(c)
+    })
+    .await
+    .map(|users| {
+        Json(
+            users
+                .into_iter()
+                .map(|(user_id, username, role)| UserSummary {
+                    user_id: user_id.to_string(),
+                    username,
+                    role,
+                })
+                .collect(),
+        )
+    })
+    .map_err(internal_error)
+}
+
+enum RoleChange {
+    Changed { username: String },
+    UnknownUser,
+    LastOwner,
+}
+
+#[tracing::instrument(
+    name = "Change a user's role",
+    skip(body, conn, caller),
+    fields(caller = %caller)
+)]
+#[put("/admin/users/<id>/role", data = "<body>")]
+pub async fn put_user_role(
+    id: &str,
+    body: Json<RoleUpdate>,
+    caller: Authorized<ManageUsers>,
+    conn: NewsletterDbConn,
+    request_id: RequestId,
+) -> Result<Json<UserSummary>, Problem> {
+    let id = Uuid::parse_str(id).map_err(|_| Problem::from_status(Status::NotFound))?;
+    let role = Role::parse(&body.role).ok_or_else(|| {
+        let known: Vec<_> = Role::ALL.iter().map(Role::as_str).collect();
+        Problem::new(Status::BadRequest, "invalid_role")
+            .with_detail("The role change was rejected.")
+            .with_errors(vec![FieldError::invalid(
+                "role",
+                format!("Must be one of {}.", known.join(", ")),
+            )])
+    })?;
+
+    let audit = AuditEntry::new(
+        Actor::from(&caller.caller),
+        "user.role_changed",
+        &request_id,
+    )
+    .with_target_type("user")
+    .with_target(id);
+    let change = conn
+        .run(move |c: &mut PgConnection| change_role(c, id, role, audit))
+        .await
+        .map_err(internal_error)?;
+    match change {
+        RoleChange::UnknownUser => Err(Problem::from_status(Status::NotFound)),
+        RoleChange::LastOwner => Err(Problem::new(Status::Conflict, "last_owner")
+            .with_detail("The last owner cannot be given another role.")),
+        RoleChange::Changed { username } => Ok(Json(UserSummary {
+            user_id: id.to_string(),
+            username,
+            role: role.to_string(),
+        })),
+    }
+}
+
+/// Changes the role, unless that would leave nobody able to manage users.
+fn change_role(
+    conn: &PgConnection,
+    id: Uuid,
+    role: Role,
+    audit: AuditEntry,
+) -> Result<RoleChange, diesel::result::Error> {
+    use crate::schema::users;
+    conn.transaction(|| {
+        // locking the owners keeps two demotions from each leaving one owner
+        let owners = users::table
+            .select(users::user_id)
+            .filter(users::role.eq(Role::Owner.as_str()))
+            .for_update()
+            .load::<Uuid>(conn)?;
+        let (username, previous) = match users::table
+            .find(id)
+            .select((users::username, users::role))
+            .first::<(String, String)>(conn)
+        {
+            Ok(user) => user,
+            Err(diesel::result::Error::NotFound) => return Ok(RoleChange::UnknownUser),
+            Err(e) => return Err(e),
+        };
+        if role != Role::Owner && owners == [id] {
+            return Ok(RoleChange::LastOwner);
+        }
+        diesel::update(users::table.find(id))
+            .set(users::role.eq(role.as_str()))
+            .execute(conn)?;
+        audit
+            .with_before(serde_json::json!({ "username": username, "role": previous }))
+            .with_after(serde_json::json!({ "username": username, "role": role.as_str() }))
+            .record(conn)?;
+        Ok(RoleChange::Changed { username })
+    })
+}
+
+fn internal_error(e: diesel::result::Error) -> Problem {
+    tracing::error!(error.cause_chain = ?e, "Failed to access the users.");
+    Problem::new(Status::InternalServerError, "internal_error")
+}
@@ -1,3 +1,99 @@
+use crate::email::Email;
+use crate::startup::{CheckEmailHealth, NewsletterDbConn};
+use diesel::{PgConnection, RunQueryDsl};
+use diesel_migrations::MigrationConnection;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::serde_json::Map;
+use rocket::serde::json::{json, Json, Value};
+use rocket::State;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
 // TODO: why can't this be called health_check?
 #[get("/health_check")]
 pub async fn health() {}
+
+/// The process is up and serving requests; dependencies are not consulted.
+#[get("/health/live")]
+pub async fn liveness() {}
+
+/// Whether this instance can do useful work: the database answers, its schema
+/// is up to date and, when enabled, the email backend accepts our credentials.
+#[tracing::instrument(name = "Check readiness", skip(conn, email_client, check_email))]
+#[get("/health/ready")]
+pub async fn readiness(
+    conn: Result<NewsletterDbConn, ()>,
+    email_client: &State<Arc<dyn Email>>,
+    check_email: &State<CheckEmailHealth>,
+) -> status::Custom<Json<Value>> {
+    let mut checks = Map::new();
+    match conn {
+        Ok(conn) => {
+            checks.insert("database".into(), timed(ping_database(&conn)).await);
+            checks.insert("migrations".into(), timed(check_migrations(&conn)).await);
+        }
+        Err(()) => {
+            tracing::warn!("Readiness check failed: no connection available from the pool.");
+            let unavailable = json!({ "status": "fail" });
+            checks.insert("database".into(), unavailable.clone());
+            checks.insert("migrations".into(), unavailable);
+        }
+    }
+    if check_email.0 {
+        checks.insert("email".into(), timed(email_client.check_health()).await);
+    }
+
+    let ready = checks.values().all(|check| check["status"] == "pass");
+    let (status, overall) = if ready {
+        (Status::Ok, "pass")
+    } else {
+        (Status::ServiceUnavailable, "fail")
+    };
+    status::Custom(status, Json(json!({ "status": overall, "checks": checks })))
+}
+
+async fn timed(check: impl Future<Output = Result<(), anyhow::Error>>) -> Value {
+    let start = Instant::now();
+    let result = check.await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    match result {
+        Ok(()) => json!({ "status": "pass", "latency_ms": latency_ms }),
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Readiness check failed.");
+            // the probe is public, so the details stay in the logs
+            json!({ "status": "fail", "latency_ms": latency_ms })
+        }
+    }
+}
+
+async fn ping_database(conn: &NewsletterDbConn) -> Result<(), anyhow::Error> {
+    conn.run(|c: &mut PgConnection| diesel::sql_query("SELECT 1").execute(c))
+        .await?;
+    Ok(())
+}
+
+// the migrations the binary was built with, so that the check does not depend
+// on finding a migrations directory at runtime
+#[allow(dead_code)]
+mod embedded_migrations {
+    use diesel_migrations::EmbedMigrations;
+
+    #[derive(EmbedMigrations)]
+    struct _Dummy;
+
+    pub fn versions() -> impl Iterator<Item = &'static str> {
+        ALL_MIGRATIONS.iter().map(|migration| migration.version())
+    }
+}
+
+async fn check_migrations(conn: &NewsletterDbConn) -> Result<(), anyhow::Error> {
+    let applied = conn
+        .run(|c: &mut PgConnection| c.previously_run_migration_versions())
+        .await?;
+    if embedded_migrations::versions().any(|version| !applied.contains(version)) {
+        anyhow::bail!("The database has pending migrations.");
+    }
+    Ok(())
+}
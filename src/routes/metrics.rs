@@ -0,0 +1,42 @@
+use crate::guards::MetricsScraper;
+use crate::problem::Problem;
+use crate::startup::NewsletterDbConn;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use rocket::http::{ContentType, Status};
+
+#[tracing::instrument(name = "Render metrics", skip(conn, _scraper))]
+#[get("/metrics")]
+pub async fn render_metrics(
+    conn: NewsletterDbConn,
+    _scraper: MetricsScraper,
+) -> Result<(ContentType, String), Problem> {
+    let counts = conn
+        .run(|conn: &mut PgConnection| count_subscribers_by_status(conn))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to count subscribers: {:?}", e);
+            Problem::new(Status::InternalServerError, "internal_error")
+        })?;
+    let body = crate::metrics::render(&counts).map_err(|e| {
+        tracing::error!("Failed to encode metrics: {:?}", e);
+        Problem::new(Status::InternalServerError, "internal_error")
+    })?;
+    let content_type = ContentType::with_params("text", "plain", ("version", "0.0.4"));
+    Ok((content_type, body))
+}
+
+fn count_subscribers_by_status(
+    conn: &PgConnection,
+) -> Result<Vec<(&'static str, i64)>, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::*;
+    ["confirmed", "pending_confirmation"]
+        .into_iter()
+        .map(|s| {
+            let count = subscriptions
+                .filter(status.eq(s))
+                .count()
+                .get_result(conn)?;
+            Ok((s, count))
+        })
+        .collect()
+}
@@ -1,12 +1,20 @@
+mod admin;
 mod health_check;
+mod metrics;
 mod newsletters;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tracking;
+mod webhooks;
 
+pub use admin::*;
 pub use health_check::*;
+pub use metrics::*;
 pub use newsletters::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use tracking::*;
+pub use webhooks::*;
 
 fn error_chain_fmt(
     e: &impl std::error::Error,
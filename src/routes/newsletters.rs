@@ -1,72 +1,165 @@
+use crate::audit::{Actor, AuditEntry};
+use crate::domain::permissions::Publish;
 use crate::domain::SubscriberEmail;
-use crate::email::Email;
-use crate::guards::AuthenticatedUser;
+use crate::email::{Delivery, Email};
+use crate::guards::{Authorized, RequestId};
+use crate::models::{NewIssueDelivery, NewIssueLink, NewNewsletterIssue};
+use crate::problem::Problem;
 use crate::routes::error_chain_fmt;
+use crate::shutdown::ShutdownCoordinator;
 use crate::startup::NewsletterDbConn;
+use crate::telemetry::Pii;
+use crate::tracking::{self, TrackedIssue, Tracker};
 use anyhow::Context;
-use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use chrono::Utc;
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
 use rocket::http::Status;
 use rocket::response::Responder;
-use rocket::{Request, Response, State};
+use rocket::serde::json::Json;
+use rocket::{Request, State};
 use std::sync::Arc;
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    #[serde(default)]
+    tracking: TrackingOverride,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 pub struct Content {
     html: String,
     text: String,
 }
 
+/// Turns open or click tracking on or off for this issue only.
+#[derive(serde::Deserialize, Default)]
+pub struct TrackingOverride {
+    opens: Option<bool>,
+    clicks: Option<bool>,
+}
+
+/// The issue and what became of its emails; recipients whose email failed are
+/// listed by `GET /admin/issues/<id>/deliveries`.
+#[derive(serde::Serialize)]
+pub struct PublishedIssue {
+    issue_id: String,
+    recipients: usize,
+    sent: usize,
+    failed: usize,
+    suppressed: usize,
+}
+
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, conn, email_client, user)
-    fields(username=tracing::field::Empty,
-           user_id=tracing::field::Empty)
+    skip(body, conn, email_client, tracker, shutdown, caller)
+    fields(caller = %caller)
 )]
 #[post("/newsletters", data = "<body>")]
 pub async fn publish_newsletter(
     body: rocket::serde::json::Json<BodyData>,
+    caller: Authorized<Publish>,
     conn: NewsletterDbConn,
     email_client: &State<Arc<dyn Email>>,
-    user: AuthenticatedUser,
-) -> Result<(), PublishError> {
-    tracing::Span::current()
-        .record("username", &tracing::field::display(&user.username))
-        .record("user_id", &tracing::field::display(&user.user_id));
-
+    tracker: &State<Tracker>,
+    shutdown: &State<ShutdownCoordinator>,
+    request_id: RequestId,
+) -> Result<Json<PublishedIssue>, PublishError> {
     let subscribers = conn
         .run(|conn: &mut PgConnection| get_confirmed_subscribers(conn))
         .await
         .context("Failed to fetch confirmed subscribers from database.")?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
+    let track_opens = body.tracking.opens.unwrap_or(tracker.tracks_opens());
+    let track_clicks = body.tracking.clicks.unwrap_or(tracker.tracks_clicks());
+    let issue = TrackedIssue {
+        id: Uuid::new_v4(),
+        track_opens,
+        track_clicks,
+        links: if track_clicks {
+            tracking::links(&body.content.html)
+        } else {
+            Vec::new()
+        },
+    };
+    let (stored_issue, title, content) = (issue.clone(), body.title.clone(), body.content.clone());
+    let recipients: Vec<Uuid> = subscribers.iter().map(|s| s.id).collect();
+    let audit = AuditEntry::new(
+        Actor::from(&caller.caller),
+        "newsletter.published",
+        &request_id,
+    )
+    .with_target_type("newsletter_issue")
+    .with_target(issue.id)
+    .with_after(serde_json::json!({
+        "title": body.title,
+        "recipients": recipients.len(),
+        "track_opens": track_opens,
+        "track_clicks": track_clicks,
+    }));
+    conn.run(move |conn: &mut PgConnection| {
+        store_issue(conn, &stored_issue, &title, &content, &recipients, audit)
+    })
+    .await
+    .context("Failed to store the newsletter issue.")?;
+
+    let total = subscribers.len();
+    let mut published = PublishedIssue {
+        issue_id: issue.id.to_string(),
+        recipients: total,
+        sent: 0,
+        failed: 0,
+        suppressed: 0,
+    };
+    let progress = shutdown.track(format!(
+        "Publishing '{}': 0 of {} subscribers done",
+        body.title, total
+    ));
+    for (done, subscriber) in subscribers.into_iter().enumerate() {
+        progress.describe(format!(
+            "Publishing '{}': {} of {} subscribers done",
+            body.title, done, total
+        ));
+        // a failed send is recorded, and does not hold up the other recipients
+        let outcome = match &subscriber.email {
+            Ok(email) => {
+                let html = tracker.personalize(&issue, subscriber.id, &body.content.html);
                 email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
+                    .send_email(email, &body.title, &html, &body.content.text)
                     .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
             }
             Err(error) => {
                 tracing::warn!(error.cause_chain = ?error,
                     "Skipping a confirmed subscriber. \
                     Their stored contact details are invalid",
                 );
+                Err(anyhow::anyhow!("The stored address is not a valid email."))
             }
-        }
+        };
+        let update = match outcome {
+            Ok(Delivery::Sent { message_id }) => {
+                published.sent += 1;
+                DeliveryUpdate::new("sent").with_message_id(message_id)
+            }
+            Ok(Delivery::Suppressed) => {
+                published.suppressed += 1;
+                DeliveryUpdate::new("suppressed")
+            }
+            Err(e) => {
+                published.failed += 1;
+                tracing::error!(error.cause_chain = ?e, "Failed to send a newsletter issue.");
+                DeliveryUpdate::new("failed").with_error(format!("{:#}", e))
+            }
+        };
+        let (issue_id, subscriber_id) = (issue.id, subscriber.id);
+        conn.run(move |conn: &mut PgConnection| {
+            update_delivery(conn, &issue_id, &subscriber_id, update)
+        })
+        .await
+        .context("Failed to record the delivery of a newsletter issue.")?;
     }
-    Ok(())
+    Ok(Json(published))
 }
 
 #[derive(thiserror::Error)]
@@ -82,36 +175,145 @@ impl std::fmt::Debug for PublishError {
 }
 
 impl<'r> Responder<'r, 'static> for PublishError {
-    fn respond_to(self, _request: &'r Request<'_>) -> rocket::response::Result<'static> {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
         tracing::warn!("PublishError: {:?}", self);
-        Response::build()
-            .status(match self {
-                PublishError::UnexpectedError(_) => Status::InternalServerError,
-            })
-            .ok()
+        match self {
+            PublishError::UnexpectedError(_) => {
+                Problem::new(Status::InternalServerError, "internal_error")
+            }
+        }
+        .respond_to(request)
     }
 }
 
 pub struct ConfirmedSubscriber {
-    pub email: SubscriberEmail,
+    pub id: Uuid,
+    /// An error if the stored address is not valid.
+    pub email: Result<SubscriberEmail, anyhow::Error>,
 }
 
 #[tracing::instrument(name = "Get confirmed subscribers", skip(conn))]
 fn get_confirmed_subscribers(
     conn: &PgConnection,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, diesel::result::Error> {
+) -> Result<Vec<ConfirmedSubscriber>, diesel::result::Error> {
     use crate::schema::subscriptions as subs;
     let rows = subs::table
-        .select(subs::email)
+        .select((subs::id, subs::email))
         .filter(subs::status.eq("confirmed"))
-        .load::<String>(conn)?;
+        .load::<(Uuid, String)>(conn)?;
 
     let confirmed_subscribers = rows
         .into_iter()
-        .map(|email| match SubscriberEmail::parse(email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
-            Err(error) => Err(anyhow::anyhow!(error)),
+        .map(|(id, email)| ConfirmedSubscriber {
+            id,
+            email: SubscriberEmail::parse(email.clone()).map_err(|_| {
+                // the parse error quotes the address verbatim
+                anyhow::anyhow!("{} is not a valid subscriber email.", Pii(&email))
+            }),
         })
         .collect();
     Ok(confirmed_subscribers)
 }
+
+/// Stores the issue, with a queued delivery for each of its recipients, and
+/// who published it.
+#[tracing::instrument(
+    name = "Store a newsletter issue",
+    skip(conn, issue, content, recipients, audit)
+)]
+fn store_issue(
+    conn: &PgConnection,
+    issue: &TrackedIssue,
+    title: &str,
+    content: &Content,
+    recipients: &[Uuid],
+    audit: AuditEntry,
+) -> Result<(), diesel::result::Error> {
+    use crate::schema::{issue_deliveries, issue_links, newsletter_issues};
+    conn.transaction(|| {
+        diesel::insert_into(newsletter_issues::table)
+            .values(NewNewsletterIssue {
+                id: &issue.id,
+                title,
+                text_content: &content.text,
+                html_content: &content.html,
+                published_at: &Utc::now(),
+                track_opens: issue.track_opens,
+                track_clicks: issue.track_clicks,
+            })
+            .execute(conn)?;
+        let links: Vec<_> = issue
+            .links
+            .iter()
+            .enumerate()
+            .map(|(position, url)| NewIssueLink {
+                issue_id: &issue.id,
+                position: position as i32,
+                url,
+            })
+            .collect();
+        diesel::insert_into(issue_links::table)
+            .values(&links)
+            .execute(conn)?;
+        let now = Utc::now();
+        let deliveries: Vec<_> = recipients
+            .iter()
+            .map(|subscriber_id| NewIssueDelivery {
+                issue_id: &issue.id,
+                subscriber_id,
+                status: "queued",
+                error: None,
+                message_id: None,
+                updated_at: &now,
+            })
+            .collect();
+        diesel::insert_into(issue_deliveries::table)
+            .values(&deliveries)
+            .execute(conn)?;
+        audit.record(conn)
+    })
+}
+
+struct DeliveryUpdate {
+    status: &'static str,
+    error: Option<String>,
+    message_id: Option<String>,
+}
+
+impl DeliveryUpdate {
+    fn new(status: &'static str) -> Self {
+        Self {
+            status,
+            error: None,
+            message_id: None,
+        }
+    }
+
+    fn with_error(mut self, error: String) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    fn with_message_id(mut self, message_id: Option<String>) -> Self {
+        self.message_id = message_id;
+        self
+    }
+}
+
+fn update_delivery(
+    conn: &PgConnection,
+    issue_id: &Uuid,
+    subscriber_id: &Uuid,
+    update: DeliveryUpdate,
+) -> Result<(), diesel::result::Error> {
+    use crate::schema::issue_deliveries;
+    diesel::update(issue_deliveries::table.find((issue_id, subscriber_id)))
+        .set((
+            issue_deliveries::status.eq(update.status),
+            issue_deliveries::error.eq(update.error),
+            issue_deliveries::message_id.eq(update.message_id),
+            issue_deliveries::updated_at.eq(Utc::now()),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
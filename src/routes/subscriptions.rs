@@ -1,53 +1,67 @@
 use crate::domain::SubscriberName;
-use crate::domain::{NewSubscriber, SubscriberEmail};
+use crate::domain::{
+    EmailPolicy, EmailPolicyViolation, NewSubscriber, SubscriberEmail, SubscriptionTokens,
+};
 use crate::email::Email;
 use crate::models::{NewSubscription, NewSubscriptionToken};
+use crate::pages::{Branding, Page};
+use crate::problem::{FieldError, Problem};
 use crate::routes::error_chain_fmt;
 use crate::startup::{ApplicationBaseUrl, NewsletterDbConn};
 use anyhow::Context;
 use chrono::Utc;
 use diesel::{PgConnection, RunQueryDsl};
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
+use rocket::data::{self, Data, FromData};
 use rocket::form::Form;
 use rocket::http::Status;
+use rocket::outcome::Outcome;
 use rocket::response::Responder;
-use rocket::{Request, Response, State};
+use rocket::serde::json::{json, Json};
+use rocket::{Request, State};
+use rocket_dyn_templates::Template;
+use secrecy::ExposeSecret;
 use std::borrow::Borrow;
 use std::error::Error;
 use std::fmt::Formatter;
 use std::sync::Arc;
 use uuid::Uuid;
 
+#[get("/subscribe")]
+pub fn subscribe_form(branding: &State<Branding>) -> Template {
+    branding.render("subscribe", json!({}))
+}
+
 #[tracing::instrument(
 name = "Adding a new subscriber",
-skip(form, conn, email_client, base_url),
+skip(body, conn, email_client, email_policy, tokens, base_url),
     fields(
-        request_id = %Uuid::new_v4(),
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = %body.0.email.as_deref().unwrap_or_default(),
+        subscriber_name = %body.0.name.as_deref().unwrap_or_default()
     )
 )]
-#[post("/subscriptions", data = "<form>")]
+#[post("/subscriptions", data = "<body>")]
 pub async fn subscribe(
-    form: Form<FormData>,
+    body: SubscribeBody,
     conn: NewsletterDbConn,
     email_client: &State<Arc<dyn Email>>,
+    email_policy: &State<EmailPolicy>,
+    tokens: &State<SubscriptionTokens>,
     base_url: &State<ApplicationBaseUrl>,
-) -> Result<(), SubscribeError> {
-    let new_subscriber = form
-        .into_inner()
-        .try_into()
-        .map_err(SubscribeError::ValidationError)?;
-    let (subscription_token, new_subscriber) = conn
+) -> Result<Page, SubscribeError> {
+    let new_subscriber: NewSubscriber =
+        body.0.try_into().map_err(SubscribeError::ValidationError)?;
+    email_policy.check(&new_subscriber.email)?;
+    let canonical_email = email_policy.canonicalize(&new_subscriber.email);
+    let subscription_token = tokens.generate();
+    let token_hash = tokens.hash(&subscription_token);
+    let new_subscriber = conn
         .run_transaction::<_, SubscribeError, _, _>(
             move |conn| {
-                let subscriber_id = insert_subscriber(&new_subscriber, conn)
+                let subscriber_id = insert_subscriber(&new_subscriber, &canonical_email, conn)
                     .context("Failed to insert new subscriber in the database.")?;
-                let subscription_token = generate_subscription_token();
-                store_token(conn, &subscriber_id, &subscription_token)
+                store_token(conn, &subscriber_id, &token_hash)
                     .context("Failed to store the confirmation token for a new subscriber.")?;
-                Ok((subscription_token, new_subscriber))
+                Ok(new_subscriber)
             },
             |e| {
                 anyhow::Error::new(e)
@@ -65,45 +79,119 @@ pub async fn subscribe(
     )
     .await
     .context("Failed to send a confirmation email.")?;
-    Ok(())
+    Ok(Page::new("subscribe_pending"))
 }
 
-#[derive(FromForm)]
+// fields are optional so that missing ones are reported alongside invalid ones
+#[derive(FromForm, serde::Deserialize)]
 pub struct FormData {
-    name: String,
-    email: String,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+/// Subscriber details posted either as a form or as JSON.
+pub struct SubscribeBody(FormData);
+
+#[async_trait]
+impl<'r> FromData<'r> for SubscribeBody {
+    type Error = anyhow::Error;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let content_type = req.content_type();
+        if matches!(content_type, Some(ct) if ct.is_json()) {
+            Json::<FormData>::from_data(req, data)
+                .await
+                .map(|json| SubscribeBody(json.into_inner()))
+                .map_failure(|(status, e)| (status, anyhow::anyhow!("{:?}", e)))
+        } else if matches!(content_type, Some(ct) if ct.is_form()) {
+            Form::<FormData>::from_data(req, data)
+                .await
+                .map(|form| SubscribeBody(form.into_inner()))
+                .map_failure(|(status, e)| (status, anyhow::anyhow!("{:?}", e)))
+        } else {
+            Outcome::Failure((
+                Status::UnsupportedMediaType,
+                anyhow::anyhow!("Subscriptions must be posted as a form or as JSON."),
+            ))
+        }
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = Vec<FieldError>;
 
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let email = SubscriberEmail::parse(form.email)?;
-        let name = SubscriberName::parse(form.name)?;
-        Ok(NewSubscriber { email, name })
+        let email = form
+            .email
+            .ok_or_else(|| FieldError::missing("email"))
+            .and_then(|email| {
+                SubscriberEmail::parse(email).map_err(|e| FieldError::invalid("email", e))
+            });
+        let name = form
+            .name
+            .ok_or_else(|| FieldError::missing("name"))
+            .and_then(|name| {
+                SubscriberName::parse(name).map_err(|e| FieldError::invalid("name", e))
+            });
+        match (email, name) {
+            (Ok(email), Ok(name)) => Ok(NewSubscriber { email, name }),
+            (email, name) => Err(email.err().into_iter().chain(name.err()).collect()),
+        }
     }
 }
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    // the field messages quote the submitted values, so only name the fields
+    #[error("Invalid subscriber details: {}", invalid_fields(.0))]
+    ValidationError(Vec<FieldError>),
+    #[error(transparent)]
+    PolicyViolation(#[from] EmailPolicyViolation),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
 impl<'r> Responder<'r, 'static> for SubscribeError {
-    fn respond_to(self, _request: &'r Request<'_>) -> rocket::response::Result<'static> {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
         tracing::warn!("SubscribeError: {:?}", self);
-        Response::build()
-            .status(match self {
-                SubscribeError::ValidationError(_) => Status::BadRequest,
-                SubscribeError::UnexpectedError(_) => Status::InternalServerError,
-            })
-            .ok()
+        let (template, errors, problem) = match self {
+            SubscribeError::ValidationError(errors) => (
+                "subscribe",
+                errors,
+                Problem::new(Status::BadRequest, "validation_failed")
+                    .with_detail("The subscriber details are invalid."),
+            ),
+            SubscribeError::PolicyViolation(violation) => (
+                "subscribe",
+                vec![FieldError {
+                    field: "email",
+                    code: violation.code().into(),
+                    message: violation.to_string(),
+                }],
+                Problem::new(Status::BadRequest, "email_policy_violation")
+                    .with_detail("The email address is not accepted."),
+            ),
+            SubscribeError::UnexpectedError(_) => (
+                "error",
+                Vec::new(),
+                Problem::new(Status::InternalServerError, "internal_error"),
+            ),
+        };
+        Page::new(template)
+            .with_context(json!({ "errors": &errors }))
+            .with_problem(problem.with_errors(errors))
+            .respond_to(request)
     }
 }
 
+fn invalid_fields(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("{} ({})", e.field, e.code))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl std::fmt::Debug for SubscribeError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
@@ -112,18 +200,20 @@ impl std::fmt::Debug for SubscribeError {
 
 #[tracing::instrument(
     name = "Store subscription token in the database",
-    skip(subscription_token, conn)
+    skip(token_hash, conn)
 )]
 pub fn store_token(
     conn: &PgConnection,
     subscriber_id: &Uuid,
-    subscription_token: &str,
+    token_hash: &str,
 ) -> Result<(), StoreTokenError> {
     use crate::schema::subscription_tokens::dsl::subscription_tokens;
     diesel::insert_into(subscription_tokens)
         .values(NewSubscriptionToken {
-            subscription_token,
+            subscription_token: token_hash,
             subscriber_id,
+            created_at: &Utc::now(),
+            hashed: true,
         })
         .execute(conn)
         .map_err(StoreTokenError)?;
@@ -179,15 +269,17 @@ async fn send_confirmation_email(
     );
     email_client
         .send_email(&new_subscriber.email, "Welcome!", html_body, plain_body)
-        .await
+        .await?;
+    Ok(())
 }
 
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, conn)
+    skip(new_subscriber, canonical_email, conn)
 )]
 fn insert_subscriber(
     new_subscriber: &NewSubscriber,
+    canonical_email: &str,
     conn: &PgConnection,
 ) -> Result<Uuid, diesel::result::Error> {
     use crate::schema::subscriptions;
@@ -195,19 +287,12 @@ fn insert_subscriber(
     diesel::insert_into(subscriptions::table)
         .values(NewSubscription {
             id: &subscriber_id,
-            email: new_subscriber.email.as_ref(),
-            name: new_subscriber.name.as_ref(),
+            email: new_subscriber.email.expose_secret(),
+            name: new_subscriber.name.expose_secret(),
             subscribed_at: &Utc::now(),
             status: "pending_confirmation",
+            canonical_email,
         })
         .execute(conn)?;
     Ok(subscriber_id)
 }
-
-fn generate_subscription_token() -> String {
-    let mut rng = thread_rng();
-    std::iter::repeat_with(|| rng.sample(Alphanumeric))
-        .map(char::from)
-        .take(25)
-        .collect()
-}
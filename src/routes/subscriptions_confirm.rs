@@ -1,31 +1,117 @@
+use crate::domain::SubscriptionTokens;
+use crate::guards::CsrfToken;
 use crate::models::SubscriptionToken;
+use crate::pages::Page;
+use crate::problem::Problem;
 use crate::startup::NewsletterDbConn;
-use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
-use rocket::http::Status;
+use chrono::Utc;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use rocket::form::Form;
+use rocket::http::{CookieJar, Status};
+use rocket::serde::json::json;
+use rocket::State;
 use uuid::Uuid;
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(subscription_token, conn))]
+/// Mail scanners prefetch links, so following the link only asks for
+/// confirmation; the state change happens in `confirm_submission`. API
+/// clients are told to post there instead of getting an empty page.
+#[tracing::instrument(
+    name = "Show the confirmation page",
+    skip(subscription_token, conn, tokens, cookies)
+)]
 #[get("/subscriptions/confirm?<subscription_token>")]
 pub async fn confirm(
     subscription_token: Option<&str>,
     conn: NewsletterDbConn,
-) -> Result<(), Status> {
+    tokens: &State<SubscriptionTokens>,
+    cookies: &CookieJar<'_>,
+) -> Page {
     let subscription_token = match subscription_token {
         Some(token) => token,
-        None => return Err(Status::BadRequest),
+        None => return missing_token_page(),
     };
-    let id = match get_subscriber_id_from_token(&conn, subscription_token.to_string()).await {
-        Ok(id) => id,
-        Err(_) => return Err(Status::InternalServerError),
+    match get_subscriber_id_from_token(&conn, tokens, subscription_token.to_string()).await {
+        Ok(TokenLookup::Valid(_)) => Page::new("confirm_subscription")
+            .with_context(json!({
+                "subscription_token": subscription_token,
+                "csrf_token": CsrfToken::issue(cookies).as_ref(),
+            }))
+            .with_api_problem(
+                Problem::new(Status::MethodNotAllowed, "confirmation_requires_post")
+                    .with_detail(
+                        "Confirm the subscription by posting its token, along with the CSRF \
+                         token set as a cookie, to this address.",
+                    )
+                    .with_allow("POST"),
+            ),
+        Ok(TokenLookup::Expired) => expired_token_page(),
+        Ok(TokenLookup::Unknown) => invalid_token_page(),
+        Err(_) => internal_error_page(),
+    }
+}
+
+#[derive(FromForm)]
+pub struct ConfirmFormData {
+    subscription_token: Option<String>,
+    csrf_token: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(form, conn, tokens, cookies)
+)]
+#[post("/subscriptions/confirm", data = "<form>")]
+pub async fn confirm_submission(
+    form: Form<ConfirmFormData>,
+    conn: NewsletterDbConn,
+    tokens: &State<SubscriptionTokens>,
+    cookies: &CookieJar<'_>,
+) -> Page {
+    let form = form.into_inner();
+    let csrf_token = form.csrf_token.unwrap_or_default();
+    if !CsrfToken::verify(cookies, &csrf_token) {
+        return Page::new("error").with_problem(
+            Problem::new(Status::Forbidden, "invalid_csrf_token")
+                .with_detail("The form has expired, please follow the link again."),
+        );
+    }
+    let subscription_token = match form.subscription_token {
+        Some(token) => token,
+        None => return missing_token_page(),
+    };
+    let lookup = match get_subscriber_id_from_token(&conn, tokens, subscription_token).await {
+        Ok(lookup) => lookup,
+        Err(_) => return internal_error_page(),
     };
-    match id {
-        None => Err(Status::Unauthorized),
-        Some(subscriber_id) => confirm_subscriber(&conn, subscriber_id)
-            .await
-            .map_err(|_| Status::InternalServerError),
+    match lookup {
+        TokenLookup::Unknown => invalid_token_page(),
+        TokenLookup::Expired => expired_token_page(),
+        TokenLookup::Valid(subscriber_id) => match confirm_subscriber(&conn, subscriber_id).await {
+            Ok(()) => Page::new("subscription_confirmed"),
+            Err(_) => internal_error_page(),
+        },
     }
 }
 
+fn missing_token_page() -> Page {
+    Page::new("invalid_token").with_problem(Problem::new(Status::BadRequest, "missing_token"))
+}
+
+fn invalid_token_page() -> Page {
+    Page::new("invalid_token").with_problem(Problem::new(Status::Unauthorized, "invalid_token"))
+}
+
+fn expired_token_page() -> Page {
+    Page::new("invalid_token").with_problem(
+        Problem::new(Status::Unauthorized, "expired_token")
+            .with_detail("The confirmation link has expired, please subscribe again."),
+    )
+}
+
+fn internal_error_page() -> Page {
+    Page::new("error").with_problem(Problem::new(Status::InternalServerError, "internal_error"))
+}
+
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, conn))]
 pub async fn confirm_subscriber(
     conn: &NewsletterDbConn,
@@ -45,22 +131,42 @@ pub async fn confirm_subscriber(
     .await
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(token, conn))]
+pub enum TokenLookup {
+    Valid(Uuid),
+    Expired,
+    Unknown,
+}
+
+#[tracing::instrument(name = "Get subscriber_id from token", skip(tokens, token, conn))]
 pub async fn get_subscriber_id_from_token(
     conn: &NewsletterDbConn,
+    tokens: &SubscriptionTokens,
     token: String,
-) -> Result<Option<Uuid>, diesel::result::Error> {
+) -> Result<TokenLookup, diesel::result::Error> {
     use crate::schema::subscription_tokens::dsl::*;
-    conn.run(move |c| {
-        subscription_tokens
-            .filter(subscription_token.eq(token))
-            .first::<SubscriptionToken>(c)
-            .map(|st| st.subscriber_id)
-            .optional()
-            .map_err(|e| {
-                tracing::error!("Failed to execute query: {:?}", e);
-                e
-            })
+    let token_hash = tokens.hash(&token);
+    let ttl = tokens.ttl();
+    let found = conn
+        .run(move |c| {
+            // plaintext rows are left over from before tokens were hashed
+            subscription_tokens
+                .filter(
+                    subscription_token
+                        .eq(token_hash)
+                        .and(hashed.eq(true))
+                        .or(subscription_token.eq(token).and(hashed.eq(false))),
+                )
+                .first::<SubscriptionToken>(c)
+                .optional()
+                .map_err(|e| {
+                    tracing::error!("Failed to execute query: {:?}", e);
+                    e
+                })
+        })
+        .await?;
+    Ok(match found {
+        None => TokenLookup::Unknown,
+        Some(st) if st.created_at + ttl < Utc::now() => TokenLookup::Expired,
+        Some(st) => TokenLookup::Valid(st.subscriber_id),
     })
-    .await
 }
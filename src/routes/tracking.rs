@@ -0,0 +1,106 @@
+use crate::models::NewTrackingEvent;
+use crate::problem::Problem;
+use crate::startup::NewsletterDbConn;
+use crate::tracking::{TrackedAction, Tracker, TrackingToken};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use rocket::http::{Header, Status};
+use rocket::response::Redirect;
+use rocket::State;
+use uuid::Uuid;
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(Responder)]
+#[response(content_type = "image/gif")]
+pub struct Pixel {
+    body: &'static [u8],
+    // every load is an open; caches would hide them
+    cache_control: Header<'static>,
+}
+
+#[tracing::instrument(name = "Track an open", skip(token, conn, tracker))]
+#[get("/t/o/<token>")]
+pub async fn track_open(
+    token: &str,
+    conn: NewsletterDbConn,
+    tracker: &State<Tracker>,
+) -> Result<Pixel, Problem> {
+    let token = tracker
+        .verify(token)
+        .filter(|token| token.action == TrackedAction::Open)
+        .ok_or_else(|| Problem::from_status(Status::NotFound))?;
+    // a mail client gets its pixel whether or not the open was recorded
+    if let Err(e) = conn.run(move |c| record(c, &token)).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to record an open.");
+    }
+    Ok(Pixel {
+        body: PIXEL,
+        cache_control: Header::new("Cache-Control", "no-store, max-age=0"),
+    })
+}
+
+#[tracing::instrument(name = "Track a click", skip(token, conn, tracker))]
+#[get("/t/c/<token>")]
+pub async fn track_click(
+    token: &str,
+    conn: NewsletterDbConn,
+    tracker: &State<Tracker>,
+) -> Result<Redirect, Problem> {
+    let token = tracker
+        .verify(token)
+        .ok_or_else(|| Problem::from_status(Status::NotFound))?;
+    let position = match token.action {
+        TrackedAction::Click { position } => position,
+        TrackedAction::Open => return Err(Problem::from_status(Status::NotFound)),
+    };
+
+    let url = conn
+        .run(move |c: &mut PgConnection| {
+            use crate::schema::issue_links;
+            let url = issue_links::table
+                .select(issue_links::url)
+                .filter(issue_links::issue_id.eq(token.issue_id))
+                .filter(issue_links::position.eq(position))
+                .first::<String>(c)
+                .optional()?;
+            // the reader is sent on to the link even if the click is lost
+            if url.is_some() {
+                if let Err(e) = record(c, &token) {
+                    tracing::error!(error.cause_chain = ?e, "Failed to record a click.");
+                }
+            }
+            Ok(url)
+        })
+        .await
+        .map_err(|e: diesel::result::Error| {
+            tracing::error!(error.cause_chain = ?e, "Failed to look up a tracked link.");
+            Problem::new(Status::InternalServerError, "internal_error")
+        })?
+        .ok_or_else(|| Problem::from_status(Status::NotFound))?;
+    Ok(Redirect::found(url))
+}
+
+fn record(conn: &PgConnection, token: &TrackingToken) -> Result<(), diesel::result::Error> {
+    use crate::schema::tracking_events;
+    let (kind, link_position) = match token.action {
+        TrackedAction::Open => ("open", None),
+        TrackedAction::Click { position } => ("click", Some(position)),
+    };
+    diesel::insert_into(tracking_events::table)
+        .values(NewTrackingEvent {
+            id: &Uuid::new_v4(),
+            issue_id: &token.issue_id,
+            subscriber_id: &token.subscriber_id,
+            kind,
+            link_position,
+            occurred_at: &Utc::now(),
+        })
+        .execute(conn)?;
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use crate::audit::{Actor, AuditEntry};
+use crate::domain::{EmailPolicy, SubscriberEmail};
+use crate::guards::RequestId;
+use crate::models::{AuditedSuppression, NewSuppression, Suppression};
+use crate::problem::Problem;
+use crate::sns::{SnsError, SnsMessage, SnsVerifier};
+use crate::startup::NewsletterDbConn;
+use chrono::Utc;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, RunQueryDsl,
+};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use rocket::State;
+use uuid::Uuid;
+
+/// SNS messages are at most 256 KiB.
+const MAX_MESSAGE_SIZE: u64 = 256 * 1024;
+
+/// An SES bounce, complaint or delivery event. Identity notifications call
+/// the type `notificationType`, configuration set events `eventType`.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SesEvent {
+    #[serde(alias = "eventType")]
+    notification_type: String,
+    bounce: Option<Bounce>,
+    complaint: Option<Complaint>,
+    mail: Option<Mail>,
+}
+
+/// The email that the event is about.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Mail {
+    message_id: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Bounce {
+    bounce_type: String,
+    bounce_sub_type: Option<String>,
+    bounced_recipients: Vec<Recipient>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Complaint {
+    complained_recipients: Vec<Recipient>,
+    complaint_feedback_type: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Recipient {
+    email_address: String,
+}
+
+/// Receives SES events through an SNS subscription. SNS posts its JSON as
+/// `text/plain`, so the body is read and parsed by hand.
+#[tracing::instrument(
+    name = "Handle an SES notification",
+    skip(body, conn, verifier, email_policy, request_id),
+    fields(message_id = tracing::field::Empty, message_type = tracing::field::Empty)
+)]
+#[post("/webhooks/ses", data = "<body>")]
+pub async fn ses_webhook(
+    body: Data<'_>,
+    conn: NewsletterDbConn,
+    verifier: &State<SnsVerifier>,
+    email_policy: &State<EmailPolicy>,
+    request_id: RequestId,
+) -> Result<(), Problem> {
+    let body = body
+        .open(MAX_MESSAGE_SIZE.bytes())
+        .into_string()
+        .await
+        .map_err(|_| invalid_message("The body could not be read."))?;
+    if !body.is_complete() {
+        return Err(Problem::from_status(Status::PayloadTooLarge));
+    }
+    let message: SnsMessage = serde_json::from_str(&body)
+        .map_err(|e| invalid_message(format!("The body is not an SNS message: {}", e)))?;
+    tracing::Span::current()
+        .record("message_id", &tracing::field::display(&message.message_id))
+        .record(
+            "message_type",
+            &tracing::field::display(&message.message_type),
+        );
+
+    verifier
+        .verify(&message, Utc::now())
+        .await
+        .map_err(|e| match e {
+            SnsError::Certificate(_) => {
+                // a 5xx makes SNS retry, by which time the certificate may be reachable
+                tracing::error!(error.cause_chain = ?e, "Failed to verify an SNS message.");
+                Problem::new(Status::InternalServerError, "internal_error")
+            }
+            SnsError::UnknownMessageType(_)
+            | SnsError::MissingField(_)
+            | SnsError::InvalidTimestamp => invalid_message(e.to_string()),
+            _ => {
+                tracing::warn!(error.cause_chain = ?e, "Rejected an SNS message.");
+                Problem::new(Status::Forbidden, "untrusted_sns_message").with_detail(e.to_string())
+            }
+        })?;
+
+    match message.message_type.as_str() {
+        "SubscriptionConfirmation" => confirm_subscription(verifier, &message).await,
+        "UnsubscribeConfirmation" => {
+            tracing::warn!(topic_arn = %message.topic_arn, "The SNS subscription was removed.");
+            Ok(())
+        }
+        _ => {
+            let event: SesEvent = serde_json::from_str(&message.message)
+                .map_err(|e| invalid_message(format!("The message is not an SES event: {}", e)))?;
+            handle_event(&conn, email_policy, event, request_id)
+                .await
+                .map_err(|e| {
+                    tracing::error!(error.cause_chain = ?e, "Failed to record an SES event.");
+                    Problem::new(Status::InternalServerError, "internal_error")
+                })
+        }
+    }
+}
+
+#[tracing::instrument(name = "Confirm the SNS subscription", skip(verifier, message))]
+async fn confirm_subscription(verifier: &SnsVerifier, message: &SnsMessage) -> Result<(), Problem> {
+    // the URL is part of what SNS signed
+    let subscribe_url = message.subscribe_url.as_deref().unwrap_or_default();
+    verifier
+        .http_client()
+        .get(subscribe_url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| {
+            tracing::error!(error.cause_chain = ?e, "Failed to confirm the SNS subscription.");
+            Problem::new(Status::InternalServerError, "internal_error")
+        })?;
+    tracing::info!(topic_arn = %message.topic_arn, "Confirmed the SNS subscription.");
+    Ok(())
+}
+
+async fn handle_event(
+    conn: &NewsletterDbConn,
+    email_policy: &EmailPolicy,
+    event: SesEvent,
+    request_id: RequestId,
+) -> Result<(), diesel::result::Error> {
+    let message_id = event.mail.map(|mail| mail.message_id);
+    let (reason, status, detail, recipients) = match event.notification_type.as_str() {
+        "Bounce" => match event.bounce {
+            Some(bounce) if bounce.bounce_type == "Permanent" => (
+                "bounce",
+                "bounced",
+                bounce.bounce_sub_type,
+                bounce.bounced_recipients,
+            ),
+            _ => {
+                tracing::info!("Ignoring a bounce that is not permanent.");
+                return Ok(());
+            }
+        },
+        "Complaint" => match event.complaint {
+            Some(complaint) => (
+                "complaint",
+                "complained",
+                complaint.complaint_feedback_type,
+                complaint.complained_recipients,
+            ),
+            None => return Ok(()),
+        },
+        "Delivery" => {
+            tracing::info!("An email was delivered.");
+            return Ok(());
+        }
+        other => {
+            tracing::info!(notification_type = %other, "Ignoring an SES event.");
+            return Ok(());
+        }
+    };
+
+    let recipients: Vec<(String, String)> = recipients
+        .into_iter()
+        .map(|recipient| {
+            tracing::info!(
+                recipient = %recipient.email_address,
+                reason,
+                "Suppressing an address."
+            );
+            let canonical_email = canonicalize(email_policy, &recipient.email_address);
+            (recipient.email_address, canonical_email)
+        })
+        .collect();
+    conn.run(move |c: &mut PgConnection| {
+        use crate::schema::{issue_deliveries, subscriptions};
+        c.transaction(|| {
+            if let (Some(message_id), "bounce") = (&message_id, reason) {
+                diesel::update(
+                    issue_deliveries::table.filter(issue_deliveries::message_id.eq(message_id)),
+                )
+                .set((
+                    issue_deliveries::status.eq("bounced"),
+                    issue_deliveries::updated_at.eq(Utc::now()),
+                ))
+                .execute(c)?;
+            }
+            for (email, canonical_email) in &recipients {
+                if let Some(suppression) = suppress(c, canonical_email, reason, detail.as_deref())?
+                {
+                    AuditEntry::new(Actor::System, "suppression.added", &request_id)
+                        .with_target(suppression.id)
+                        .with_after(AuditedSuppression::from(&suppression))
+                        .record(c)?;
+                }
+                let changed = subscriptions::table
+                    .select((subscriptions::id, subscriptions::status))
+                    .filter(
+                        subscriptions::email
+                            .eq(email)
+                            .or(subscriptions::canonical_email.eq(canonical_email)),
+                    )
+                    .filter(subscriptions::status.ne(status))
+                    .for_update()
+                    .load::<(Uuid, String)>(c)?;
+                for (id, previous) in changed {
+                    diesel::update(subscriptions::table.find(id))
+                        .set(subscriptions::status.eq(status))
+                        .execute(c)?;
+                    AuditEntry::new(Actor::System, "subscriber.status_changed", &request_id)
+                        .with_target(id)
+                        .with_before(serde_json::json!({ "status": previous }))
+                        .with_after(serde_json::json!({ "status": status, "reason": reason }))
+                        .record(c)?;
+                }
+            }
+            Ok(())
+        })
+    })
+    .await
+}
+
+/// SES echoes the address that was sent to, so it should parse; one that does
+/// not is still suppressed, keyed on its lowercased form.
+fn canonicalize(email_policy: &EmailPolicy, email: &str) -> String {
+    match SubscriberEmail::parse(email.to_string()) {
+        Ok(email) => email_policy.canonicalize(&email),
+        Err(_) => email.to_lowercase(),
+    }
+}
+
+/// Adds `canonical_email` to the suppression list, returning the new entry; an
+/// address that is already on it keeps its original reason.
+fn suppress(
+    conn: &PgConnection,
+    canonical_email: &str,
+    reason: &str,
+    detail: Option<&str>,
+) -> Result<Option<Suppression>, diesel::result::Error> {
+    use crate::schema::suppressions;
+    diesel::insert_into(suppressions::table)
+        .values(NewSuppression {
+            canonical_email,
+            reason,
+            detail,
+            created_at: &Utc::now(),
+            source: "ses",
+            id: Uuid::new_v4(),
+        })
+        .on_conflict_do_nothing()
+        .get_result(conn)
+        .optional()
+}
+
+fn invalid_message(detail: impl Into<String>) -> Problem {
+    Problem::new(Status::BadRequest, "invalid_sns_message").with_detail(detail)
+}
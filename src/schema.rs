@@ -1,3 +1,77 @@
+table! {
+    api_tokens (id) {
+        id -> Uuid,
+        name -> Text,
+        token_hash -> Text,
+        token_prefix -> Text,
+        scopes -> Array<Text>,
+        created_by -> Uuid,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Int8,
+        occurred_at -> Timestamptz,
+        actor_type -> Text,
+        actor_id -> Nullable<Uuid>,
+        actor_name -> Text,
+        action -> Text,
+        target_type -> Text,
+        target_id -> Nullable<Text>,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        request_id -> Nullable<Text>,
+    }
+}
+
+table! {
+    issue_deliveries (issue_id, subscriber_id) {
+        issue_id -> Uuid,
+        subscriber_id -> Uuid,
+        status -> Text,
+        error -> Nullable<Text>,
+        message_id -> Nullable<Text>,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    issue_links (issue_id, position) {
+        issue_id -> Uuid,
+        position -> Int4,
+        url -> Text,
+    }
+}
+
+table! {
+    login_failures (kind, subject) {
+        kind -> Text,
+        subject -> Text,
+        failures -> Int4,
+        last_failure_at -> Timestamptz,
+        next_attempt_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+        attempt_started_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    newsletter_issues (id) {
+        id -> Uuid,
+        title -> Text,
+        text_content -> Text,
+        html_content -> Text,
+        published_at -> Timestamptz,
+        track_opens -> Bool,
+        track_clicks -> Bool,
+    }
+}
+
 table! {
     subscriptions (id) {
         id -> Uuid,
@@ -5,6 +79,15 @@ table! {
         name -> Text,
         subscribed_at -> Timestamptz,
         status -> Text,
+        canonical_email -> Nullable<Text>,
+    }
+}
+
+table! {
+    recovery_codes (user_id, code_hash) {
+        user_id -> Uuid,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -12,6 +95,39 @@ table! {
     subscription_tokens (subscription_token) {
         subscription_token -> Text,
         subscriber_id -> Uuid,
+        created_at -> Timestamptz,
+        hashed -> Bool,
+    }
+}
+
+table! {
+    suppressions (canonical_email) {
+        canonical_email -> Text,
+        reason -> Text,
+        detail -> Nullable<Text>,
+        created_at -> Timestamptz,
+        source -> Text,
+        id -> Uuid,
+    }
+}
+
+table! {
+    tracking_events (id) {
+        id -> Uuid,
+        issue_id -> Uuid,
+        subscriber_id -> Uuid,
+        kind -> Text,
+        link_position -> Nullable<Int4>,
+        occurred_at -> Timestamptz,
+    }
+}
+
+table! {
+    two_factor_sessions (token_hash) {
+        token_hash -> Text,
+        user_id -> Uuid,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
     }
 }
 
@@ -20,5 +136,13 @@ table! {
         user_id -> Uuid,
         username -> Text,
         password_hash -> Text,
+        role -> Text,
+        totp_secret -> Nullable<Text>,
+        totp_enabled_at -> Nullable<Timestamptz>,
+        totp_last_step -> Nullable<Int8>,
     }
 }
+
+joinable!(issue_deliveries -> subscriptions (subscriber_id));
+
+allow_tables_to_appear_in_same_query!(issue_deliveries, subscriptions);
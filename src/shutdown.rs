@@ -0,0 +1,177 @@
+use crate::configuration::ShutdownSettings;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Orbit, Request, Rocket};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Keeps track of the work in progress, so that a shutdown can wait for it to
+/// finish and report whatever did not.
+///
+/// Rocket stops accepting connections as soon as a shutdown is requested; the
+/// coordinator then gives in-flight requests and background work up to the
+/// grace period, counted from that request, before the process exits.
+#[derive(Clone)]
+pub struct ShutdownCoordinator {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    grace_period: Duration,
+    next_id: AtomicU64,
+    work: Mutex<BTreeMap<u64, String>>,
+    idle: Notify,
+    requested_at: Mutex<Option<Instant>>,
+}
+
+/// What was still running when the grace period ran out.
+#[derive(Debug)]
+pub struct ShutdownReport {
+    pub unfinished: Vec<String>,
+}
+
+impl ShutdownReport {
+    pub fn is_clean(&self) -> bool {
+        self.unfinished.is_empty()
+    }
+}
+
+/// Marks a piece of work as in progress until dropped.
+pub struct WorkGuard {
+    coordinator: ShutdownCoordinator,
+    id: u64,
+}
+
+impl WorkGuard {
+    /// Updates the description, e.g. with progress, that the shutdown report
+    /// shows if the work does not finish in time.
+    pub fn describe(&self, description: impl Into<String>) {
+        let mut work = self.coordinator.inner.work.lock().unwrap();
+        if let Some(entry) = work.get_mut(&self.id) {
+            *entry = description.into();
+        }
+    }
+}
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        let mut work = self.coordinator.inner.work.lock().unwrap();
+        work.remove(&self.id);
+        if work.is_empty() {
+            self.coordinator.inner.idle.notify_waiters();
+        }
+    }
+}
+
+impl ShutdownCoordinator {
+    pub fn new(settings: &ShutdownSettings) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                grace_period: Duration::from_secs(settings.grace_period_seconds),
+                next_id: AtomicU64::new(0),
+                work: Mutex::new(BTreeMap::new()),
+                idle: Notify::new(),
+                requested_at: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        self.inner.grace_period
+    }
+
+    pub fn track(&self, description: impl Into<String>) -> WorkGuard {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .work
+            .lock()
+            .unwrap()
+            .insert(id, description.into());
+        WorkGuard {
+            coordinator: self.clone(),
+            id,
+        }
+    }
+
+    pub fn in_flight(&self) -> Vec<String> {
+        self.inner.work.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.requested_at.lock().unwrap().is_some()
+    }
+
+    fn request(&self) {
+        self.inner
+            .requested_at
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+    }
+
+    /// Waits for the tracked work to finish, for at most the rest of the
+    /// grace period, and reports what is left.
+    pub async fn drain(&self) -> ShutdownReport {
+        self.request();
+        let requested_at = self.inner.requested_at.lock().unwrap().unwrap();
+        let deadline = tokio::time::Instant::from_std(requested_at + self.inner.grace_period);
+        loop {
+            // registered before checking, so a notification in between is not lost
+            let idle = self.inner.idle.notified();
+            if self.inner.work.lock().unwrap().is_empty() {
+                break;
+            }
+            if tokio::time::timeout_at(deadline, idle).await.is_err() {
+                break;
+            }
+        }
+
+        let report = ShutdownReport {
+            unfinished: self.in_flight(),
+        };
+        if report.is_clean() {
+            tracing::info!("Shut down after all in-flight work finished.");
+        } else {
+            tracing::warn!(
+                unfinished = ?report.unfinished,
+                "Shut down with work left unfinished after the grace period."
+            );
+        }
+        report
+    }
+}
+
+struct InFlightRequest {
+    _guard: WorkGuard,
+}
+
+#[rocket::async_trait]
+impl Fairing for ShutdownCoordinator {
+    fn info(&self) -> Info {
+        Info {
+            name: "Shutdown coordinator",
+            kind: Kind::Liftoff | Kind::Request,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let shutdown = rocket.shutdown();
+        let coordinator = self.clone();
+        tokio::spawn(async move {
+            shutdown.await;
+            tracing::info!("Shutdown requested, no longer accepting connections.");
+            coordinator.request();
+        });
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        // the query is left out, as it may carry tokens
+        let description = format!("{} {}", request.method(), request.uri().path());
+        // dropped along with the request, once its response has been sent
+        request.local_cache(|| InFlightRequest {
+            _guard: self.track(description),
+        });
+    }
+}
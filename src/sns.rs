@@ -0,0 +1,377 @@
+use crate::configuration::SesWebhookSettings;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::sign::Verifier;
+use openssl::x509::X509;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How old a message may be. SNS retries failed deliveries well within this,
+/// so anything older is a replay of a message that was captured earlier.
+const MAX_MESSAGE_AGE_MINUTES: i64 = 60;
+/// How far ahead of our clock SNS's may be.
+const MAX_CLOCK_SKEW_MINUTES: i64 = 5;
+
+/// A message that Amazon SNS POSTs to an HTTP(S) subscription.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SnsMessage {
+    #[serde(rename = "Type")]
+    pub message_type: String,
+    pub message_id: String,
+    pub topic_arn: String,
+    pub subject: Option<String>,
+    pub message: String,
+    pub timestamp: String,
+    pub signature_version: String,
+    pub signature: String,
+    #[serde(rename = "SigningCertURL")]
+    pub signing_cert_url: String,
+    #[serde(rename = "SubscribeURL")]
+    pub subscribe_url: Option<String>,
+    pub token: Option<String>,
+}
+
+impl SnsMessage {
+    /// The canonical form that SNS signs, which depends on the message type.
+    pub fn string_to_sign(&self) -> Result<String, SnsError> {
+        let fields: Vec<(&str, Option<&str>)> = match self.message_type.as_str() {
+            "Notification" => vec![
+                ("Message", Some(&self.message)),
+                ("MessageId", Some(&self.message_id)),
+                ("Subject", self.subject.as_deref()),
+                ("Timestamp", Some(&self.timestamp)),
+                ("TopicArn", Some(&self.topic_arn)),
+                ("Type", Some(&self.message_type)),
+            ],
+            "SubscriptionConfirmation" | "UnsubscribeConfirmation" => vec![
+                ("Message", Some(&self.message)),
+                ("MessageId", Some(&self.message_id)),
+                (
+                    "SubscribeURL",
+                    Some(
+                        self.subscribe_url
+                            .as_deref()
+                            .ok_or(SnsError::MissingField("SubscribeURL"))?,
+                    ),
+                ),
+                ("Timestamp", Some(&self.timestamp)),
+                (
+                    "Token",
+                    Some(
+                        self.token
+                            .as_deref()
+                            .ok_or(SnsError::MissingField("Token"))?,
+                    ),
+                ),
+                ("TopicArn", Some(&self.topic_arn)),
+                ("Type", Some(&self.message_type)),
+            ],
+            other => return Err(SnsError::UnknownMessageType(other.to_string())),
+        };
+        Ok(fields
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{}\n{}\n", name, value)))
+            .collect())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnsError {
+    #[error("Messages from topic {0} are not accepted.")]
+    UnknownTopic(String),
+    #[error("Unknown message type {0}.")]
+    UnknownMessageType(String),
+    #[error("The message has no {0}.")]
+    MissingField(&'static str),
+    #[error("The signing certificate is not hosted by SNS: {0}")]
+    UntrustedCertificateUrl(String),
+    #[error("Unsupported signature version {0}.")]
+    UnsupportedSignatureVersion(String),
+    #[error("The signature does not match the message.")]
+    InvalidSignature,
+    #[error("The message has an invalid Timestamp.")]
+    InvalidTimestamp,
+    #[error("The message was sent at {0}, which is too long ago or in the future.")]
+    OutOfDate(DateTime<Utc>),
+    #[error("Failed to load the signing certificate.")]
+    Certificate(#[source] anyhow::Error),
+}
+
+/// Checks that SNS messages were signed by AWS and come from one of the
+/// configured topics.
+pub struct SnsVerifier {
+    topic_arns: Vec<String>,
+    certificates_dir: Option<PathBuf>,
+    http_client: reqwest::Client,
+    // SNS signs with the same few certificates, so there is no eviction
+    certificates: Mutex<HashMap<String, X509>>,
+}
+
+impl SnsVerifier {
+    pub fn new(settings: &SesWebhookSettings) -> Self {
+        Self {
+            topic_arns: settings.topic_arns.clone(),
+            certificates_dir: settings.certificates_dir.clone(),
+            http_client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to build the HTTP client for SNS."),
+            certificates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Also used to confirm subscriptions, as the URL to visit comes in a
+    /// message that has been verified.
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    /// Checks the signature, then that the message was sent recently as of
+    /// `now`, so that a captured message cannot be replayed later on.
+    pub async fn verify(&self, message: &SnsMessage, now: DateTime<Utc>) -> Result<(), SnsError> {
+        if !self.topic_arns.contains(&message.topic_arn) {
+            return Err(SnsError::UnknownTopic(message.topic_arn.clone()));
+        }
+        let digest = match message.signature_version.as_str() {
+            "1" => MessageDigest::sha1(),
+            "2" => MessageDigest::sha256(),
+            other => return Err(SnsError::UnsupportedSignatureVersion(other.to_string())),
+        };
+        let string_to_sign = message.string_to_sign()?;
+        let signature =
+            base64::decode(&message.signature).map_err(|_| SnsError::InvalidSignature)?;
+        let certificate = self.certificate(&message.signing_cert_url).await?;
+
+        let public_key = certificate
+            .public_key()
+            .map_err(|e| SnsError::Certificate(e.into()))?;
+        let valid = Verifier::new(digest, &public_key)
+            .and_then(|mut verifier| {
+                verifier.update(string_to_sign.as_bytes())?;
+                verifier.verify(&signature)
+            })
+            .unwrap_or(false);
+        if !valid {
+            return Err(SnsError::InvalidSignature);
+        }
+
+        let sent_at = DateTime::parse_from_rfc3339(&message.timestamp)
+            .map_err(|_| SnsError::InvalidTimestamp)?
+            .with_timezone(&Utc);
+        let age = now - sent_at;
+        if age > chrono::Duration::minutes(MAX_MESSAGE_AGE_MINUTES)
+            || -age > chrono::Duration::minutes(MAX_CLOCK_SKEW_MINUTES)
+        {
+            return Err(SnsError::OutOfDate(sent_at));
+        }
+        Ok(())
+    }
+
+    async fn certificate(&self, url: &str) -> Result<X509, SnsError> {
+        let parsed = Url::parse(url)
+            .ok()
+            .filter(is_sns_certificate_url)
+            .ok_or_else(|| SnsError::UntrustedCertificateUrl(url.to_string()))?;
+        if let Some(certificate) = self.certificates.lock().unwrap().get(url) {
+            return Ok(certificate.clone());
+        }
+
+        let pem = match &self.certificates_dir {
+            Some(dir) => {
+                let file_name = parsed
+                    .path_segments()
+                    .and_then(|mut s| s.next_back())
+                    .unwrap_or("");
+                std::fs::read(dir.join(file_name))
+                    .with_context(|| format!("Failed to read {} from {:?}.", file_name, dir))
+            }
+            None => self.download(parsed).await,
+        }
+        .map_err(SnsError::Certificate)?;
+        let certificate = X509::from_pem(&pem)
+            .context("Failed to parse the signing certificate.")
+            .and_then(|certificate| {
+                let now = Asn1Time::days_from_now(0)?;
+                if certificate.not_before() > now || certificate.not_after() < now {
+                    return Err(anyhow!(
+                        "The signing certificate is not valid at this time."
+                    ));
+                }
+                Ok(certificate)
+            })
+            .map_err(SnsError::Certificate)?;
+
+        self.certificates
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), certificate.clone());
+        Ok(certificate)
+    }
+
+    async fn download(&self, url: Url) -> Result<Vec<u8>, anyhow::Error> {
+        let response = self.http_client.get(url).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+/// SNS serves its signing certificates over https from `sns.<region>.amazonaws.com`.
+fn is_sns_certificate_url(url: &Url) -> bool {
+    let region = url
+        .host_str()
+        .and_then(|host| {
+            let rest = host.strip_prefix("sns.")?;
+            rest.strip_suffix(".amazonaws.com")
+                .or_else(|| rest.strip_suffix(".amazonaws.com.cn"))
+        })
+        .filter(|region| {
+            !region.is_empty()
+                && region
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        });
+    url.scheme() == "https"
+        && url.port().is_none()
+        && url.username().is_empty()
+        && region.is_some()
+        && url.path().ends_with(".pem")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use openssl::x509::X509NameBuilder;
+
+    fn notification() -> SnsMessage {
+        SnsMessage {
+            message_type: "Notification".into(),
+            message_id: "22b80b92-fdea-4c2c-8f9d-bdfb0c7bf324".into(),
+            topic_arn: "arn:aws:sns:us-west-2:123456789012:ses-events".into(),
+            subject: None,
+            message: "{}".into(),
+            timestamp: "2022-01-14T18:10:22.123Z".into(),
+            signature_version: "2".into(),
+            signature: String::new(),
+            signing_cert_url:
+                "https://sns.us-west-2.amazonaws.com/SimpleNotificationService-test.pem".into(),
+            subscribe_url: None,
+            token: None,
+        }
+    }
+
+    #[test]
+    fn the_subject_is_only_signed_when_present() {
+        let mut message = notification();
+        assert!(!message.string_to_sign().unwrap().contains("Subject"));
+
+        message.subject = Some("Amazon SES Email Event Notification".into());
+        assert!(message
+            .string_to_sign()
+            .unwrap()
+            .contains("\nSubject\nAmazon SES Email Event Notification\n"));
+    }
+
+    #[test]
+    fn confirmations_must_carry_their_token() {
+        let mut message = notification();
+        message.message_type = "SubscriptionConfirmation".into();
+        message.subscribe_url = Some("https://sns.us-west-2.amazonaws.com/?Action=Confirm".into());
+        assert_err!(message.string_to_sign());
+
+        message.token = Some("2336412f37".into());
+        assert_ok!(message.string_to_sign());
+    }
+
+    #[test]
+    fn only_sns_hosts_can_serve_certificates() {
+        let accepted = |url: &str| is_sns_certificate_url(&Url::parse(url).unwrap());
+        assert!(accepted(
+            "https://sns.eu-west-1.amazonaws.com/SimpleNotificationService-1.pem"
+        ));
+        assert!(accepted("https://sns.cn-north-1.amazonaws.com.cn/cert.pem"));
+        assert!(!accepted("http://sns.eu-west-1.amazonaws.com/cert.pem"));
+        assert!(!accepted(
+            "https://sns.eu-west-1.amazonaws.com.evil.com/cert.pem"
+        ));
+        assert!(!accepted(
+            "https://evil.com/sns.eu-west-1.amazonaws.com/cert.pem"
+        ));
+        assert!(!accepted(
+            "https://sns.eu-west-1.amazonaws.com:8443/cert.pem"
+        ));
+        assert!(!accepted("https://s3.amazonaws.com/cert.pem"));
+    }
+
+    #[tokio::test]
+    async fn signatures_are_checked_against_the_certificate() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "sns.amazonaws.com")
+            .unwrap();
+        let name = name.build();
+        let mut certificate = X509::builder().unwrap();
+        certificate.set_subject_name(&name).unwrap();
+        certificate.set_issuer_name(&name).unwrap();
+        certificate.set_pubkey(&key).unwrap();
+        certificate
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        certificate
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        certificate.sign(&key, MessageDigest::sha256()).unwrap();
+        let certificate = certificate.build();
+
+        let mut message = notification();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        signer
+            .update(message.string_to_sign().unwrap().as_bytes())
+            .unwrap();
+        message.signature = base64::encode(signer.sign_to_vec().unwrap());
+
+        let verifier = SnsVerifier::new(&SesWebhookSettings {
+            topic_arns: vec![message.topic_arn.clone()],
+            certificates_dir: None,
+        });
+        verifier
+            .certificates
+            .lock()
+            .unwrap()
+            .insert(message.signing_cert_url.clone(), certificate);
+        let sent_at = DateTime::parse_from_rfc3339(&message.timestamp).unwrap();
+        let now = sent_at.with_timezone(&Utc) + chrono::Duration::minutes(1);
+        assert_ok!(verifier.verify(&message, now).await);
+
+        let much_later = now + chrono::Duration::hours(2);
+        assert!(matches!(
+            verifier.verify(&message, much_later).await,
+            Err(SnsError::OutOfDate(_))
+        ));
+        let much_earlier = now - chrono::Duration::hours(2);
+        assert!(matches!(
+            verifier.verify(&message, much_earlier).await,
+            Err(SnsError::OutOfDate(_))
+        ));
+
+        message.message = r#"{"tampered": true}"#.into();
+        assert!(matches!(
+            verifier.verify(&message, now).await,
+            Err(SnsError::InvalidSignature)
+        ));
+
+        message.topic_arn = "arn:aws:sns:us-west-2:210987654321:ses-events".into();
+        assert!(matches!(
+            verifier.verify(&message, now).await,
+            Err(SnsError::UnknownTopic(_))
+        ));
+    }
+}
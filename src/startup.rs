@@ -1,78 +1,242 @@
+use crate::canonical_emails::recanonicalize_on_startup;
 use crate::catchers::*;
-use crate::configuration::Settings;
+use crate::clock::Clock;
+use crate::configuration::{DatabaseSettings, Settings};
+use crate::cors::Cors;
 use crate::diesel::Connection;
-use crate::email::Email;
+use crate::domain::{EmailPolicy, SubscriptionTokens, TotpSecretKey};
+use crate::email::{Email, MeteredEmailClient, SuppressingEmailClient};
+use crate::guards::{MetricsAccess, RequestIdHeader};
+use crate::login_throttle::LoginThrottle;
+use crate::metrics::{RequestMetrics, DB_POOL_CHECKOUT_DURATION};
+use crate::pages::Branding;
+use crate::password_hashing::PasswordHashing;
 use crate::port_saver;
 use crate::port_saver::Port;
 use crate::routes::*;
+use crate::shutdown::{ShutdownCoordinator, ShutdownReport};
+use crate::sns::SnsVerifier;
+use crate::telemetry::spawn_blocking_with_tracing;
+use crate::trace_context::{traced, TraceContext};
+use crate::tracking::Tracker;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use diesel::PgConnection;
-use rocket::fairing::Fairing;
-use rocket::figment::{
-    util::map,
-    value::{Map, Value},
-};
-use rocket::{Config, Ignite, Rocket};
-use rocket_sync_db_pools::{database, diesel, ConnectionPool};
-use std::sync::Arc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Config, Ignite, Request, Rocket};
+use rocket_dyn_templates::Template;
+use secrecy::ExposeSecret;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub struct Application {
     pub port: Port,
     pub server: Rocket<Ignite>,
+    pub shutdown: ShutdownCoordinator,
 }
 
 impl Application {
     pub async fn build(
         settings: &Settings,
         email_client: Arc<dyn Email>,
+        clock: Arc<dyn Clock>,
     ) -> Result<Self, rocket::Error> {
         let (port_saver, port) = port_saver::create_pair();
-        let db: Map<_, Value> = map! {
-            "url" => settings.database.connection_string().into()
-        };
+        let shutdown = ShutdownCoordinator::new(&settings.shutdown);
+        let email_policy =
+            EmailPolicy::new(&settings.email_policy).expect("Failed to load the email policy.");
+        let password_hashing = PasswordHashing::new(&settings.password_hashing)
+            .expect("Failed to set up password hashing.");
+        let db_pool = NewsletterDbPool::new(&settings.database);
+        recanonicalize_on_startup(&db_pool, &email_policy).await;
+        let email_client = SuppressingEmailClient::new(
+            Arc::new(MeteredEmailClient::new(email_client)),
+            db_pool.clone(),
+            email_policy.clone(),
+        );
         rocket::build()
             .configure(
                 Config::figment()
-                    .merge((
-                        "databases",
-                        map![settings.database.database_name.clone() => db],
-                    ))
+                    .merge(("template_dir", settings.branding.templates_dir.clone()))
                     .merge(Config {
                         port: settings.application.port.unwrap_or(0),
                         address: settings.application.host,
+                        // in-flight requests keep their connections for the whole grace period
+                        shutdown: rocket::config::Shutdown {
+                            grace: settings.shutdown.grace_period_seconds.min(u32::MAX.into())
+                                as u32,
+                            ..Default::default()
+                        },
                         ..Config::default()
                     }),
             )
             .attach(port_saver)
-            .attach(NewsletterDbConn::named_fairing(
-                settings.database.database_name.clone(),
-            ))
-            .manage(email_client)
+            .attach(shutdown.clone())
+            .attach(RequestIdHeader)
+            .attach(RequestMetrics)
+            .attach(TraceContext)
+            .attach(Cors::new(&settings.cors))
+            .attach(Template::fairing())
+            .manage(db_pool)
+            .manage::<Arc<dyn Email>>(Arc::new(email_client))
+            .manage(MetricsAccess::new(&settings.metrics))
+            .manage(email_policy)
+            .manage(SubscriptionTokens::new(&settings.subscription_tokens))
+            .manage(Branding::new(&settings.branding))
             .manage(ApplicationBaseUrl(settings.application.base_url.clone()))
-            .mount("/", routes![health, subscribe, confirm, publish_newsletter])
+            .manage(CheckEmailHealth(settings.health.check_email))
+            .manage(shutdown.clone())
+            .manage(SnsVerifier::new(&settings.ses_webhook))
+            .manage(LoginThrottle::new(&settings.login_throttle))
+            .manage(password_hashing)
+            .manage(TotpSecretKey::new(&settings.two_factor.encryption_key))
+            .manage(TwoFactorSessionLifetime(chrono::Duration::minutes(
+                settings.two_factor.session_minutes,
+            )))
+            .manage(clock)
+            .manage(Tracker::new(
+                &settings.tracking,
+                settings.application.base_url.clone(),
+            ))
+            .mount(
+                "/",
+                traced(routes![
+                    health,
+                    liveness,
+                    readiness,
+                    subscribe_form,
+                    subscribe,
+                    confirm,
+                    confirm_submission,
+                    publish_newsletter,
+                    track_open,
+                    track_click,
+                    render_metrics,
+                    get_log_level,
+                    put_log_level,
+                    create_api_token,
+                    list_api_tokens,
+                    revoke_api_token,
+                    list_users,
+                    put_user_role,
+                    list_login_throttles,
+                    unlock_logins,
+                    list_audit_log,
+                    enroll_two_factor,
+                    confirm_two_factor,
+                    disable_two_factor,
+                    start_two_factor_session,
+                    list_suppressions,
+                    add_suppression,
+                    remove_suppression,
+                    issue_stats,
+                    issue_deliveries,
+                    ses_webhook
+                ]),
+            )
             .register(
                 "/",
                 catchers![
                     unprocessable_entity_to_bad_request,
-                    unauthorized_request_credentials
+                    unauthorized_request_credentials,
+                    problem_details
                 ],
             )
             .ignite()
             .await
-            .map(|server| Application { port, server })
+            .map(|server| Application {
+                port,
+                server,
+                shutdown,
+            })
+    }
+
+    /// Serves requests until a shutdown is requested, e.g. by SIGTERM, then
+    /// waits out the in-flight work for at most the grace period.
+    pub async fn run_until_stopped(self) -> Result<ShutdownReport, rocket::Error> {
+        self.server.launch().await?;
+        Ok(self.shutdown.drain().await)
     }
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
-#[database("newsletter")]
-pub struct NewsletterDbConn(diesel::PgConnection);
+/// How long a session started with a one-time code lasts.
+pub struct TwoFactorSessionLifetime(pub chrono::Duration);
 
-impl NewsletterDbConn {
-    pub fn named_fairing(database_name: String) -> impl Fairing {
-        let pool_name = Box::leak(Box::new(format!("'{}' Database Pool", database_name)));
-        let database_name = Box::leak(Box::new(database_name));
+pub struct CheckEmailHealth(pub bool);
+
+/// The database connection pool, shared by requests and by work outside of
+/// them, such as the suppression check in front of the email client.
+#[derive(Clone)]
+pub struct NewsletterDbPool(Pool<ConnectionManager<PgConnection>>);
 
-        <ConnectionPool<Self, diesel::PgConnection>>::fairing(pool_name, database_name)
+impl NewsletterDbPool {
+    /// Connects lazily, so that the app starts, and reports that it is not
+    /// ready, while the database is down.
+    pub fn new(settings: &DatabaseSettings) -> Self {
+        let pool = Pool::builder()
+            // the size Rocket gives its own database pools
+            .max_size(Config::default().workers as u32 * 4)
+            .min_idle(Some(0))
+            .connection_timeout(Duration::from_secs(5))
+            .build_unchecked(ConnectionManager::new(
+                settings.connection_string().expose_secret(),
+            ));
+        Self(pool)
+    }
+
+    /// Checks out a connection, recording how long that took.
+    pub async fn get(&self) -> Result<NewsletterDbConn, anyhow::Error> {
+        let pool = self.0.clone();
+        let timer = DB_POOL_CHECKOUT_DURATION.start_timer();
+        let conn = spawn_blocking_with_tracing(move || pool.get()).await??;
+        timer.observe_duration();
+        Ok(NewsletterDbConn(Arc::new(Mutex::new(conn))))
+    }
+}
+
+/// A connection checked out of `NewsletterDbPool`, which queries run on
+/// through `run`, off the async workers.
+pub struct NewsletterDbConn(Arc<Mutex<PooledConnection<ConnectionManager<PgConnection>>>>);
+
+#[async_trait]
+impl<'r> FromRequest<'r> for NewsletterDbConn {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let pool = match request.rocket().state::<NewsletterDbPool>() {
+            Some(pool) => pool,
+            None => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        match pool.get().await {
+            Ok(conn) => Outcome::Success(conn),
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to check out a database connection.");
+                Outcome::Failure((Status::ServiceUnavailable, ()))
+            }
+        }
+    }
+}
+
+impl NewsletterDbConn {
+    pub async fn run<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut diesel::PgConnection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.0.clone();
+        let ran = spawn_blocking_with_tracing(move || {
+            let mut conn = conn
+                .lock()
+                .expect("A query panicked while holding the connection.");
+            f(&mut conn)
+        })
+        .await;
+        match ran {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
     }
 
     pub async fn run_transaction<T, E, F, G>(&self, f: F, error_mapper: G) -> Result<T, E>
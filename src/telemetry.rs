@@ -1,27 +1,57 @@
+use crate::configuration::{OtlpSettings, RedactionMode, TelemetrySettings};
+use crate::log_filter::LogFilter;
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{Sampler, Tracer};
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::trace::TraceError;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use std::any::TypeId;
+use std::fmt::{Debug, Display, Formatter};
 use tokio::task::JoinHandle;
-use tracing::subscriber::set_global_default;
-use tracing::Subscriber;
+use tracing::field::{DisplayValue, Field, FieldSet, Value, ValueSet, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::{set_global_default, Interest};
+use tracing::{Event, Metadata, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::fmt::MakeWriter;
-use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{layer::SubscriberExt, reload, EnvFilter, Registry};
 
+/// Bunyan logs, plus OpenTelemetry spans given a `tracer` from
+/// `init_otlp_tracer`, behind a filter that `LogFilter::global` can change at
+/// runtime, with personal data redacted.
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let directives = env_filter.to_string();
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+    LogFilter::install(handle, directives);
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let otel_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
 
     Registry::default()
         .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(RedactionLayer::new(
+            JsonStorageLayer
+                .and_then(formatting_layer)
+                .and_then(otel_layer),
+        ))
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
@@ -29,6 +59,33 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
 
+/// Builds a tracer that exports spans in batches over OTLP/HTTP, and installs
+/// the W3C trace context propagator used for incoming and outgoing requests.
+pub fn init_otlp_tracer(settings: &OtlpSettings) -> Result<Tracer, TraceError> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        settings.sampling_ratio,
+    )));
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(settings.endpoint.clone()),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    settings.service_name.clone(),
+                )])),
+        )
+        // exports from its own thread, independent of the runtime that is current here
+        .install_batch(opentelemetry::runtime::TokioCurrentThread)
+}
+
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 where
     F: FnOnce() -> R + Send + 'static,
@@ -37,3 +94,364 @@ where
     let current_span = tracing::Span::current();
     tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+static REDACTION: OnceCell<Redaction> = OnceCell::new();
+
+/// How personal data is redacted, both by the subscriber and by `Pii`.
+pub struct Redaction {
+    mode: RedactionMode,
+    key: Option<Secret<String>>,
+    fields: Vec<String>,
+}
+
+impl Redaction {
+    pub fn new(settings: &TelemetrySettings) -> Self {
+        Self {
+            mode: settings.redaction,
+            key: settings.redaction_key.clone(),
+            fields: settings.pii_fields.clone(),
+        }
+    }
+
+    /// Applies to redaction from now on; only the first call has an effect.
+    /// Until then the defaults apply, which mask personal data.
+    pub fn install(self) {
+        let _ = REDACTION.set(self);
+    }
+
+    fn current() -> &'static Redaction {
+        REDACTION.get_or_init(|| Redaction::new(&TelemetrySettings::default()))
+    }
+
+    fn is_pii(&self, field: &str) -> bool {
+        self.fields.iter().any(|pii| pii == field)
+    }
+
+    fn redact(&self, value: &str) -> String {
+        match self.mode {
+            RedactionMode::Off => value.to_string(),
+            RedactionMode::Hash => {
+                // the configuration requires a key in this mode
+                let key = self.key.as_ref().map(|key| key.expose_secret().as_bytes());
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.unwrap_or_default())
+                    .expect("HMAC can take a key of any size");
+                mac.update(value.as_bytes());
+                let digest = hex::encode(mac.finalize().into_bytes());
+                format!("hmac:{}", &digest[..16])
+            }
+            RedactionMode::Mask => {
+                let (head, domain) = match value.rsplit_once('@') {
+                    Some((local_part, domain)) => (local_part, Some(domain)),
+                    None => (value, None),
+                };
+                let mut masked: String = head.chars().take(1).collect();
+                masked.push_str("***");
+                if let Some(domain) = domain {
+                    masked.push('@');
+                    masked.push_str(domain);
+                }
+                masked
+            }
+        }
+    }
+}
+
+/// Tags a value as personal data. Both `Display` and `Debug` apply the
+/// configured redaction, so it is safe to interpolate into messages, e.g. of
+/// errors, that are not recorded under a field of their own.
+pub struct Pii<T>(pub T);
+
+impl<T: AsRef<str>> Display for Pii<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&Redaction::current().redact(self.0.as_ref()))
+    }
+}
+
+impl<T: AsRef<str>> Debug for Pii<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", Redaction::current().redact(self.0.as_ref()))
+    }
+}
+
+/// Hands spans and events to `inner` with the values of personal data fields,
+/// as configured by `TelemetrySettings::pii_fields`, redacted.
+pub struct RedactionLayer<L> {
+    inner: L,
+}
+
+impl<L> RedactionLayer<L> {
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, L> Layer<S> for RedactionLayer<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        if !has_pii(metadata.fields()) {
+            return self.inner.on_new_span(attrs, id, ctx);
+        }
+        let recorded = RecordedValues::of(|visitor| attrs.record(visitor));
+        recorded.with_value_set(metadata.fields(), |values| {
+            let attrs = if attrs.is_root() {
+                Attributes::new_root(metadata, values)
+            } else if let Some(parent) = attrs.parent() {
+                Attributes::child_of(parent.clone(), metadata, values)
+            } else {
+                Attributes::new(metadata, values)
+            };
+            self.inner.on_new_span(&attrs, id, ctx)
+        })
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let fields = match ctx.metadata(span) {
+            Some(metadata) if has_pii(metadata.fields()) => metadata.fields(),
+            _ => return self.inner.on_record(span, values, ctx),
+        };
+        let recorded = RecordedValues::of(|visitor| values.record(visitor));
+        recorded.with_value_set(fields, |values| {
+            self.inner.on_record(span, &Record::new(values), ctx)
+        })
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !has_pii(metadata.fields()) {
+            return self.inner.on_event(event, ctx);
+        }
+        let recorded = RecordedValues::of(|visitor| event.record(visitor));
+        recorded.with_value_set(metadata.fields(), |values| {
+            let event = if event.is_root() {
+                Event::new_child_of(None, metadata, values)
+            } else if let Some(parent) = event.parent() {
+                Event::new_child_of(parent.clone(), metadata, values)
+            } else {
+                Event::new(metadata, values)
+            };
+            self.inner.on_event(&event, ctx)
+        })
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx)
+    }
+
+    // lets `OpenTelemetrySpanExt` find the OpenTelemetry layer inside
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+fn has_pii(fields: &FieldSet) -> bool {
+    let redaction = Redaction::current();
+    fields.iter().any(|field| redaction.is_pii(field.name()))
+}
+
+/// A copy of recorded field values, with personal data redacted. Values other
+/// than numbers and booleans are kept as the text they format to, which is
+/// how the layers we pass them on to record them anyway.
+struct RecordedValues(Vec<(Field, RecordedValue)>);
+
+enum RecordedValue {
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+    Text(DisplayValue<String>),
+}
+
+impl RecordedValues {
+    fn of(record: impl FnOnce(&mut dyn Visit)) -> Self {
+        let mut recorded = Self(Vec::new());
+        record(&mut recorded);
+        recorded
+    }
+
+    fn with_value_set<R>(&self, fields: &FieldSet, f: impl FnOnce(&ValueSet<'_>) -> R) -> R {
+        // a value set is built from an array; tracing allows at most 32 fields
+        let mut values: [(&Field, Option<&dyn Value>); 32] = match self.0.first() {
+            Some((field, _)) => [(field, None); 32],
+            None => return f(&fields.value_set(&[])),
+        };
+        for (slot, (field, value)) in values.iter_mut().zip(&self.0) {
+            let value: &dyn Value = match value {
+                RecordedValue::I64(v) => v,
+                RecordedValue::U64(v) => v,
+                RecordedValue::F64(v) => v,
+                RecordedValue::Bool(v) => v,
+                RecordedValue::Text(v) => v,
+            };
+            *slot = (field, Some(value));
+        }
+        f(&fields.value_set(&values))
+    }
+
+    fn push_text(&mut self, field: &Field, text: String) {
+        let redaction = Redaction::current();
+        let text = if redaction.is_pii(field.name()) {
+            redaction.redact(&text)
+        } else {
+            text
+        };
+        self.0.push((
+            field.clone(),
+            RecordedValue::Text(tracing::field::display(text)),
+        ));
+    }
+}
+
+impl Visit for RecordedValues {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.push((field.clone(), RecordedValue::F64(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.clone(), RecordedValue::I64(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.clone(), RecordedValue::U64(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.clone(), RecordedValue::Bool(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push_text(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.push_text(field, format!("{:?}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Redaction, RedactionLayer};
+    use crate::configuration::{RedactionMode, TelemetrySettings};
+    use secrecy::Secret;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::Registry;
+
+    fn redaction(mode: RedactionMode, key: Option<&str>) -> Redaction {
+        Redaction::new(&TelemetrySettings {
+            redaction: mode,
+            redaction_key: key.map(|key| Secret::new(key.to_string())),
+            ..TelemetrySettings::default()
+        })
+    }
+
+    #[test]
+    fn masking_keeps_only_the_first_character_and_the_email_domain() {
+        let mask = redaction(RedactionMode::Mask, None);
+        assert_eq!(mask.redact("ursula@gmail.com"), "u***@gmail.com");
+        assert_eq!(mask.redact("le guin"), "l***");
+        assert_eq!(mask.redact(""), "***");
+    }
+
+    #[test]
+    fn hashing_is_stable_keyed_and_hides_the_value() {
+        let hash = redaction(RedactionMode::Hash, Some("key"));
+        let hashed = hash.redact("ursula@gmail.com");
+        assert_eq!(hashed, hash.redact("ursula@gmail.com"));
+        assert!(hashed.starts_with("hmac:"));
+        assert!(!hashed.contains("ursula"));
+        let other_key = redaction(RedactionMode::Hash, Some("another key"));
+        assert_ne!(hashed, other_key.redact("ursula@gmail.com"));
+    }
+
+    #[test]
+    fn redaction_can_be_turned_off() {
+        let off = redaction(RedactionMode::Off, None);
+        assert_eq!(off.redact("ursula@gmail.com"), "ursula@gmail.com");
+    }
+
+    /// Keeps the text of every value it is shown.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let text = format!("{}={:?}", field.name(), value);
+            self.0.lock().unwrap().push(text);
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Recorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    #[test]
+    fn the_layer_redacts_personal_data_fields_of_spans_and_events() {
+        let recorder = Recorder::default();
+        let subscriber = Registry::default().with(RedactionLayer::new(recorder.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span =
+                tracing::info_span!("subscribe", subscriber_email = "ursula@gmail.com").entered();
+            tracing::info!(recipient = %"ursula@gmail.com", attempt = 2, "Sending.");
+        });
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "subscriber_email=u***@gmail.com",
+                "message=Sending.",
+                "recipient=u***@gmail.com",
+                "attempt=2",
+            ]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::guards::RequestId;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::{global, Context};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::HeaderMap;
+use rocket::route::{Handler, Outcome};
+use rocket::{Data, Request, Route};
+use std::borrow::Cow;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Extracts the W3C `traceparent` of incoming requests, so that the spans of
+/// the request become part of the caller's trace.
+pub struct TraceContext;
+
+struct RemoteContext(Context);
+
+#[rocket::async_trait]
+impl Fairing for TraceContext {
+    fn info(&self) -> Info {
+        Info {
+            name: "Trace context",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&RocketHeaders(request.headers()))
+        });
+        request.local_cache(|| RemoteContext(context));
+    }
+}
+
+struct RocketHeaders<'a, 'h>(&'a HeaderMap<'h>);
+
+impl Extractor for RocketHeaders<'_, '_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get_one(key)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter_map(|header| match header.name.into_cow() {
+                Cow::Borrowed(name) => Some(name),
+                Cow::Owned(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// Runs each handler, guards included, inside a root span for the request that
+/// carries its request id, and whose parent is the context extracted by
+/// `TraceContext`. Work spawned with `spawn_blocking_with_tracing` or
+/// instrumented with the current span inherits both.
+pub fn traced(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(TracedHandler(route.handler));
+            route
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+struct TracedHandler(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for TracedHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let route = request.route().map(|route| route.uri.path()).unwrap_or("");
+        let span = tracing::info_span!(
+            "HTTP request",
+            http.method = %request.method(),
+            http.route = %route,
+            request_id = %RequestId::get(request),
+        );
+        let remote = request.local_cache(|| RemoteContext(Context::new()));
+        span.set_parent(remote.0.clone());
+        self.0.handle(request, data).instrument(span).await
+    }
+}
@@ -0,0 +1,326 @@
+use crate::configuration::TrackingSettings;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Bytes of the HMAC kept in a token: plenty to make forging one impractical,
+/// while keeping tracking URLs short.
+const TAG_LENGTH: usize = 16;
+const OPEN_PAYLOAD_LENGTH: usize = 1 + 16 + 16;
+const CLICK_PAYLOAD_LENGTH: usize = OPEN_PAYLOAD_LENGTH + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackedAction {
+    Open,
+    /// A click on the link at `position` in the issue's list of links.
+    Click {
+        position: i32,
+    },
+}
+
+/// Who did what with which issue, as carried by a tracking URL.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrackingToken {
+    pub issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub action: TrackedAction,
+}
+
+/// What a published issue tracks.
+#[derive(Clone)]
+pub struct TrackedIssue {
+    pub id: Uuid,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    /// The distinct links of the issue, as returned by `links`.
+    pub links: Vec<String>,
+}
+
+/// Rewrites newsletter HTML for each recipient so that opens and clicks can be
+/// recorded, and checks the tokens in the resulting URLs.
+///
+/// Tokens are signed with a secret of their own, so that they cannot be forged
+/// to record or redirect anything.
+pub struct Tracker {
+    key: Secret<String>,
+    base_url: String,
+    opens: bool,
+    clicks: bool,
+}
+
+impl Tracker {
+    pub fn new(settings: &TrackingSettings, base_url: String) -> Self {
+        Self {
+            key: settings.secret.clone(),
+            base_url,
+            opens: settings.opens,
+            clicks: settings.clicks,
+        }
+    }
+
+    /// Whether issues track opens unless they say otherwise.
+    pub fn tracks_opens(&self) -> bool {
+        self.opens
+    }
+
+    /// Whether issues track clicks unless they say otherwise.
+    pub fn tracks_clicks(&self) -> bool {
+        self.clicks
+    }
+
+    /// The HTML to send `subscriber_id`, with its links going through the click
+    /// redirect and an open pixel at the end of the body, as far as `issue`
+    /// tracks either.
+    pub fn personalize(&self, issue: &TrackedIssue, subscriber_id: Uuid, html: &str) -> String {
+        let mut html = if issue.track_clicks {
+            rewrite_links(html, |url| {
+                let position = issue.links.iter().position(|link| link == url)?;
+                Some(self.url(&TrackingToken {
+                    issue_id: issue.id,
+                    subscriber_id,
+                    action: TrackedAction::Click {
+                        position: position as i32,
+                    },
+                }))
+            })
+        } else {
+            html.to_string()
+        };
+        if issue.track_opens {
+            let pixel = format!(
+                r#"<img src="{}" width="1" height="1" alt="" style="border:0;height:1px;width:1px" />"#,
+                self.url(&TrackingToken {
+                    issue_id: issue.id,
+                    subscriber_id,
+                    action: TrackedAction::Open,
+                })
+            );
+            match html.to_ascii_lowercase().rfind("</body") {
+                Some(end_of_body) => html.insert_str(end_of_body, &pixel),
+                None => html.push_str(&pixel),
+            }
+        }
+        html
+    }
+
+    pub fn url(&self, token: &TrackingToken) -> String {
+        let path = match token.action {
+            TrackedAction::Open => "o",
+            TrackedAction::Click { .. } => "c",
+        };
+        format!("{}/t/{}/{}", self.base_url, path, self.sign(token))
+    }
+
+    pub fn sign(&self, token: &TrackingToken) -> String {
+        let mut payload = Vec::with_capacity(CLICK_PAYLOAD_LENGTH + TAG_LENGTH);
+        match token.action {
+            TrackedAction::Open => payload.push(b'o'),
+            TrackedAction::Click { .. } => payload.push(b'c'),
+        }
+        payload.extend_from_slice(token.issue_id.as_bytes());
+        payload.extend_from_slice(token.subscriber_id.as_bytes());
+        if let TrackedAction::Click { position } = token.action {
+            payload.extend_from_slice(&position.to_be_bytes());
+        }
+        let tag = self.mac(&payload).finalize().into_bytes();
+        payload.extend_from_slice(&tag[..TAG_LENGTH]);
+        base64::encode_config(payload, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// The token, if it was signed by this tracker.
+    pub fn verify(&self, token: &str) -> Option<TrackingToken> {
+        let bytes = base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok()?;
+        if bytes.len() < TAG_LENGTH {
+            return None;
+        }
+        let (payload, tag) = bytes.split_at(bytes.len() - TAG_LENGTH);
+        self.mac(payload).verify_truncated_left(tag).ok()?;
+
+        let action = match (payload[0], payload.len()) {
+            (b'o', OPEN_PAYLOAD_LENGTH) => TrackedAction::Open,
+            (b'c', CLICK_PAYLOAD_LENGTH) => {
+                let mut position = [0; 4];
+                position.copy_from_slice(&payload[OPEN_PAYLOAD_LENGTH..]);
+                TrackedAction::Click {
+                    position: i32::from_be_bytes(position),
+                }
+            }
+            _ => return None,
+        };
+        Some(TrackingToken {
+            issue_id: Uuid::from_slice(&payload[1..17]).ok()?,
+            subscriber_id: Uuid::from_slice(&payload[17..33]).ok()?,
+            action,
+        })
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(b"tracking:");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// The distinct http(s) links of the anchors in `html`, in order of first
+/// appearance.
+pub fn links(html: &str) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    rewrite_links(html, |url| {
+        if !links.iter().any(|link| link == url) {
+            links.push(url.to_string());
+        }
+        None
+    });
+    links
+}
+
+/// Replaces the `href` of every anchor that links to an http(s) URL with what
+/// `rewrite` returns for it, if anything. The URL is passed with `&amp;`
+/// unescaped.
+fn rewrite_links(html: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    // ASCII lowercasing keeps byte offsets, so they apply to `html` as well
+    let lowercase = html.to_ascii_lowercase();
+    let mut output = String::with_capacity(html.len());
+    let mut copied = 0;
+    let mut searched = 0;
+    while let Some(found) = lowercase[searched..].find("href") {
+        let attribute = searched + found;
+        searched = attribute + "href".len();
+        let in_anchor = lowercase[..attribute]
+            .rfind('<')
+            .map(|tag| &lowercase[tag + 1..attribute])
+            .filter(|tag| {
+                !tag.contains('>')
+                    && tag.starts_with('a')
+                    && tag[1..].starts_with(|c: char| c.is_ascii_whitespace())
+            })
+            .is_some();
+        let preceded_by_space = lowercase[..attribute].ends_with(|c: char| c.is_ascii_whitespace());
+        if !in_anchor || !preceded_by_space {
+            continue;
+        }
+
+        let rest = &lowercase[searched..];
+        let after_name = rest.trim_start();
+        let after_equals = match after_name.strip_prefix('=') {
+            Some(after_equals) => after_equals.trim_start(),
+            None => continue,
+        };
+        let quote = match after_equals.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => continue,
+        };
+        let value_start = searched + (rest.len() - after_equals.len()) + 1;
+        let value_end = match html[value_start..].find(quote) {
+            Some(length) => value_start + length,
+            None => break,
+        };
+        searched = value_end + 1;
+
+        let value = &lowercase[value_start..value_end];
+        if !(value.starts_with("http://") || value.starts_with("https://")) {
+            continue;
+        }
+        let url = html[value_start..value_end].replace("&amp;", "&");
+        if let Some(replacement) = rewrite(&url) {
+            output.push_str(&html[copied..value_start]);
+            output.push_str(&replacement);
+            copied = value_end;
+        }
+    }
+    output.push_str(&html[copied..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(secret: &str) -> Tracker {
+        let settings = TrackingSettings {
+            secret: Secret::new(secret.to_string()),
+            opens: false,
+            clicks: false,
+        };
+        Tracker::new(&settings, "https://example.com".into())
+    }
+
+    fn click(position: i32) -> TrackingToken {
+        TrackingToken {
+            issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            action: TrackedAction::Click { position },
+        }
+    }
+
+    #[test]
+    fn signed_tokens_can_be_verified() {
+        let tracker = tracker("secret");
+        let token = click(3);
+        assert_eq!(tracker.verify(&tracker.sign(&token)), Some(token));
+
+        let open = TrackingToken {
+            action: TrackedAction::Open,
+            ..click(0)
+        };
+        assert_eq!(tracker.verify(&tracker.sign(&open)), Some(open));
+    }
+
+    #[test]
+    fn tampered_or_foreign_tokens_are_rejected() {
+        let token = tracker("secret").sign(&click(3));
+        let mut bytes = base64::decode_config(&token, base64::URL_SAFE_NO_PAD).unwrap();
+        bytes[36] ^= 1;
+        let tampered = base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+
+        assert_eq!(tracker("secret").verify(&tampered), None);
+        assert_eq!(tracker("another secret").verify(&token), None);
+        assert_eq!(tracker("secret").verify("not a token"), None);
+        assert_eq!(tracker("secret").verify(""), None);
+    }
+
+    #[test]
+    fn only_http_links_in_anchors_are_collected() {
+        let html = r#"<html><head><link href="https://example.com/style.css"></head><body>
+            <a href="https://example.com/a?x=1&amp;y=2">A</a>
+            <A class="button" HREF = 'http://example.com/b'>B</A>
+            <a href="mailto:someone@example.com">Mail</a>
+            <a href="https://example.com/a?x=1&amp;y=2">A again</a>
+            <a data-href="https://example.com/c">Not a link</a>
+        </body></html>"#;
+
+        assert_eq!(
+            links(html),
+            vec!["https://example.com/a?x=1&y=2", "http://example.com/b"]
+        );
+    }
+
+    #[test]
+    fn personalized_html_tracks_what_the_issue_tracks() {
+        let tracker = tracker("secret");
+        let html = r#"<body><a href="https://example.com/a">A</a></body>"#;
+        let mut issue = TrackedIssue {
+            id: Uuid::new_v4(),
+            track_opens: false,
+            track_clicks: false,
+            links: links(html),
+        };
+        let subscriber_id = Uuid::new_v4();
+        assert_eq!(tracker.personalize(&issue, subscriber_id, html), html);
+
+        issue.track_clicks = true;
+        let clicks = tracker.personalize(&issue, subscriber_id, html);
+        assert!(!clicks.contains("https://example.com/a"));
+        assert!(clicks.contains(r#"<a href="https://example.com/t/c/"#));
+        assert!(!clicks.contains("/t/o/"));
+
+        issue.track_opens = true;
+        let both = tracker.personalize(&issue, subscriber_id, html);
+        assert!(both.contains("/t/c/"));
+        assert!(both.contains(r#"<img src="https://example.com/t/o/"#));
+        assert!(both.ends_with(" /></body>"));
+    }
+}
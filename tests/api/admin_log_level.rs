@@ -0,0 +1,120 @@
+use crate::helpers::spawn_app;
+use once_cell::sync::Lazy;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+// the log filter is shared by every app in the test run; these tests take
+// turns changing it, and only ever to directives that keep `info` enabled
+static LOG_FILTER: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[tokio::test]
+async fn the_log_level_requires_credentials() {
+    // arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // act
+    let get = client
+        .get(format!("{}/admin/log-level", app.address))
+        .send()
+        .await
+        .unwrap();
+    let put = client
+        .put(format!("{}/admin/log-level", app.address))
+        .json(&serde_json::json!({ "directives": "info" }))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(get.status().as_u16(), 401);
+    assert_eq!(put.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_log_level_can_be_changed() {
+    // arrange
+    let _lock = LOG_FILTER.lock().await;
+    let app = spawn_app().await;
+    let original: serde_json::Value = app.get_log_level().await.json().await.unwrap();
+
+    // act
+    let response = app
+        .put_log_level(serde_json::json!({ "directives": "info,zero2prod=debug" }))
+        .await;
+    let current: serde_json::Value = app.get_log_level().await.json().await.unwrap();
+    app.put_log_level(serde_json::json!({ "directives": original["directives"] }))
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(current["directives"], "info,zero2prod=debug");
+    assert!(current["revert_at"].is_null());
+}
+
+#[tokio::test]
+async fn invalid_directives_are_rejected() {
+    // arrange
+    let _lock = LOG_FILTER.lock().await;
+    let app = spawn_app().await;
+    let original: serde_json::Value = app.get_log_level().await.json().await.unwrap();
+    let test_cases = vec![
+        (
+            serde_json::json!({ "directives": "zero2prod=loudest" }),
+            "directives",
+        ),
+        (serde_json::json!({ "directives": " " }), "directives"),
+        (
+            serde_json::json!({ "directives": "debug", "ttl_seconds": 0 }),
+            "ttl_seconds",
+        ),
+    ];
+
+    for (body, field) in test_cases {
+        // act
+        let response = app.put_log_level(body.clone()).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {}.",
+            body
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "invalid_log_level");
+        assert_eq!(problem["errors"][0]["field"], field);
+    }
+    let current: serde_json::Value = app.get_log_level().await.json().await.unwrap();
+    assert_eq!(current["directives"], original["directives"]);
+}
+
+#[tokio::test]
+async fn a_temporary_log_level_reverts_after_its_ttl() {
+    // arrange
+    let _lock = LOG_FILTER.lock().await;
+    let app = spawn_app().await;
+    let original: serde_json::Value = app.get_log_level().await.json().await.unwrap();
+
+    // act
+    let response = app
+        .put_log_level(serde_json::json!({
+            "directives": "info,zero2prod=trace",
+            "ttl_seconds": 1,
+        }))
+        .await;
+    let during: serde_json::Value = response.json().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let after: serde_json::Value = app.get_log_level().await.json().await.unwrap();
+
+    // assert
+    assert_eq!(during["directives"], "info,zero2prod=trace");
+    assert!(during["revert_at"].is_string());
+    assert_eq!(after["directives"], original["directives"]);
+    assert!(after["revert_at"].is_null());
+    let reverts = app.audit_entries("?action=log_level.reverted").await;
+    assert_eq!(reverts.len(), 1);
+    assert_eq!(reverts[0]["actor_type"], "system");
+    assert_eq!(reverts[0]["before"]["directives"], "info,zero2prod=trace");
+    assert_eq!(reverts[0]["after"]["directives"], original["directives"]);
+}
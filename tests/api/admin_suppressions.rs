@@ -0,0 +1,203 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use crate::newsletters::create_confirmed_subscriber;
+
+fn newsletter() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn suppressions_require_credentials() {
+    // arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // act
+    let get = client
+        .get(format!("{}/admin/suppressions", app.address))
+        .send()
+        .await
+        .unwrap();
+    let post = client
+        .post(format!("{}/admin/suppressions", app.address))
+        .json(&serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(get.status().as_u16(), 401);
+    assert_eq!(post.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_get_no_confirmation_email() {
+    // arrange
+    let app = spawn_app().await;
+    app.post_suppressions(serde_json::json!({ "email": "Ursula_Le_Guin@gmail.com" }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(app.email_client.sent_emails.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn suppressions_cover_provider_aliases() {
+    // arrange
+    let app = spawn_app_with(|c| c.email_policy.apply_provider_rules = true).await;
+    app.post_suppressions(serde_json::json!({ "email": "ursulaleguin@gmail.com" }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula.le.guin%2Bnews%40gmail.com".into())
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(app.email_client.sent_emails.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_get_no_newsletters() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.email_client.sent_emails.lock().unwrap().clear();
+    app.post_suppressions(serde_json::json!({
+        "email": "ursula_le_guin@gmail.com",
+        "reason": "forgotten",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // act
+    let response = app.post_newsletters(newsletter()).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(app.email_client.sent_emails.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn suppressions_can_be_listed_and_filtered() {
+    // arrange
+    let app = spawn_app().await;
+    let added = app
+        .post_suppressions(serde_json::json!({
+            "email": "ursula_le_guin@gmail.com",
+            "reason": "complaint",
+            "detail": "Reported by phone",
+        }))
+        .await;
+    app.post_suppressions(serde_json::json!({ "email": "octavia_butler@gmail.com" }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // act
+    let all: serde_json::Value = app.get_suppressions("").await.json().await.unwrap();
+    let complaints: serde_json::Value = app
+        .get_suppressions("?reason=complaint")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(added.status().as_u16(), 201);
+    assert_eq!(all.as_array().unwrap().len(), 2);
+    let complaints = complaints.as_array().unwrap();
+    assert_eq!(complaints.len(), 1);
+    assert_eq!(complaints[0]["canonical_email"], "ursula_le_guin@gmail.com");
+    assert_eq!(complaints[0]["detail"], "Reported by phone");
+    assert_eq!(complaints[0]["source"], "admin");
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected() {
+    // arrange
+    let app = spawn_app().await;
+    app.post_suppressions(serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .await
+        .error_for_status()
+        .unwrap();
+    let test_cases = vec![
+        (
+            serde_json::json!({ "email": "not-an-email" }),
+            400,
+            "invalid_suppression",
+            "an invalid email",
+        ),
+        (
+            serde_json::json!({ "email": "octavia_butler@gmail.com", "reason": "boredom" }),
+            400,
+            "invalid_suppression",
+            "an unknown reason",
+        ),
+        (
+            serde_json::json!({ "email": "URSULA_LE_GUIN@gmail.com" }),
+            409,
+            "already_suppressed",
+            "an address that is already suppressed",
+        ),
+    ];
+
+    for (body, status, code, description) in test_cases {
+        // act
+        let response = app.post_suppressions(body).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            status,
+            "The API did not reject {}.",
+            description
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], code);
+    }
+}
+
+#[tokio::test]
+async fn removed_suppressions_no_longer_block_sends() {
+    // arrange
+    let app = spawn_app().await;
+    app.post_suppressions(serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // act
+    let removed = app
+        .delete_suppressions(serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .await;
+    let removed_again = app
+        .delete_suppressions(serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // assert
+    assert_eq!(removed.status().as_u16(), 204);
+    assert_eq!(removed_again.status().as_u16(), 404);
+    assert_eq!(app.email_client.sent_emails.lock().unwrap().len(), 1);
+}
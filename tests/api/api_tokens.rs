@@ -0,0 +1,237 @@
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletters::create_confirmed_subscriber;
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+fn newsletter() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Release notes",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn publish_with_token(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .bearer_auth(token)
+        .json(&newsletter())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn a_token_with_the_publish_scope_can_publish() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, token) = app.create_api_token(&["publish"]).await;
+
+    // act
+    let response = publish_with_token(&app, &token).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let published: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(published["sent"], 1);
+}
+
+#[tokio::test]
+async fn the_bearer_scheme_is_case_insensitive() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, token) = app.create_api_token(&["publish"]).await;
+
+    // act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .header("Authorization", format!("bearer {}", token))
+        .json(&newsletter())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_token_without_the_scope_is_forbidden() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, token) = app.create_api_token(&["read-subscribers"]).await;
+
+    // act
+    let publish = publish_with_token(&app, &token).await;
+    let list = reqwest::Client::new()
+        .get(format!("{}/admin/suppressions", app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    let add = reqwest::Client::new()
+        .post(format!("{}/admin/suppressions", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(publish.status().as_u16(), 403);
+    let problem: serde_json::Value = publish.json().await.unwrap();
+    assert_eq!(problem["code"], "insufficient_scope");
+    assert_eq!(list.status().as_u16(), 200);
+    assert_eq!(add.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn revoked_expired_and_unknown_tokens_are_rejected() {
+    // arrange
+    let app = spawn_app().await;
+    let (revoked_id, revoked) = app.create_api_token(&["publish"]).await;
+    let (expired_id, expired) = app.create_api_token(&["publish"]).await;
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/api-tokens/{}", app.address, revoked_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    {
+        use zero2prod::schema::api_tokens;
+        diesel::update(api_tokens::table.find(uuid::Uuid::parse_str(&expired_id).unwrap()))
+            .set(api_tokens::expires_at.eq(Utc::now() - Duration::minutes(1)))
+            .execute(&app.db_connection)
+            .unwrap();
+    }
+    let test_cases = vec![
+        (revoked, "a revoked token"),
+        (expired, "an expired token"),
+        ("z2p_notatokenatall".to_string(), "an unknown token"),
+    ];
+
+    for (token, description) in test_cases {
+        // act
+        let response = publish_with_token(&app, &token).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "The API did not reject {}.",
+            description
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "invalid_token");
+    }
+}
+
+#[tokio::test]
+async fn tokens_are_listed_without_the_secret_and_with_last_use() {
+    // arrange
+    let app = spawn_app().await;
+    let (id, token) = app.create_api_token(&["publish", "publish"]).await;
+
+    // act
+    let before: serde_json::Value = app.get_api_tokens().await.json().await.unwrap();
+    publish_with_token(&app, &token)
+        .await
+        .error_for_status()
+        .unwrap();
+    let after: serde_json::Value = app.get_api_tokens().await.json().await.unwrap();
+
+    // assert
+    assert_eq!(before[0]["id"], id.as_str());
+    assert_eq!(before[0]["scopes"], serde_json::json!(["publish"]));
+    assert!(before[0]["last_used_at"].is_null());
+    assert!(before[0].get("token").is_none());
+    assert!(token.starts_with(before[0]["prefix"].as_str().unwrap()));
+    assert!(!after[0]["last_used_at"].is_null());
+}
+
+#[tokio::test]
+async fn invalid_token_requests_are_rejected() {
+    // arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "name": " ", "scopes": ["publish"] }),
+            "an empty name",
+        ),
+        (
+            serde_json::json!({ "name": "ci", "scopes": [] }),
+            "no scopes",
+        ),
+        (
+            serde_json::json!({ "name": "ci", "scopes": ["admin"] }),
+            "an unknown scope",
+        ),
+        (
+            serde_json::json!({ "name": "ci", "scopes": ["publish"], "expires_in_days": 0 }),
+            "a lifetime of zero days",
+        ),
+        (
+            serde_json::json!({ "name": "ci", "scopes": ["publish"], "expires_in_days": 366 }),
+            "a lifetime over a year",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // act
+        let response = app.post_api_tokens(body).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {}.",
+            description
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "invalid_api_token");
+    }
+}
+
+#[tokio::test]
+async fn managing_tokens_requires_a_user() {
+    // arrange
+    let app = spawn_app().await;
+    let (id, token) = app
+        .create_api_token(&["publish", "manage-subscribers"])
+        .await;
+
+    // act
+    let with_token = reqwest::Client::new()
+        .post(format!("{}/admin/api-tokens", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "name": "escalated", "scopes": ["publish"] }))
+        .send()
+        .await
+        .unwrap();
+    let anonymous = reqwest::Client::new()
+        .delete(format!("{}/admin/api-tokens/{}", app.address, id))
+        .send()
+        .await
+        .unwrap();
+    let unknown = reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/api-tokens/{}",
+            app.address,
+            uuid::Uuid::new_v4()
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(with_token.status().as_u16(), 403);
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(unknown.status().as_u16(), 404);
+}
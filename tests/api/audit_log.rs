@@ -0,0 +1,222 @@
+use crate::helpers::spawn_app;
+use crate::newsletters::create_confirmed_subscriber;
+use diesel::RunQueryDsl;
+
+#[tokio::test]
+async fn publishing_records_who_published_what() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // assert
+    let request_id = response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let published: serde_json::Value = response.json().await.unwrap();
+    let entries = app.audit_entries("?action=newsletter.published").await;
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry["actor_type"], "user");
+    assert_eq!(entry["actor_name"], app.test_user.username.as_str());
+    assert_eq!(
+        entry["actor_id"],
+        app.test_user.user_id.to_string().as_str()
+    );
+    assert_eq!(entry["target_type"], "newsletter_issue");
+    assert_eq!(entry["target_id"], published["issue_id"]);
+    assert_eq!(entry["after"]["title"], "Newsletter title");
+    assert_eq!(entry["after"]["recipients"], 1);
+    assert_eq!(entry["request_id"], request_id.as_str());
+}
+
+#[tokio::test]
+async fn subscriber_edits_record_the_state_before_and_after() {
+    // arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({ "email": "ursula@example.com", "reason": "manual" });
+    let suppression: serde_json::Value = app
+        .post_suppressions(body.clone())
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // act
+    app.delete_suppressions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // assert
+    let entries = app.audit_entries("?target_type=suppression").await;
+    assert_eq!(entries.len(), 2);
+    let (removed, added) = (&entries[0], &entries[1]);
+    assert_eq!(removed["action"], "suppression.removed");
+    assert_eq!(removed["target_id"], suppression["id"]);
+    assert_eq!(removed["before"]["reason"], "manual");
+    assert!(removed["after"].is_null());
+    assert_eq!(added["action"], "suppression.added");
+    assert_eq!(added["target_id"], suppression["id"]);
+    assert!(added["before"].is_null());
+    assert_eq!(added["after"]["reason"], "manual");
+}
+
+#[tokio::test]
+async fn suppressions_are_recorded_without_the_address() {
+    // arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "email": "Ursula@Example.com",
+        "reason": "forgotten",
+        "detail": "Asked to be forgotten as ursula@example.com."
+    });
+
+    // act
+    app.post_suppressions(body.clone())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.delete_suppressions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // assert
+    let entries = app.audit_entries("?target_type=suppression").await;
+    assert_eq!(entries.len(), 2);
+    for entry in entries {
+        assert!(!entry.to_string().to_lowercase().contains("ursula"));
+    }
+}
+
+#[tokio::test]
+async fn api_tokens_are_recorded_as_the_actor() {
+    // arrange
+    let app = spawn_app().await;
+    let (token_id, token) = app.create_api_token(&["manage-subscribers"]).await;
+
+    // act
+    reqwest::Client::new()
+        .post(format!("{}/admin/suppressions", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "email": "ursula@example.com" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // assert
+    let entries = app.audit_entries("?actor=ci").await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["actor_type"], "api_token");
+    assert_eq!(entries[0]["actor_id"], token_id.as_str());
+    let created = app.audit_entries("?action=api_token.created").await;
+    assert_eq!(created[0]["target_id"], token_id.as_str());
+    assert_eq!(created[0]["actor_name"], app.test_user.username.as_str());
+}
+
+#[tokio::test]
+async fn user_management_is_recorded() {
+    // arrange
+    let app = spawn_app().await;
+    let editor = app.add_user("editor");
+
+    // act
+    reqwest::Client::new()
+        .put(format!(
+            "{}/admin/users/{}/role",
+            app.address, editor.user_id
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "role": "viewer" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // assert
+    let query = format!("?target_type=user&target_id={}", editor.user_id);
+    let entries = app.audit_entries(&query).await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["action"], "user.role_changed");
+    assert_eq!(entries[0]["before"]["role"], "editor");
+    assert_eq!(entries[0]["after"]["role"], "viewer");
+}
+
+#[tokio::test]
+async fn entries_can_be_filtered_by_time() {
+    // arrange
+    let app = spawn_app().await;
+    app.post_suppressions(serde_json::json!({ "email": "ursula@example.com" }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // act
+    let since_yesterday = app.audit_entries("?since=2000-01-01T00:00:00Z").await;
+    let until_then = app.audit_entries("?until=2000-01-01T00:00:00Z").await;
+    let invalid = app.get_audit_log("?since=yesterday").await;
+
+    // assert
+    assert_eq!(since_yesterday.len(), 1);
+    assert!(until_then.is_empty());
+    assert_eq!(invalid.status().as_u16(), 400);
+    let problem: serde_json::Value = invalid.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_audit_filter");
+}
+
+#[tokio::test]
+async fn only_owners_can_read_the_audit_log() {
+    // arrange
+    let app = spawn_app().await;
+    let editor = app.add_user("editor");
+
+    // act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/audit", app.address))
+        .basic_auth(&editor.username, Some(&editor.password))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn entries_cannot_be_changed_or_removed() {
+    // arrange
+    let app = spawn_app().await;
+    app.post_suppressions(serde_json::json!({ "email": "ursula@example.com" }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // act
+    let updated = diesel::sql_query("UPDATE audit_log SET actor_name = 'someone else'")
+        .execute(&app.db_connection);
+    let deleted = diesel::sql_query("DELETE FROM audit_log").execute(&app.db_connection);
+    let truncated = diesel::sql_query("TRUNCATE audit_log").execute(&app.db_connection);
+
+    // assert
+    assert!(updated.is_err());
+    assert!(deleted.is_err());
+    assert!(truncated.is_err());
+    assert_eq!(app.audit_entries("").await.len(), 1);
+}
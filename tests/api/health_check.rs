@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -18,3 +18,58 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_does_not_depend_on_the_database() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_reports_every_component() {
+    // arrange
+    let app = spawn_app_with(|c| c.health.check_email = true).await;
+
+    // act
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "pass");
+    for component in ["database", "migrations", "email"] {
+        assert_eq!(body["checks"][component]["status"], "pass");
+        assert!(body["checks"][component]["latency_ms"].is_number());
+    }
+}
+
+#[tokio::test]
+async fn readiness_fails_with_pending_migrations() {
+    // arrange
+    let app = spawn_app().await;
+    diesel_migrations::revert_latest_migration(&app.db_connection).unwrap();
+
+    // act
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "fail");
+    assert_eq!(body["checks"]["database"]["status"], "pass");
+    assert_eq!(body["checks"]["migrations"]["status"], "fail");
+    assert!(body["checks"]["migrations"].get("error").is_none());
+    assert!(body["checks"].get("email").is_none());
+}
@@ -1,40 +1,94 @@
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use async_trait::async_trait;
+use chrono::Utc;
 use diesel::prelude::*;
 use diesel::{Connection, PgConnection};
 use once_cell::sync::Lazy;
 use reqwest::Url;
+use secrecy::ExposeSecret;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
-use zero2prod::configuration::{get_configuration, Settings};
-use zero2prod::domain::SubscriberEmail;
-use zero2prod::email::Email;
+use zero2prod::clock::{Clock, FixedClock};
+use zero2prod::configuration::{get_configuration, OtlpSettings, Settings};
+use zero2prod::domain::{SubscriberEmail, TotpSecret};
+use zero2prod::email::{Delivery, Email};
 use zero2prod::models::NewUser;
+use zero2prod::shutdown::{ShutdownCoordinator, ShutdownReport};
 use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::telemetry::{get_subscriber, init_otlp_tracer, init_subscriber};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".into();
     let subscriber_name = "test".into();
+    // export spans promptly instead of every five seconds
+    std::env::set_var("OTEL_BSP_SCHEDULE_DELAY", "100");
+    // the exporter's flush timer belongs to the runtime it is created in, and
+    // the runtime of whichever test gets here first is gone when that test ends
+    let _runtime = EXPORTER_RUNTIME.enter();
+    let tracer = init_otlp_tracer(&OtlpSettings {
+        endpoint: COLLECTOR.endpoint.clone(),
+        service_name: "zero2prod-test".into(),
+        sampling_ratio: 1.0,
+    })
+    .expect("Failed to set up the OTLP exporter.");
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            Some(tracer),
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::sink,
+            Some(tracer),
+        );
         init_subscriber(subscriber);
     }
 });
 
+static EXPORTER_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .expect("Failed to build the runtime for the OTLP exporter.")
+});
+
+/// Receives the spans exported by every app spawned in this test run.
+pub static COLLECTOR: Lazy<FakeCollector> = Lazy::new(FakeCollector::start);
+
+pub const BROWSER_ACCEPT: &str = "text/html,application/xhtml+xml,*/*;q=0.8";
+
 pub struct TestApp {
     pub port: u16,
     pub address: String,
     pub db_connection: PgConnection,
     pub email_client: Arc<MockEmailClient>,
+    /// The clock that one-time codes are checked against.
+    pub clock: Arc<FixedClock>,
     pub test_user: TestUser,
+    pub shutdown: ShutdownCoordinator,
+    server_shutdown: rocket::Shutdown,
+    server: JoinHandle<Result<(), rocket::Error>>,
 }
 
 impl TestApp {
+    /// Shuts the app down like SIGTERM would, see `Application::run_until_stopped`.
+    pub async fn stop(&mut self) -> ShutdownReport {
+        self.server_shutdown.clone().notify();
+        (&mut self.server).await.unwrap().unwrap();
+        self.shutdown.drain().await
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
             .post(&format!("{}/subscriptions", &self.address))
@@ -45,6 +99,26 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_subscriptions_json(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscriptions_from_browser(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", BROWSER_ACCEPT)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
             .post(&format!("{}/newsletters", &self.address))
@@ -55,6 +129,178 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_log_level(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/log-level", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_log_level(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/log-level", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_suppressions(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/suppressions{}", &self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_suppressions(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/suppressions", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_suppressions(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/suppressions", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue_deliveries(&self, issue_id: &str, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/issues/{}/deliveries{}",
+                &self.address, issue_id, query
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_api_tokens(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/api-tokens", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_api_tokens(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/api-tokens", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/audit{}", &self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// The audit log entries that `query` selects.
+    pub async fn audit_entries(&self, query: &str) -> Vec<serde_json::Value> {
+        self.get_audit_log(query)
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_two_factor_enrollment(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/2fa/enrollment", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_two_factor_confirmation(&self, code: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/2fa/confirmation", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Enables two-factor authentication for the test user, returning the
+    /// secret and the recovery codes, with the clock moved on past the step of
+    /// the code that confirmed it.
+    pub async fn enable_two_factor(&self) -> (TotpSecret, Vec<String>) {
+        let enrollment: serde_json::Value = self
+            .post_two_factor_enrollment()
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let secret = TotpSecret::parse(enrollment["secret"].as_str().unwrap()).unwrap();
+        let confirmation: serde_json::Value = self
+            .post_two_factor_confirmation(&secret.code_at(self.clock.now()))
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let recovery_codes = confirmation["recovery_codes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|code| code.as_str().unwrap().to_string())
+            .collect();
+        self.clock.advance(chrono::Duration::seconds(30));
+        (secret, recovery_codes)
+    }
+
+    /// Stores another user, with `role`.
+    pub fn add_user(&self, role: &str) -> TestUser {
+        let user = TestUser::with_role(role);
+        user.store(&self.db_connection);
+        user
+    }
+
+    /// Creates an API token with `scopes`, returning its id and the token.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> (String, String) {
+        let created: serde_json::Value = self
+            .post_api_tokens(serde_json::json!({ "name": "ci", "scopes": scopes }))
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        (
+            created["id"].as_str().unwrap().to_string(),
+            created["token"].as_str().unwrap().to_string(),
+        )
+    }
+
     pub fn get_confirmation_links(&self, email: &SentEmail) -> ConfirmationLinks {
         let get_link = |s: &str| {
             let links: Vec<_> = linkify::LinkFinder::new()
@@ -74,6 +320,41 @@ impl TestApp {
         let plain_text = get_link(&email.text_content);
         ConfirmationLinks { html, plain_text }
     }
+
+    /// Follows the confirmation link like a browser would and submits the form on that page.
+    pub async fn confirm_subscription(&self, confirmation_link: Url) -> reqwest::Response {
+        let client = reqwest::Client::builder()
+            .cookie_store(true)
+            .build()
+            .unwrap();
+        let page = client
+            .get(confirmation_link)
+            .header("Accept", BROWSER_ACCEPT)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let form_field = |name: &str| {
+            let marker = format!(r#"name="{}" value=""#, name);
+            let start = page.find(&marker).expect("Missing form field.") + marker.len();
+            page[start..].split('"').next().unwrap().to_string()
+        };
+
+        client
+            .post(format!("{}/subscriptions/confirm", &self.address))
+            .header("Accept", BROWSER_ACCEPT)
+            .form(&[
+                ("subscription_token", form_field("subscription_token")),
+                ("csrf_token", form_field("csrf_token")),
+            ])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -85,6 +366,7 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: String,
 }
 
 impl TestUser {
@@ -93,6 +375,14 @@ impl TestUser {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role: "owner".into(),
+        }
+    }
+
+    pub fn with_role(role: &str) -> Self {
+        Self {
+            role: role.into(),
+            ..Self::generate()
         }
     }
 
@@ -113,6 +403,7 @@ impl TestUser {
                 user_id: &self.user_id,
                 username: &self.username,
                 password_hash: &password_hash,
+                role: &self.role,
             })
             .execute(conn)
             .expect("Failed to store test user.");
@@ -124,18 +415,36 @@ pub struct SentEmail {
     pub subject: String,
     pub html_content: String,
     pub text_content: String,
+    pub message_id: String,
 }
 
 pub struct MockEmailClient {
     pub sent_emails: Mutex<Vec<SentEmail>>,
+    send_delay: Mutex<Duration>,
+    failing_recipients: Mutex<Vec<String>>,
 }
 
 impl MockEmailClient {
     fn new() -> Self {
         Self {
             sent_emails: Mutex::new(Vec::new()),
+            send_delay: Mutex::new(Duration::ZERO),
+            failing_recipients: Mutex::new(Vec::new()),
         }
     }
+
+    /// Makes every following send to `recipient` fail.
+    pub fn fail_sends_to(&self, recipient: &str) {
+        self.failing_recipients
+            .lock()
+            .unwrap()
+            .push(recipient.to_string());
+    }
+
+    /// Makes every following send take at least `delay`.
+    pub fn delay_sends(&self, delay: Duration) {
+        *self.send_delay.lock().unwrap() = delay;
+    }
 }
 
 #[async_trait]
@@ -146,23 +455,44 @@ impl Email for MockEmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), anyhow::Error> {
-        Ok(self.sent_emails.lock().unwrap().push(SentEmail {
-            recipient: recipient.as_ref().to_string(),
+    ) -> Result<Delivery, anyhow::Error> {
+        let delay = *self.send_delay.lock().unwrap();
+        tokio::time::sleep(delay).await;
+        let recipient = recipient.expose_secret();
+        if self.failing_recipients.lock().unwrap().contains(recipient) {
+            anyhow::bail!("The mock was told to fail sends to this recipient.");
+        }
+        let message_id = Uuid::new_v4().to_string();
+        self.sent_emails.lock().unwrap().push(SentEmail {
+            recipient: recipient.to_string(),
             subject: subject.to_string(),
             html_content: html_content.to_string(),
             text_content: text_content.to_string(),
-        }))
+            message_id: message_id.clone(),
+        });
+        Ok(Delivery::Sent {
+            message_id: Some(message_id),
+        })
+    }
+
+    fn backend(&self) -> &'static str {
+        "mock"
     }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, but lets the test adjust the configuration first.
+pub async fn spawn_app_with(customize: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let configuration = {
         let mut c = get_configuration().expect("Failed to read configuration.");
         c.application.port = None;
         c.database.database_name = Uuid::new_v4().to_string();
+        customize(&mut c);
         println!("spawning with name {} ", c.database.database_name);
         c
     };
@@ -171,10 +501,13 @@ pub async fn spawn_app() -> TestApp {
 
     let email_client = Arc::new(MockEmailClient::new());
 
-    let app = Application::build(&configuration, email_client.clone())
+    let clock = Arc::new(FixedClock::new(Utc::now()));
+
+    let app = Application::build(&configuration, email_client.clone(), clock.clone())
         .await
         .unwrap();
-    let _ = tokio::spawn(app.server.launch());
+    let server_shutdown = app.server.shutdown();
+    let server = tokio::spawn(app.server.launch());
     let port = app.port.get().await;
 
     let test_user = TestUser::generate();
@@ -186,7 +519,11 @@ pub async fn spawn_app() -> TestApp {
         address: format!("http://127.0.0.1:{}", port),
         db_connection,
         email_client,
+        clock,
         test_user,
+        shutdown: app.shutdown,
+        server_shutdown,
+        server,
     }
 }
 
@@ -208,14 +545,88 @@ fn setup_database(configuration: &Settings) -> PgConnection {
 
 fn connect_to_database(configuration: &Settings) -> PgConnection {
     let connection_string = configuration.database.connection_string();
-    let connection =
-        PgConnection::establish(&connection_string).expect("Failed to connect to Postgres.");
+    let connection = PgConnection::establish(connection_string.expose_secret())
+        .expect("Failed to connect to Postgres.");
     connection
 }
 
 fn connect_without_database(configuration: &Settings) -> PgConnection {
     let connection_string = configuration.database.connection_string_without_database();
-    let connection =
-        PgConnection::establish(&connection_string).expect("Failed to connect to Postgres.");
+    let connection = PgConnection::establish(connection_string.expose_secret())
+        .expect("Failed to connect to Postgres.");
     connection
 }
+
+/// Stand-in for an OpenTelemetry collector: accepts OTLP/HTTP exports and
+/// keeps the raw protobuf bodies.
+pub struct FakeCollector {
+    pub endpoint: String,
+    exports: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl FakeCollector {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let exports = Arc::new(Mutex::new(Vec::new()));
+        let received = exports.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let received = received.clone();
+                std::thread::spawn(move || serve_exports(stream, received));
+            }
+        });
+        Self { endpoint, exports }
+    }
+
+    /// Waits up to ten seconds for an export containing a span of the trace.
+    pub async fn received_trace(&self, trace_id: &[u8]) -> bool {
+        for _ in 0..100 {
+            let received = self
+                .exports
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|body| body.windows(trace_id.len()).any(|w| w == trace_id));
+            if received {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        false
+    }
+}
+
+fn serve_exports(stream: TcpStream, exports: Arc<Mutex<Vec<Vec<u8>>>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let mut content_length = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        exports.lock().unwrap().push(body);
+        if stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .is_err()
+        {
+            return;
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletters::create_confirmed_subscriber;
+
+async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    app.post_subscriptions(format!(
+        "name=le%20guin&email={}",
+        email.replace('@', "%40")
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+    let confirmation_link = {
+        let mut emails = app.email_client.sent_emails.lock().unwrap();
+        app.get_confirmation_links(&emails.pop().unwrap())
+    };
+    app.confirm_subscription(confirmation_link.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn publish(app: &TestApp) -> serde_json::Value {
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap()
+}
+
+/// Three confirmed subscribers: one who gets the issue, one on the
+/// suppression list and one whose email fails to send.
+async fn publish_to_mixed_recipients(app: &TestApp) -> serde_json::Value {
+    create_confirmed_subscriber(app).await;
+    create_confirmed_subscriber_with_email(app, "octavia_butler@gmail.com").await;
+    create_confirmed_subscriber_with_email(app, "nk_jemisin@gmail.com").await;
+    app.post_suppressions(serde_json::json!({ "email": "octavia_butler@gmail.com" }))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.email_client.fail_sends_to("nk_jemisin@gmail.com");
+    publish(app).await
+}
+
+#[tokio::test]
+async fn publishing_reports_what_became_of_each_email() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let published = publish_to_mixed_recipients(&app).await;
+
+    // assert
+    assert_eq!(published["recipients"], 3);
+    assert_eq!(published["sent"], 1);
+    assert_eq!(published["suppressed"], 1);
+    assert_eq!(published["failed"], 1);
+    assert_eq!(app.email_client.sent_emails.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn deliveries_are_listed_per_recipient() {
+    // arrange
+    let app = spawn_app().await;
+    let issue_id = publish_to_mixed_recipients(&app).await["issue_id"].clone();
+
+    // act
+    let response = app
+        .get_issue_deliveries(issue_id.as_str().unwrap(), "")
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        report["counts"],
+        serde_json::json!({ "sent": 1, "suppressed": 1, "failed": 1 })
+    );
+    let deliveries = report["deliveries"].as_array().unwrap();
+    let status_of = |email: &str| {
+        deliveries
+            .iter()
+            .find(|d| d["email"] == email)
+            .unwrap_or_else(|| panic!("No delivery to {}.", email))
+    };
+    let sent = status_of("ursula_le_guin@gmail.com");
+    assert_eq!(sent["status"], "sent");
+    assert_eq!(
+        sent["message_id"],
+        app.email_client.sent_emails.lock().unwrap()[0].message_id
+    );
+    assert_eq!(
+        status_of("octavia_butler@gmail.com")["status"],
+        "suppressed"
+    );
+    let failed = status_of("nk_jemisin@gmail.com");
+    assert_eq!(failed["status"], "failed");
+    assert!(failed["error"].as_str().unwrap().contains("told to fail"));
+}
+
+#[tokio::test]
+async fn deliveries_can_be_filtered_by_status() {
+    // arrange
+    let app = spawn_app().await;
+    let issue_id = publish_to_mixed_recipients(&app).await["issue_id"].clone();
+
+    // act
+    let report: serde_json::Value = app
+        .get_issue_deliveries(issue_id.as_str().unwrap(), "?status=failed")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // assert
+    let deliveries = report["deliveries"].as_array().unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["email"], "nk_jemisin@gmail.com");
+    assert_eq!(report["counts"]["sent"], 1, "Counts cover every recipient.");
+}
+
+#[tokio::test]
+async fn deliveries_can_be_exported_as_csv() {
+    // arrange
+    let app = spawn_app().await;
+    let issue_id = publish_to_mixed_recipients(&app).await["issue_id"].clone();
+
+    // act
+    let response = app
+        .get_issue_deliveries(issue_id.as_str().unwrap(), "?format=csv&status=suppressed")
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "subscriber_id,email,status,error,message_id,updated_at"
+    );
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(",octavia_butler@gmail.com,suppressed,"));
+}
+
+#[tokio::test]
+async fn invalid_delivery_queries_are_rejected() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish(&app).await["issue_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let unknown_issue = uuid::Uuid::new_v4().to_string();
+    let test_cases = vec![
+        (issue_id.as_str(), "?status=lost", 400, "an unknown status"),
+        (issue_id.as_str(), "?format=xml", 400, "an unknown format"),
+        (unknown_issue.as_str(), "", 404, "an unknown issue"),
+    ];
+
+    for (issue_id, query, status, description) in test_cases {
+        // act
+        let response = app.get_issue_deliveries(issue_id, query).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            status,
+            "The API did not reject {}.",
+            description
+        );
+    }
+    let anonymous = reqwest::get(format!(
+        "{}/admin/issues/{}/deliveries",
+        app.address, issue_id
+    ))
+    .await
+    .unwrap();
+    assert_eq!(anonymous.status().as_u16(), 401);
+}
@@ -0,0 +1,275 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use diesel::{QueryDsl, RunQueryDsl};
+use zero2prod::configuration::Settings;
+
+/// Any request that needs credentials will do.
+async fn log_in(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/suppressions", app.address))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Three failures lock out a username, without delays in between.
+fn strict(c: &mut Settings) {
+    c.login_throttle.max_failures_per_username = 3;
+    c.login_throttle.initial_delay_milliseconds = 0;
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_out_a_username() {
+    // arrange
+    let app = spawn_app_with(strict).await;
+    let username = app.test_user.username.clone();
+    for _ in 0..3 {
+        assert_eq!(
+            log_in(&app, &username, "guess").await.status().as_u16(),
+            401
+        );
+    }
+
+    // act
+    let response = log_in(&app, &username, &app.test_user.password).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().get("WWW-Authenticate").is_none());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((890..=900).contains(&retry_after));
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "locked_out");
+}
+
+#[tokio::test]
+async fn unknown_usernames_are_throttled_like_known_ones() {
+    // arrange
+    let app = spawn_app_with(strict).await;
+    let known = app.test_user.username.clone();
+    let unknown = uuid::Uuid::new_v4().to_string();
+
+    for username in [known, unknown] {
+        // act
+        let mut statuses = Vec::new();
+        for _ in 0..4 {
+            statuses.push(log_in(&app, &username, "guess").await.status().as_u16());
+        }
+
+        // assert
+        assert_eq!(statuses, vec![401, 401, 401, 429]);
+    }
+}
+
+#[tokio::test]
+async fn a_failure_delays_the_next_attempt() {
+    // arrange
+    let app = spawn_app().await;
+    let username = app.test_user.username.clone();
+    log_in(&app, &username, "guess").await;
+
+    // act
+    let response = log_in(&app, &username, &app.test_user.password).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(response.headers()["Retry-After"], "1");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "login_throttled");
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let retried = log_in(&app, &username, &app.test_user.password).await;
+    assert_eq!(retried.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn concurrent_guesses_are_checked_one_at_a_time() {
+    // arrange
+    let app = spawn_app().await;
+    let username = app.test_user.username.clone();
+    let guess = || log_in(&app, &username, "guess");
+    assert_eq!(guess().await.status().as_u16(), 401);
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    // act
+    let responses = tokio::join!(guess(), guess(), guess(), guess(), guess());
+
+    // assert
+    let mut statuses: Vec<_> = [
+        responses.0,
+        responses.1,
+        responses.2,
+        responses.3,
+        responses.4,
+    ]
+    .iter()
+    .map(|response| response.status().as_u16())
+    .collect();
+    statuses.sort_unstable();
+    assert_eq!(statuses, vec![401, 429, 429, 429, 429]);
+    let retried = guess().await;
+    assert_eq!(retried.status().as_u16(), 429);
+    let problem: serde_json::Value = retried.json().await.unwrap();
+    assert_eq!(problem["code"], "login_throttled");
+}
+
+#[tokio::test]
+async fn concurrent_logins_without_failures_are_not_held_up() {
+    // arrange
+    let app = spawn_app().await;
+    let (username, password) = (&app.test_user.username, &app.test_user.password);
+    let request = || log_in(&app, username, password);
+
+    // act
+    let responses = tokio::join!(request(), request(), request(), request(), request());
+
+    // assert
+    for response in [
+        responses.0,
+        responses.1,
+        responses.2,
+        responses.3,
+        responses.4,
+    ] {
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn an_address_guessing_many_usernames_is_locked_out() {
+    // arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttle.max_failures_per_ip = 3;
+        c.login_throttle.initial_delay_milliseconds = 0;
+    })
+    .await;
+    for _ in 0..3 {
+        log_in(&app, &uuid::Uuid::new_v4().to_string(), "guess").await;
+    }
+
+    // act
+    let response = log_in(&app, &app.test_user.username, &app.test_user.password).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn a_forged_client_address_does_not_escape_the_lockout() {
+    // arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttle.max_failures_per_ip = 3;
+        c.login_throttle.initial_delay_milliseconds = 0;
+    })
+    .await;
+    for i in 0..3 {
+        reqwest::Client::new()
+            .get(format!("{}/admin/suppressions", app.address))
+            .basic_auth(uuid::Uuid::new_v4().to_string(), Some("guess"))
+            .header("X-Real-IP", format!("203.0.113.{}", i))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+    }
+
+    // act
+    let response = log_in(&app, &app.test_user.username, &app.test_user.password).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn a_successful_login_forgives_earlier_failures() {
+    // arrange
+    let app = spawn_app_with(strict).await;
+    let username = app.test_user.username.clone();
+
+    // act
+    for _ in 0..2 {
+        log_in(&app, &username, "guess").await;
+    }
+    log_in(&app, &username, &app.test_user.password)
+        .await
+        .error_for_status()
+        .unwrap();
+    for _ in 0..2 {
+        log_in(&app, &username, "guess").await;
+    }
+    let response = log_in(&app, &username, &app.test_user.password).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn owners_can_see_and_lift_lockouts() {
+    // arrange
+    let app = spawn_app_with(strict).await;
+    let editor = app.add_user("editor");
+    for _ in 0..3 {
+        log_in(&app, &editor.username, "guess").await;
+    }
+    let client = reqwest::Client::new();
+    let admin_url = format!("{}/admin/login-throttles", app.address);
+
+    // act
+    let listed: serde_json::Value = client
+        .get(&admin_url)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let unlock = |subject: String| {
+        client
+            .delete(&admin_url)
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .json(&serde_json::json!({ "kind": "username", "subject": subject }))
+            .send()
+    };
+    let unlocked = unlock(editor.username.clone()).await.unwrap();
+    let unlocked_again = unlock(editor.username.clone()).await.unwrap();
+
+    // assert
+    assert_eq!(listed[0]["kind"], "username");
+    assert_eq!(listed[0]["subject"], editor.username.as_str());
+    assert_eq!(listed[0]["failures"], 3);
+    assert!(!listed[0]["locked_until"].is_null());
+    assert_eq!(listed[1]["kind"], "ip");
+    assert!(listed[1]["locked_until"].is_null());
+    assert_eq!(unlocked.status().as_u16(), 204);
+    assert_eq!(unlocked_again.status().as_u16(), 404);
+    let response = log_in(&app, &editor.username, &editor.password).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn passwords_are_rehashed_with_new_parameters_on_login() {
+    // arrange
+    let app = spawn_app_with(|c| c.password_hashing.iterations = 3).await;
+    let (username, password) = (&app.test_user.username, &app.test_user.password);
+    let stored_hash = || {
+        use zero2prod::schema::users;
+        users::table
+            .find(app.test_user.user_id)
+            .select(users::password_hash)
+            .first::<String>(&app.db_connection)
+            .unwrap()
+    };
+    assert!(stored_hash().contains("t=2"));
+
+    // act
+    let response = log_in(&app, username, password).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(stored_hash().contains("m=15000,t=3,p=1"));
+    let response = log_in(&app, username, password).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -1,5 +1,19 @@
+mod admin_log_level;
+mod admin_suppressions;
+mod api_tokens;
+mod audit_log;
 mod health_check;
 mod helpers;
+mod issue_deliveries;
+mod login_throttle;
+mod metrics;
 mod newsletters;
+mod request_id;
+mod roles;
+mod ses_webhook;
+mod shutdown;
 mod subscriptions;
 mod subscriptions_confirm;
+mod trace_context;
+mod tracking;
+mod two_factor;
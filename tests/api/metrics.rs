@@ -0,0 +1,96 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use secrecy::Secret;
+
+#[tokio::test]
+async fn metrics_are_served_to_allowed_addresses() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+
+    // act
+    let response = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let metrics = response.text().await.unwrap();
+    assert!(metrics
+        .contains(r#"http_requests_total{method="POST",route="/subscriptions",status="200"}"#));
+    assert!(metrics.contains("http_request_duration_seconds_bucket"));
+    assert!(metrics.contains(r#"emails_sent_total{backend="mock",outcome="success"}"#));
+    assert!(metrics.contains(r#"subscribers{status="pending_confirmation"} 1"#));
+    assert!(metrics.contains(r#"subscribers{status="confirmed"} 0"#));
+    assert!(metrics.contains("db_pool_checkout_duration_seconds_count"));
+}
+
+#[tokio::test]
+async fn password_verification_is_timed() {
+    // arrange
+    let app = spawn_app().await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+
+    // act
+    let response = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    let metrics = response.text().await.unwrap();
+    assert!(metrics.contains("password_verification_duration_seconds_count"));
+    assert!(metrics.contains("argon2_queue_wait_seconds_count"));
+    assert!(metrics.contains("argon2_jobs_in_flight"));
+}
+
+#[tokio::test]
+async fn metrics_are_forbidden_to_other_addresses_without_a_token() {
+    // arrange
+    let app = spawn_app_with(|c| c.metrics.allowed_addresses.clear()).await;
+
+    // act
+    let response = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 403);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "metrics_forbidden");
+}
+
+#[tokio::test]
+async fn metrics_are_served_to_scrapers_with_the_configured_token() {
+    // arrange
+    let app = spawn_app_with(|c| {
+        c.metrics.allowed_addresses.clear();
+        c.metrics.bearer_token = Some(Secret::new("scrape-token".into()));
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    // act
+    let with_token = client
+        .get(format!("{}/metrics", app.address))
+        .bearer_auth("scrape-token")
+        .send()
+        .await
+        .unwrap();
+    let with_wrong_token = client
+        .get(format!("{}/metrics", app.address))
+        .bearer_auth("guessed-token")
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(with_token.status().as_u16(), 200);
+    assert_eq!(with_wrong_token.status().as_u16(), 403);
+}
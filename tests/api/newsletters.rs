@@ -87,7 +87,11 @@ async fn newsletters_returns_400_for_invalid_data() {
             response.status().as_u16(),
             "The API did not fail with 400 Bad Request when the payload was {}.",
             error_message
-        )
+        );
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "application/problem+json"
+        );
     }
 }
 
@@ -116,6 +120,8 @@ async fn requests_missing_authorization_are_rejected() {
         r#"Basic realm="publish""#,
         response.headers()["WWW-Authenticate"]
     );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "missing_credentials");
 }
 
 #[tokio::test]
@@ -147,6 +153,8 @@ async fn non_existing_user_is_rejected() {
         r#"Basic realm="publish""#,
         response.headers()["WWW-Authenticate"]
     );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_credentials");
 }
 
 #[tokio::test]
@@ -180,9 +188,11 @@ async fn invalid_password_is_rejected() {
         r#"Basic realm="publish""#,
         response.headers()["WWW-Authenticate"]
     );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_credentials");
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     app.post_subscriptions(body.into())
         .await
@@ -193,12 +203,11 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     app.get_confirmation_links(&emails.pop().unwrap())
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
+pub async fn create_confirmed_subscriber(app: &TestApp) {
     let confirmation_link = create_unconfirmed_subscriber(app).await;
 
-    reqwest::get(confirmation_link.html)
+    app.confirm_subscription(confirmation_link.html)
         .await
-        .unwrap()
         .error_for_status()
         .unwrap();
 }
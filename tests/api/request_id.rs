@@ -0,0 +1,80 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn responses_carry_a_generated_request_id() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    let request_id = response.headers().get("X-Request-Id");
+    assert!(matches!(request_id, Some(id) if !id.is_empty()));
+}
+
+#[tokio::test]
+async fn an_incoming_request_id_is_echoed_in_headers_and_error_bodies() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "edge-proxy-1234")
+        .body("name=le%20guin")
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.headers()["X-Request-Id"], "edge-proxy-1234");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["request_id"], "edge-proxy-1234");
+}
+
+#[tokio::test]
+async fn unusable_incoming_request_ids_are_replaced() {
+    // arrange
+    let app = spawn_app().await;
+    let too_long = "x".repeat(200);
+    let test_cases = vec!["", "has spaces", too_long.as_str()];
+
+    for invalid_id in test_cases {
+        // act
+        let response = reqwest::Client::new()
+            .get(format!("{}/health_check", app.address))
+            .header("X-Request-Id", invalid_id)
+            .send()
+            .await
+            .unwrap();
+
+        // assert
+        let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
+        assert!(!request_id.is_empty());
+        assert_ne!(request_id, invalid_id, "{:?} was not replaced", invalid_id);
+    }
+}
+
+#[tokio::test]
+async fn unmatched_routes_still_get_a_request_id() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::get(format!("{}/does-not-exist", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 404);
+    let request_id = response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["request_id"], request_id);
+}
@@ -0,0 +1,173 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use crate::newsletters::create_confirmed_subscriber;
+
+async fn publish_as(app: &TestApp, user: &TestUser) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_as(app: &TestApp, user: &TestUser, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}{}", app.address, path))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn put_role(app: &TestApp, user_id: &str, role: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!("{}/admin/users/{}/role", app.address, user_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "role": role }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn viewers_are_refused_publishing_with_an_explanation() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let viewer = app.add_user("viewer");
+
+    // act
+    let response = publish_as(&app, &viewer).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(
+        response.headers().get("WWW-Authenticate").is_none(),
+        "A 403 should not prompt for other credentials."
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "forbidden");
+    assert_eq!(
+        problem["detail"],
+        "The 'viewer' role may not publish newsletters."
+    );
+    assert!(app.email_client.sent_emails.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn viewers_can_read_and_editors_can_publish() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let viewer = app.add_user("viewer");
+    let editor = app.add_user("editor");
+
+    // act
+    let suppressions = get_as(&app, &viewer, "/admin/suppressions").await;
+    let published = publish_as(&app, &editor).await;
+
+    // assert
+    assert_eq!(suppressions.status().as_u16(), 200);
+    assert_eq!(published.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn only_owners_manage_users_tokens_and_settings() {
+    // arrange
+    let app = spawn_app().await;
+    let editor = app.add_user("editor");
+
+    for path in ["/admin/users", "/admin/api-tokens", "/admin/log-level"] {
+        // act
+        let response = get_as(&app, &editor, path).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            403,
+            "An editor was not refused {}.",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn owners_can_change_roles() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let user = app.add_user("viewer");
+
+    // act
+    let response = put_role(&app, &user.user_id.to_string(), "editor").await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let updated: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(updated["role"], "editor");
+    assert_eq!(publish_as(&app, &user).await.status().as_u16(), 200);
+    let users: serde_json::Value = get_as(&app, &app.test_user, "/admin/users")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(users.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn invalid_role_changes_are_rejected() {
+    // arrange
+    let app = spawn_app().await;
+    let owner_id = app.test_user.user_id.to_string();
+    let unknown_id = uuid::Uuid::new_v4().to_string();
+    let test_cases = vec![
+        (owner_id.as_str(), "viewer", 409, "demoting the last owner"),
+        (owner_id.as_str(), "admin", 400, "an unknown role"),
+        (unknown_id.as_str(), "editor", 404, "an unknown user"),
+    ];
+
+    for (user_id, role, status, description) in test_cases {
+        // act
+        let response = put_role(&app, user_id, role).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            status,
+            "The API did not reject {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn an_owner_can_step_down_once_there_is_another() {
+    // arrange
+    let app = spawn_app().await;
+    let other_owner = app.add_user("owner");
+
+    // act
+    let response = put_role(&app, &app.test_user.user_id.to_string(), "editor").await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let users: serde_json::Value = get_as(&app, &other_owner, "/admin/users")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let owners = users
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|u| u["role"] == "owner")
+        .count();
+    assert_eq!(owners, 1);
+}
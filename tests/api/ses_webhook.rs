@@ -0,0 +1,374 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use crate::newsletters::create_confirmed_subscriber;
+use chrono::{DateTime, SecondsFormat, Utc};
+use diesel::prelude::*;
+use once_cell::sync::Lazy;
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::sign::Signer;
+use openssl::x509::{X509NameBuilder, X509};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+use uuid::Uuid;
+
+const TOPIC_ARN: &str = "arn:aws:sns:us-west-2:123456789012:ses-events";
+const SIGNING_CERT_URL: &str =
+    "https://sns.us-west-2.amazonaws.com/SimpleNotificationService-test.pem";
+
+const BOUNCE: &str = include_str!("../fixtures/ses/bounce.json");
+const COMPLAINT: &str = include_str!("../fixtures/ses/complaint.json");
+const DELIVERY: &str = include_str!("../fixtures/ses/delivery.json");
+
+/// Signs messages the way SNS does, with a certificate that the apps under
+/// test read from `dir` instead of downloading it.
+struct SnsSigner {
+    key: PKey<Private>,
+    dir: PathBuf,
+}
+
+static SIGNER: Lazy<SnsSigner> = Lazy::new(|| {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "sns.amazonaws.com")
+        .unwrap();
+    let name = name.build();
+    let mut certificate = X509::builder().unwrap();
+    certificate.set_subject_name(&name).unwrap();
+    certificate.set_issuer_name(&name).unwrap();
+    certificate.set_pubkey(&key).unwrap();
+    certificate
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    certificate
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    certificate.sign(&key, MessageDigest::sha256()).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("sns-certificates-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("SimpleNotificationService-test.pem"),
+        certificate.build().to_pem().unwrap(),
+    )
+    .unwrap();
+    SnsSigner { key, dir }
+});
+
+impl SnsSigner {
+    fn sign(&self, mut message: serde_json::Value, signed_fields: &[&str]) -> serde_json::Value {
+        let string_to_sign: String = signed_fields
+            .iter()
+            .map(|field| format!("{}\n{}\n", field, message[field].as_str().unwrap()))
+            .collect();
+        let mut signer = Signer::new(MessageDigest::sha1(), &self.key).unwrap();
+        signer.update(string_to_sign.as_bytes()).unwrap();
+        message["Signature"] = base64::encode(signer.sign_to_vec().unwrap()).into();
+        message
+    }
+
+    fn notification(&self, ses_event: &str) -> serde_json::Value {
+        self.notification_sent_at(ses_event, Utc::now())
+    }
+
+    fn notification_sent_at(&self, ses_event: &str, sent_at: DateTime<Utc>) -> serde_json::Value {
+        self.sign(
+            serde_json::json!({
+                "Type": "Notification",
+                "MessageId": Uuid::new_v4().to_string(),
+                "TopicArn": TOPIC_ARN,
+                "Message": ses_event,
+                "Timestamp": timestamp(sent_at),
+                "SignatureVersion": "1",
+                "SigningCertURL": SIGNING_CERT_URL,
+                "UnsubscribeURL": "https://sns.us-west-2.amazonaws.com/?Action=Unsubscribe",
+            }),
+            &["Message", "MessageId", "Timestamp", "TopicArn", "Type"],
+        )
+    }
+
+    fn subscription_confirmation(&self, subscribe_url: &str) -> serde_json::Value {
+        self.sign(
+            serde_json::json!({
+                "Type": "SubscriptionConfirmation",
+                "MessageId": Uuid::new_v4().to_string(),
+                "Token": "2336412f37fb687f5d51e6e2425f004aed",
+                "TopicArn": TOPIC_ARN,
+                "Message": format!("You have chosen to subscribe to the topic {}.", TOPIC_ARN),
+                "SubscribeURL": subscribe_url,
+                "Timestamp": timestamp(Utc::now()),
+                "SignatureVersion": "1",
+                "SigningCertURL": SIGNING_CERT_URL,
+            }),
+            &[
+                "Message",
+                "MessageId",
+                "SubscribeURL",
+                "Timestamp",
+                "Token",
+                "TopicArn",
+                "Type",
+            ],
+        )
+    }
+}
+
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|c| {
+        c.ses_webhook.topic_arns = vec![TOPIC_ARN.into()];
+        c.ses_webhook.certificates_dir = Some(SIGNER.dir.clone());
+    })
+    .await
+}
+
+async fn post_sns(app: &TestApp, message: &serde_json::Value) -> reqwest::Response {
+    // SNS declares its JSON as plain text
+    reqwest::Client::new()
+        .post(format!("{}/webhooks/ses", app.address))
+        .header("Content-Type", "text/plain; charset=UTF-8")
+        .header("x-amz-sns-message-type", message["Type"].as_str().unwrap())
+        .body(message.to_string())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn subscriber_status(app: &TestApp) -> String {
+    use zero2prod::schema::subscriptions::dsl::*;
+    subscriptions
+        .select(status)
+        .first(&app.db_connection)
+        .unwrap()
+}
+
+fn suppression_reason(app: &TestApp) -> Option<String> {
+    use zero2prod::schema::suppressions::dsl::*;
+    suppressions
+        .select(reason)
+        .filter(canonical_email.eq("ursula_le_guin@gmail.com"))
+        .first(&app.db_connection)
+        .optional()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_permanent_bounce_suppresses_the_subscriber() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.email_client.sent_emails.lock().unwrap().clear();
+
+    // act
+    let response = post_sns(&app, &SIGNER.notification(BOUNCE)).await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app), "bounced");
+    assert_eq!(suppression_reason(&app).as_deref(), Some("bounce"));
+    assert!(app.email_client.sent_emails.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn a_complaint_suppresses_the_subscriber() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // act
+    let response = post_sns(&app, &SIGNER.notification(COMPLAINT)).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app), "complained");
+    assert_eq!(suppression_reason(&app).as_deref(), Some("complaint"));
+    let suppressed = app.audit_entries("?action=suppression.added").await;
+    assert_eq!(suppressed.len(), 1);
+    assert_eq!(suppressed[0]["actor_type"], "system");
+    assert_eq!(suppressed[0]["after"]["reason"], "complaint");
+    assert!(!suppressed[0].to_string().contains("@"));
+    let changed = app.audit_entries("?action=subscriber.status_changed").await;
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0]["actor_type"], "system");
+    assert_eq!(changed[0]["before"]["status"], "confirmed");
+    assert_eq!(changed[0]["after"]["status"], "complained");
+}
+
+#[tokio::test]
+async fn deliveries_are_acknowledged_without_changes() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // act
+    let response = post_sns(&app, &SIGNER.notification(DELIVERY)).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app), "confirmed");
+    assert_eq!(suppression_reason(&app), None);
+}
+
+#[tokio::test]
+async fn redelivered_notifications_are_accepted() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let notification = SIGNER.notification(BOUNCE);
+
+    // act
+    let first = post_sns(&app, &notification).await;
+    let second = post_sns(&app, &notification).await;
+
+    // assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let audited = app.audit_entries("?actor=system").await;
+    let actions: Vec<_> = audited.iter().map(|entry| &entry["action"]).collect();
+    assert_eq!(
+        actions.len(),
+        2,
+        "Only the first changes anything: {:?}",
+        actions
+    );
+}
+
+#[tokio::test]
+async fn untrusted_messages_are_rejected() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut tampered = SIGNER.notification(DELIVERY);
+    tampered["Message"] = BOUNCE.into();
+    let mut other_topic = SIGNER.notification(BOUNCE);
+    other_topic["TopicArn"] = "arn:aws:sns:us-west-2:210987654321:ses-events".into();
+    let mut foreign_certificate = SIGNER.notification(BOUNCE);
+    foreign_certificate["SigningCertURL"] =
+        "https://example.com/SimpleNotificationService-test.pem".into();
+    let replayed = SIGNER.notification_sent_at(BOUNCE, Utc::now() - chrono::Duration::hours(2));
+    let test_cases = vec![
+        (tampered, "a tampered message"),
+        (other_topic, "a message from another topic"),
+        (foreign_certificate, "a certificate not hosted by SNS"),
+        (replayed, "a message sent two hours ago"),
+    ];
+
+    for (message, description) in test_cases {
+        // act
+        let response = post_sns(&app, &message).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            403,
+            "The API did not reject {}.",
+            description
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "untrusted_sns_message");
+    }
+    assert_eq!(subscriber_status(&app), "confirmed");
+}
+
+#[tokio::test]
+async fn malformed_messages_are_rejected() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/ses", app.address))
+        .header("Content-Type", "text/plain; charset=UTF-8")
+        .body("this is not json")
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_sns_message");
+}
+
+#[tokio::test]
+async fn subscription_confirmations_visit_the_subscribe_url() {
+    // arrange
+    let app = spawn_app().await;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let subscribe_url = format!(
+        "http://{}/?Action=ConfirmSubscription&Token=2336412f37fb687f5d51e6e2425f004aed",
+        listener.local_addr().unwrap()
+    );
+    let (visited, visits) = mpsc::channel();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request_line = String::new();
+        BufReader::new(stream.try_clone().unwrap())
+            .read_line(&mut request_line)
+            .unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        visited.send(request_line).unwrap();
+    });
+
+    // act
+    let response = post_sns(&app, &SIGNER.subscription_confirmation(&subscribe_url)).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let request_line = visits.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(request_line.starts_with("GET /?Action=ConfirmSubscription&Token="));
+}
+
+#[tokio::test]
+async fn a_bounce_marks_the_delivery_it_refers_to() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let published: serde_json::Value = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let message_id = app.email_client.sent_emails.lock().unwrap()[0]
+        .message_id
+        .clone();
+    let mut bounce: serde_json::Value = serde_json::from_str(BOUNCE).unwrap();
+    bounce["mail"]["messageId"] = message_id.into();
+
+    // act
+    let response = post_sns(&app, &SIGNER.notification(&bounce.to_string())).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = app
+        .get_issue_deliveries(published["issue_id"].as_str().unwrap(), "")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["deliveries"][0]["status"], "bounced");
+}
@@ -0,0 +1,75 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use crate::newsletters::create_confirmed_subscriber;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+fn publish_in_background(app: &TestApp) -> JoinHandle<reqwest::Response> {
+    let request = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }));
+    tokio::spawn(async move { request.send().await.expect("Failed to execute request.") })
+}
+
+async fn wait_until_publishing(app: &TestApp) {
+    for _ in 0..100 {
+        let publishing = app
+            .shutdown
+            .in_flight()
+            .iter()
+            .any(|work| work.starts_with("Publishing"));
+        if publishing {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The newsletter issue was never published.");
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_a_newsletter_issue_to_be_published() {
+    // arrange
+    let mut app = spawn_app_with(|c| c.shutdown.grace_period_seconds = 10).await;
+    create_confirmed_subscriber(&app).await;
+    app.email_client.sent_emails.lock().unwrap().clear();
+    app.email_client.delay_sends(Duration::from_millis(500));
+    let publish = publish_in_background(&app);
+    wait_until_publishing(&app).await;
+
+    // act
+    let report = app.stop().await;
+
+    // assert
+    assert!(report.is_clean(), "Unfinished: {:?}", report.unfinished);
+    assert_eq!(publish.await.unwrap().status().as_u16(), 200);
+    assert_eq!(app.email_client.sent_emails.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn shutdown_reports_work_left_unfinished_after_the_grace_period() {
+    // arrange
+    let mut app = spawn_app_with(|c| c.shutdown.grace_period_seconds = 1).await;
+    create_confirmed_subscriber(&app).await;
+    app.email_client.delay_sends(Duration::from_secs(10));
+    let _publish = publish_in_background(&app);
+    wait_until_publishing(&app).await;
+
+    // act
+    let report = app.stop().await;
+
+    // assert
+    assert!(report
+        .unfinished
+        .iter()
+        .any(|work| work == "Publishing 'Newsletter title': 0 of 1 subscribers done"));
+    assert!(report
+        .unfinished
+        .iter()
+        .any(|work| work == "POST /newsletters"));
+}
@@ -1,6 +1,9 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, BROWSER_ACCEPT};
 use claim::assert_some;
-use diesel::RunQueryDsl;
+use diesel::{QueryDsl, RunQueryDsl};
+use zero2prod::canonical_emails::{recanonicalize, Recanonicalized};
+use zero2prod::configuration::EmailPolicySettings;
+use zero2prod::domain::EmailPolicy;
 use zero2prod::models::*;
 use zero2prod::schema::subscriptions::dsl::subscriptions;
 
@@ -36,6 +39,10 @@ async fn subscribe_persists_the_new_subscriber() {
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(
+        saved.canonical_email.as_deref(),
+        Some("ursula_le_guin@gmail.com")
+    );
 }
 
 #[tokio::test]
@@ -60,6 +67,32 @@ async fn subscribe_returns_a_500_for_duplicate_email() {
     assert_eq!(saved.name, "le guin");
 }
 
+#[tokio::test]
+async fn subscribe_returns_a_500_for_an_email_differing_only_in_case() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    app.post_subscriptions("name=Ursula&email=Ursula%40Domain.com".into())
+        .await;
+    let response = app
+        .post_subscriptions("name=Ursula&email=ursula%40domain.com".into())
+        .await;
+
+    // assert
+    assert_eq!(500, response.status().as_u16());
+
+    let saved = subscriptions
+        .load::<Subscription>(&app.db_connection)
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula@Domain.com");
+    assert_eq!(
+        saved[0].canonical_email.as_deref(),
+        Some("ursula@domain.com")
+    );
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     // arrange
@@ -173,3 +206,323 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_returns_a_400_with_a_reason_when_the_email_policy_rejects_the_address() {
+    // arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "name=Ursula&email=ursula%40mailinator.com",
+            "disposable_domain",
+        ),
+        ("name=Ursula&email=postmaster%40domain.com", "role_account"),
+    ];
+
+    for (body, reason) in test_cases {
+        // act
+        let response = app.post_subscriptions(body.into()).await;
+
+        // assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for a {}.",
+            reason
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "email_policy_violation");
+        assert_eq!(body["errors"][0]["field"], "email");
+        assert_eq!(body["errors"][0]["code"], reason);
+    }
+    assert_eq!(app.email_client.sent_emails.lock().unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn subscribe_reports_every_invalid_field_as_problem_details() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=&email=definitely-not-an-email";
+
+    // act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // assert
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], 400);
+    assert_eq!(body["code"], "validation_failed");
+    assert!(body["request_id"].is_string());
+    let errors: Vec<_> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["field"].as_str().unwrap(), e["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(errors, vec![("email", "invalid"), ("name", "invalid")]);
+}
+
+#[tokio::test]
+async fn subscribe_reports_missing_fields_as_problem_details() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = app.post_subscriptions("".into()).await;
+
+    // assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert_eq!(body["errors"][0]["code"], "missing");
+    assert_eq!(body["errors"][1]["field"], "name");
+    assert_eq!(body["errors"][1]["code"], "missing");
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json() {
+    // arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+    });
+
+    // act
+    let response = app.post_subscriptions_json(body).await;
+
+    // assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = subscriptions
+        .first::<Subscription>(&app.db_connection)
+        .expect("Result set was empty.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(app.email_client.sent_emails.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn subscribe_validates_json_like_form_data() {
+    // arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({ "name": "Ursula" });
+
+    // act
+    let response = app.post_subscriptions_json(body).await;
+
+    // assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation_failed");
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert_eq!(body["errors"][0]["code"], "missing");
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_malformed_json() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/json")
+        .body("{\"name\": ")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_rejects_unsupported_content_types() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "text/plain")
+        .body("ursula_le_guin@gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // assert
+    assert_eq!(415, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn preflight_requests_from_allowed_origins_are_answered() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::Client::new()
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/subscriptions", &app.address),
+        )
+        .header("Origin", "http://localhost:3000")
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "Content-Type")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // assert
+    assert_eq!(204, response.status().as_u16());
+    let headers = response.headers();
+    assert_eq!(
+        headers["Access-Control-Allow-Origin"],
+        "http://localhost:3000"
+    );
+    assert_eq!(headers["Access-Control-Allow-Methods"], "GET, POST");
+    assert_eq!(headers["Access-Control-Allow-Headers"], "Content-Type");
+    assert_eq!(headers["Access-Control-Max-Age"], "3600");
+    assert_eq!(headers["Vary"], "Origin");
+}
+
+#[tokio::test]
+async fn cors_headers_are_not_sent_to_other_origins() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Origin", "https://evil.example.com")
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .headers()
+        .get("Access-Control-Allow-Origin")
+        .is_none());
+    assert_eq!(response.headers()["Vary"], "Origin");
+}
+
+#[tokio::test]
+async fn the_subscribe_page_contains_a_form() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscribe", &app.address))
+        .header("Accept", BROWSER_ACCEPT)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<form action="/subscriptions" method="post">"#));
+}
+
+#[tokio::test]
+async fn browsers_are_asked_to_check_their_inbox_after_subscribing() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // act
+    let response = app.post_subscriptions_from_browser(body.into()).await;
+
+    // assert
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Check your inbox"));
+}
+
+#[tokio::test]
+async fn browsers_get_the_form_back_with_errors_for_invalid_data() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=Ursula&email=definitely-not-an-email";
+
+    // act
+    let response = app.post_subscriptions_from_browser(body.into()).await;
+
+    // assert
+    assert_eq!(400, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("definitely-not-an-email is not a valid subscriber email."));
+    assert!(html.contains(r#"<form action="/subscriptions" method="post">"#));
+}
+
+#[tokio::test]
+async fn existing_subscribers_are_brought_in_line_with_the_email_policy() {
+    // arrange
+    let app = spawn_app().await;
+    for email in [
+        "Ursula.LeGuin%40gmail.com",
+        "ursulaleguin%2Bnews%40gmail.com",
+        "le-guin%40b%C3%BCcher.example",
+    ] {
+        app.post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    // what the migration that added the column backfilled
+    diesel::sql_query("UPDATE subscriptions SET canonical_email = lower(email)")
+        .execute(&app.db_connection)
+        .unwrap();
+    let email_policy = EmailPolicy::new(&EmailPolicySettings {
+        apply_provider_rules: true,
+        ..Default::default()
+    })
+    .unwrap();
+
+    // act
+    let first = recanonicalize(&app.db_connection, &email_policy).unwrap();
+    let second = recanonicalize(&app.db_connection, &email_policy).unwrap();
+
+    // assert
+    assert_eq!(
+        first,
+        Recanonicalized {
+            updated: 3,
+            collisions: 1
+        }
+    );
+    assert_eq!(
+        second,
+        Recanonicalized {
+            updated: 0,
+            collisions: 1
+        }
+    );
+    let saved = subscriptions
+        .order(zero2prod::schema::subscriptions::subscribed_at)
+        .load::<Subscription>(&app.db_connection)
+        .unwrap();
+    let canonical: Vec<_> = saved.iter().map(|s| s.canonical_email.as_deref()).collect();
+    assert_eq!(
+        canonical,
+        vec![
+            Some("ursulaleguin@gmail.com"),
+            None,
+            Some("le-guin@xn--bcher-kva.example")
+        ]
+    );
+}
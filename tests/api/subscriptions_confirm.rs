@@ -1,7 +1,8 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, BROWSER_ACCEPT};
 use claim::assert_some;
-use diesel::RunQueryDsl;
+use diesel::{ExpressionMethods, RunQueryDsl};
 use zero2prod::models::*;
+use zero2prod::schema::subscription_tokens;
 use zero2prod::schema::subscriptions::dsl::subscriptions;
 
 #[tokio::test]
@@ -40,12 +41,42 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     let confirmation_links = app.get_confirmation_links(&email);
 
     // act
-    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    let response = reqwest::Client::new()
+        .get(confirmation_links.html)
+        .header("Accept", BROWSER_ACCEPT)
+        .send()
+        .await
+        .unwrap();
 
     // assert
     assert_eq!(response.status().as_u16(), 200);
 }
 
+#[tokio::test]
+async fn api_clients_following_the_link_are_told_to_post_instead() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    let confirmation_links = {
+        let emails = app.email_client.sent_emails.lock().unwrap();
+        app.get_confirmation_links(emails.first().unwrap())
+    };
+
+    // act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 405);
+    assert_eq!(response.headers()["Allow"], "POST");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "confirmation_requires_post");
+    let saved = subscriptions
+        .first::<Subscription>(&app.db_connection)
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
 #[tokio::test]
 async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     // arrange
@@ -68,7 +99,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     let confirmation_links = app.get_confirmation_links(&email);
 
     // act
-    reqwest::get(confirmation_links.html).await.unwrap();
+    app.confirm_subscription(confirmation_links.html).await;
 
     // assert
     let saved = subscriptions
@@ -79,3 +110,198 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn browsers_see_a_success_page_after_confirming() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    let confirmation_links = {
+        let emails = app.email_client.sent_emails.lock().unwrap();
+        app.get_confirmation_links(emails.first().unwrap())
+    };
+
+    // act
+    let response = app.confirm_subscription(confirmation_links.html).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("You're subscribed!"));
+}
+
+#[tokio::test]
+async fn browsers_see_a_friendly_page_for_an_invalid_token() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/subscriptions/confirm?subscription_token=unknown",
+            app.address
+        ))
+        .header("Accept", BROWSER_ACCEPT)
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 401);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("This link is not valid"));
+}
+
+#[tokio::test]
+async fn following_the_confirmation_link_does_not_confirm_a_subscriber() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    let confirmation_links = {
+        let emails = app.email_client.sent_emails.lock().unwrap();
+        app.get_confirmation_links(emails.first().unwrap())
+    };
+
+    // act
+    let response = reqwest::Client::new()
+        .get(confirmation_links.html)
+        .header("Accept", BROWSER_ACCEPT)
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<form action="/subscriptions/confirm" method="post">"#));
+    let saved = subscriptions
+        .first::<Subscription>(&app.db_connection)
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn confirmations_without_a_matching_csrf_token_are_rejected() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    let confirmation_links = {
+        let emails = app.email_client.sent_emails.lock().unwrap();
+        app.get_confirmation_links(emails.first().unwrap())
+    };
+    let subscription_token = confirmation_links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap()
+        .1
+        .to_string();
+
+    // act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/confirm", app.address))
+        .form(&[
+            ("subscription_token", subscription_token.as_str()),
+            ("csrf_token", "forged"),
+        ])
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 403);
+    let saved = subscriptions
+        .first::<Subscription>(&app.db_connection)
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+fn subscription_token(confirmation_link: &reqwest::Url) -> String {
+    confirmation_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap()
+        .1
+        .to_string()
+}
+
+#[tokio::test]
+async fn subscription_tokens_are_not_stored_in_plaintext() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // act
+    app.post_subscriptions(body.into()).await;
+
+    // assert
+    let confirmation_links = {
+        let emails = app.email_client.sent_emails.lock().unwrap();
+        app.get_confirmation_links(emails.first().unwrap())
+    };
+    let stored = subscription_tokens::table
+        .first::<SubscriptionToken>(&app.db_connection)
+        .expect("Failed to fetch saved subscription token.");
+    assert!(stored.hashed);
+    assert_ne!(
+        stored.subscription_token,
+        subscription_token(&confirmation_links.html)
+    );
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected_with_a_401() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    let confirmation_links = {
+        let emails = app.email_client.sent_emails.lock().unwrap();
+        app.get_confirmation_links(emails.first().unwrap())
+    };
+    diesel::update(subscription_tokens::table)
+        .set(subscription_tokens::created_at.eq(chrono::Utc::now() - chrono::Duration::days(365)))
+        .execute(&app.db_connection)
+        .unwrap();
+
+    // act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "expired_token");
+}
+
+#[tokio::test]
+async fn plaintext_tokens_issued_before_hashing_still_confirm_a_subscriber() {
+    // arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    diesel::update(subscription_tokens::table)
+        .set((
+            subscription_tokens::subscription_token.eq("legacyplaintexttoken"),
+            subscription_tokens::hashed.eq(false),
+        ))
+        .execute(&app.db_connection)
+        .unwrap();
+    let confirmation_link = reqwest::Url::parse(&format!(
+        "{}/subscriptions/confirm?subscription_token=legacyplaintexttoken",
+        app.address
+    ))
+    .unwrap();
+
+    // act
+    let response = app.confirm_subscription(confirmation_link).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = subscriptions
+        .first::<Subscription>(&app.db_connection)
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
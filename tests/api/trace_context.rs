@@ -0,0 +1,26 @@
+use crate::helpers::{spawn_app, COLLECTOR};
+
+#[tokio::test]
+async fn requests_join_the_trace_of_an_incoming_traceparent() {
+    // arrange
+    let app = spawn_app().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // act
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    let trace_id = hex::decode(trace_id).unwrap();
+    assert!(COLLECTOR.received_trace(&trace_id).await);
+}
@@ -0,0 +1,228 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use crate::newsletters::create_confirmed_subscriber;
+use reqwest::Url;
+
+const HTML: &str = r#"<html><body>
+<p>Read <a href="https://example.com/story?id=1&amp;ref=news">the story</a>
+or <a href="https://example.com/archive">the archive</a>.</p>
+<p><a href="https://example.com/story?id=1&amp;ref=news">Read it now</a></p>
+</body></html>"#;
+
+async fn publish(app: &TestApp, tracking: serde_json::Value) -> String {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": HTML,
+            },
+            "tracking": tracking,
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    let published: serde_json::Value = response.json().await.unwrap();
+    published["issue_id"].as_str().unwrap().to_string()
+}
+
+/// The tracking links in the last email sent, pointed at the app under test.
+fn tracking_links(app: &TestApp, path: &str) -> Vec<Url> {
+    let emails = app.email_client.sent_emails.lock().unwrap();
+    linkify::LinkFinder::new()
+        .links(&emails.last().unwrap().html_content)
+        .filter(|l| l.as_str().contains(path))
+        .map(|l| {
+            let mut link = Url::parse(l.as_str()).unwrap();
+            link.set_port(Some(app.port)).unwrap();
+            link
+        })
+        .collect()
+}
+
+async fn get_stats(app: &TestApp, issue_id: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/issues/{}/stats", app.address, issue_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn no_redirects() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn tracking_is_off_by_default() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // act
+    let issue_id = publish(&app, serde_json::json!({})).await;
+
+    // assert
+    let emails = app.email_client.sent_emails.lock().unwrap();
+    assert_eq!(emails.last().unwrap().html_content, HTML);
+    drop(emails);
+    let stats: serde_json::Value = get_stats(&app, &issue_id).await.json().await.unwrap();
+    assert_eq!(stats["track_opens"], false);
+    assert_eq!(stats["track_clicks"], false);
+}
+
+#[tokio::test]
+async fn clicks_are_redirected_and_counted_by_link() {
+    // arrange
+    let app = spawn_app_with(|c| c.tracking.clicks = true).await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish(&app, serde_json::json!({})).await;
+    let links = tracking_links(&app, "/t/c/");
+
+    // act
+    let mut responses = Vec::new();
+    for link in [&links[0], &links[0], &links[1]] {
+        responses.push(no_redirects().get(link.clone()).send().await.unwrap());
+    }
+
+    // assert
+    assert_eq!(links.len(), 3);
+    assert_eq!(links[0], links[2], "Repeated links should share a token.");
+    assert_eq!(responses[0].status().as_u16(), 302);
+    assert_eq!(
+        responses[0].headers()["Location"],
+        "https://example.com/story?id=1&ref=news"
+    );
+    assert_eq!(
+        responses[2].headers()["Location"],
+        "https://example.com/archive"
+    );
+    let stats: serde_json::Value = get_stats(&app, &issue_id).await.json().await.unwrap();
+    assert_eq!(
+        stats["links"],
+        serde_json::json!([
+            {
+                "url": "https://example.com/story?id=1&ref=news",
+                "unique_clicks": 1,
+                "total_clicks": 2,
+            },
+            {
+                "url": "https://example.com/archive",
+                "unique_clicks": 1,
+                "total_clicks": 1,
+            },
+        ])
+    );
+    assert!(tracking_links(&app, "/t/o/").is_empty());
+}
+
+#[tokio::test]
+async fn opens_are_tracked_when_the_issue_opts_in() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish(&app, serde_json::json!({ "opens": true })).await;
+    let pixels = tracking_links(&app, "/t/o/");
+
+    // act
+    let first = reqwest::get(pixels[0].clone()).await.unwrap();
+    let second = reqwest::get(pixels[0].clone()).await.unwrap();
+
+    // assert
+    assert_eq!(pixels.len(), 1);
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(first.headers()["Content-Type"], "image/gif");
+    assert_eq!(second.status().as_u16(), 200);
+    let stats: serde_json::Value = get_stats(&app, &issue_id).await.json().await.unwrap();
+    assert_eq!(stats["track_opens"], true);
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["total_opens"], 2);
+}
+
+#[tokio::test]
+async fn an_issue_can_opt_out_of_tracking() {
+    // arrange
+    let app = spawn_app_with(|c| {
+        c.tracking.opens = true;
+        c.tracking.clicks = true;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+
+    // act
+    publish(&app, serde_json::json!({ "opens": false, "clicks": false })).await;
+
+    // assert
+    let emails = app.email_client.sent_emails.lock().unwrap();
+    assert_eq!(emails.last().unwrap().html_content, HTML);
+}
+
+#[tokio::test]
+async fn tampered_tracking_links_are_rejected() {
+    // arrange
+    let app = spawn_app_with(|c| {
+        c.tracking.opens = true;
+        c.tracking.clicks = true;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish(&app, serde_json::json!({})).await;
+    let click = tracking_links(&app, "/t/c/").remove(0);
+    let open = tracking_links(&app, "/t/o/").remove(0);
+    let token = click.path_segments().unwrap().next_back().unwrap();
+    let mut tampered = token.to_string();
+    tampered.replace_range(..1, if token.starts_with('A') { "B" } else { "A" });
+    let test_cases = vec![
+        (
+            format!("{}/t/c/{}", app.address, tampered),
+            "a tampered token",
+        ),
+        (
+            format!("{}/t/c/{}", app.address, "not-a-token"),
+            "a made-up token",
+        ),
+        (
+            open.as_str().replace("/t/o/", "/t/c/"),
+            "an open token used for a click",
+        ),
+    ];
+
+    for (url, description) in test_cases {
+        // act
+        let response = no_redirects().get(url).send().await.unwrap();
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            404,
+            "The API did not reject {}.",
+            description
+        );
+    }
+    let stats: serde_json::Value = get_stats(&app, &issue_id).await.json().await.unwrap();
+    assert_eq!(stats["links"][0]["total_clicks"], 0);
+}
+
+#[tokio::test]
+async fn issue_stats_require_credentials_and_a_known_issue() {
+    // arrange
+    let app = spawn_app().await;
+
+    // act
+    let anonymous = reqwest::get(format!(
+        "{}/admin/issues/{}/stats",
+        app.address,
+        uuid::Uuid::new_v4()
+    ))
+    .await
+    .unwrap();
+    let unknown = get_stats(&app, &uuid::Uuid::new_v4().to_string()).await;
+    let malformed = get_stats(&app, "not-an-id").await;
+
+    // assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(unknown.status().as_u16(), 404);
+    assert_eq!(malformed.status().as_u16(), 404);
+}
@@ -0,0 +1,296 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use chrono::Duration;
+use diesel::{QueryDsl, RunQueryDsl};
+use zero2prod::clock::Clock;
+use zero2prod::configuration::Settings;
+
+/// Any request that needs credentials will do.
+async fn log_in(app: &TestApp, otp: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .get(format!("{}/admin/suppressions", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password));
+    if let Some(otp) = otp {
+        request = request.header("X-OTP", otp);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+/// Any request that needs credentials, with a two-factor session.
+async fn log_in_with_session(app: &TestApp, session: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/suppressions", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("X-2FA-Session", session)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn start_session(app: &TestApp, header: (&str, &str)) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/2fa/session", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header(header.0, header.1)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Lets a test retry right after a rejected code.
+fn no_login_delay(c: &mut Settings) {
+    c.login_throttle.initial_delay_milliseconds = 0;
+}
+
+async fn problem_code(response: reqwest::Response) -> String {
+    let problem: serde_json::Value = response.json().await.unwrap();
+    problem["code"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn enrollment_takes_effect_once_a_code_is_confirmed() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+
+    // act
+    let enrollment: serde_json::Value =
+        app.post_two_factor_enrollment().await.json().await.unwrap();
+    let secret =
+        zero2prod::domain::TotpSecret::parse(enrollment["secret"].as_str().unwrap()).unwrap();
+    let wrong_code = app.post_two_factor_confirmation("000000").await;
+    let before_confirmation = log_in(&app, None).await;
+    let confirmation = app
+        .post_two_factor_confirmation(&secret.code_at(app.clock.now()))
+        .await;
+
+    // assert
+    let uri = enrollment["provisioning_uri"].as_str().unwrap();
+    assert!(uri.starts_with("otpauth://totp/"));
+    assert!(uri.contains(&format!(
+        "secret={}",
+        enrollment["secret"].as_str().unwrap()
+    )));
+    assert_eq!(wrong_code.status().as_u16(), 400);
+    assert_eq!(before_confirmation.status().as_u16(), 200);
+    assert_eq!(confirmation.status().as_u16(), 200);
+    let confirmation: serde_json::Value = confirmation.json().await.unwrap();
+    assert_eq!(confirmation["recovery_codes"].as_array().unwrap().len(), 10);
+    assert_eq!(log_in(&app, None).await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn enabled_users_must_send_a_current_code() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+    let (secret, _) = app.enable_two_factor().await;
+    let code = secret.code_at(app.clock.now());
+    let wrong_code = if code == "123456" { "654321" } else { "123456" };
+
+    // act
+    let without_code = log_in(&app, None).await;
+    let with_wrong_code = log_in(&app, Some(wrong_code)).await;
+    let with_code = log_in(&app, Some(&code)).await;
+
+    // assert
+    assert_eq!(without_code.status().as_u16(), 401);
+    assert_eq!(problem_code(without_code).await, "otp_required");
+    assert_eq!(with_wrong_code.status().as_u16(), 401);
+    assert_eq!(problem_code(with_wrong_code).await, "invalid_otp");
+    assert_eq!(with_code.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn codes_from_the_neighbouring_time_steps_are_accepted() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+    let (secret, _) = app.enable_two_factor().await;
+    let code = secret.code_at(app.clock.now());
+
+    // act
+    app.clock.advance(Duration::seconds(30));
+    let one_step_late = log_in(&app, Some(&code)).await;
+    app.clock.advance(Duration::seconds(90));
+    let unused_code = secret.code_at(app.clock.now() - Duration::seconds(60));
+    let two_steps_late = log_in(&app, Some(&unused_code)).await;
+
+    // assert
+    assert_eq!(one_step_late.status().as_u16(), 200);
+    assert_eq!(two_steps_late.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn codes_cannot_be_replayed() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+    let (secret, _) = app.enable_two_factor().await;
+    let code = secret.code_at(app.clock.now());
+    let earlier_code = secret.code_at(app.clock.now() - Duration::seconds(30));
+
+    // act
+    let first_use = log_in(&app, Some(&code)).await;
+    let replayed = log_in(&app, Some(&code)).await;
+    let earlier = log_in(&app, Some(&earlier_code)).await;
+    app.clock.advance(Duration::seconds(30));
+    let next_code = log_in(&app, Some(&secret.code_at(app.clock.now()))).await;
+
+    // assert
+    assert_eq!(first_use.status().as_u16(), 200);
+    assert_eq!(replayed.status().as_u16(), 401);
+    assert_eq!(problem_code(replayed).await, "invalid_otp");
+    assert_eq!(earlier.status().as_u16(), 401);
+    assert_eq!(next_code.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn totp_secrets_are_stored_encrypted() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+
+    // act
+    let (secret, _) = app.enable_two_factor().await;
+
+    // assert
+    use zero2prod::schema::users;
+    let stored: Option<String> = users::table
+        .find(app.test_user.user_id)
+        .select(users::totp_secret)
+        .first(&app.db_connection)
+        .unwrap();
+    assert!(!stored.unwrap().contains(&secret.to_base32()));
+}
+
+#[tokio::test]
+async fn recovery_codes_can_only_be_used_once() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+    let (_, recovery_codes) = app.enable_two_factor().await;
+    let typed = recovery_codes[0].to_uppercase();
+
+    // act
+    let first_use = log_in(&app, Some(&typed)).await;
+    let second_use = log_in(&app, Some(&typed)).await;
+    let other_code = log_in(&app, Some(&recovery_codes[1])).await;
+
+    // assert
+    assert_eq!(first_use.status().as_u16(), 200);
+    assert_eq!(second_use.status().as_u16(), 401);
+    assert_eq!(problem_code(second_use).await, "invalid_otp");
+    assert_eq!(other_code.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn enrolling_again_is_refused_until_two_factor_is_disabled() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+    let (secret, _) = app.enable_two_factor().await;
+    let client = reqwest::Client::new();
+    let code = secret.code_at(app.clock.now());
+    let next_code = secret.code_at(app.clock.now() + Duration::seconds(30));
+
+    // act
+    let enrollment = client
+        .post(format!("{}/admin/2fa/enrollment", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("X-OTP", &code)
+        .send()
+        .await
+        .unwrap();
+    app.clock.advance(Duration::seconds(30));
+    let disabled = client
+        .delete(format!("{}/admin/2fa", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("X-OTP", &next_code)
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(enrollment.status().as_u16(), 409);
+    assert_eq!(problem_code(enrollment).await, "two_factor_enabled");
+    assert_eq!(disabled.status().as_u16(), 204);
+    assert_eq!(log_in(&app, None).await.status().as_u16(), 200);
+    assert_eq!(
+        app.post_two_factor_enrollment().await.status().as_u16(),
+        200
+    );
+}
+
+#[tokio::test]
+async fn a_session_allows_several_requests_within_a_time_step() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+    let (secret, _) = app.enable_two_factor().await;
+    let code = secret.code_at(app.clock.now());
+
+    // act
+    let started = start_session(&app, ("X-OTP", &code)).await;
+    assert_eq!(started.status().as_u16(), 201);
+    let started: serde_json::Value = started.json().await.unwrap();
+    let session = started["session"].as_str().unwrap();
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        statuses.push(log_in_with_session(&app, session).await.status().as_u16());
+    }
+
+    // assert
+    assert_eq!(statuses, vec![200, 200, 200]);
+    assert!(started["expires_at"].is_string());
+}
+
+#[tokio::test]
+async fn sessions_expire_and_cannot_start_other_sessions() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+    let (secret, _) = app.enable_two_factor().await;
+    let started: serde_json::Value =
+        start_session(&app, ("X-OTP", &secret.code_at(app.clock.now())))
+            .await
+            .json()
+            .await
+            .unwrap();
+    let session = started["session"].as_str().unwrap();
+
+    // act
+    let extended = start_session(&app, ("X-2FA-Session", session)).await;
+    let forged = log_in_with_session(&app, "not-a-session").await;
+    app.clock.advance(Duration::minutes(15));
+    let expired = log_in_with_session(&app, session).await;
+
+    // assert
+    assert_eq!(extended.status().as_u16(), 403);
+    assert_eq!(problem_code(extended).await, "otp_required");
+    assert_eq!(forged.status().as_u16(), 401);
+    assert_eq!(problem_code(forged).await, "invalid_session");
+    assert_eq!(expired.status().as_u16(), 401);
+    assert_eq!(problem_code(expired).await, "invalid_session");
+}
+
+#[tokio::test]
+async fn disabling_two_factor_ends_its_sessions() {
+    // arrange
+    let app = spawn_app_with(no_login_delay).await;
+    let (secret, _) = app.enable_two_factor().await;
+    let started: serde_json::Value =
+        start_session(&app, ("X-OTP", &secret.code_at(app.clock.now())))
+            .await
+            .json()
+            .await
+            .unwrap();
+    let session = started["session"].as_str().unwrap();
+
+    // act
+    let disabled = reqwest::Client::new()
+        .delete(format!("{}/admin/2fa", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("X-2FA-Session", session)
+        .send()
+        .await
+        .unwrap();
+    let (secret, _) = app.enable_two_factor().await;
+    let after_enabling_again = log_in_with_session(&app, session).await;
+
+    // assert
+    assert_eq!(disabled.status().as_u16(), 204);
+    assert_eq!(after_enabling_again.status().as_u16(), 401);
+    let code = secret.code_at(app.clock.now());
+    assert_eq!(log_in(&app, Some(&code)).await.status().as_u16(), 200);
+}